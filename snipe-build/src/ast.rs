@@ -0,0 +1,125 @@
+//! The definitions in a parsed MIB module, as far as they matter for code generation.
+
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    /// The imported symbols, grouped by the module they are imported from.
+    pub imports: Vec<Import>,
+    pub definitions: Vec<Definition>,
+}
+
+impl Module {
+    pub fn definition(&self, name: &str) -> Option<&Definition> {
+        self.definitions.iter().find(|x| x.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Import {
+    pub module: String,
+    pub symbols: Vec<String>,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub line: usize,
+    pub kind: DefinitionKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DefinitionKind {
+    /// A value with an OID, such as an OBJECT IDENTIFIER assignment, MODULE-IDENTITY or OBJECT-GROUP.
+    Node(Vec<OidComponent>),
+    ObjectType(ObjectType),
+    /// A NOTIFICATION-TYPE or TRAP-TYPE.
+    Notification(Notification),
+    /// A type assignment or TEXTUAL-CONVENTION.
+    Type(Syntax),
+    /// A MACRO definition, such as OBJECT-TYPE in SNMPv2-SMI.
+    Macro,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectType {
+    pub syntax: Syntax,
+    pub access: Access,
+    pub index: Vec<IndexPart>,
+    /// The entry whose index this entry shares, from an AUGMENTS clause.
+    pub augments: Option<String>,
+    pub oid: Vec<OidComponent>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    pub objects: Vec<String>,
+    /// The OID of a NOTIFICATION-TYPE, or the ENTERPRISE and trap number of a TRAP-TYPE.
+    pub oid: Vec<OidComponent>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexPart {
+    pub name: String,
+    /// Whether the index was marked IMPLIED, so its length is not encoded in the instance OID.
+    pub implied: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    NotAccessible,
+    AccessibleForNotify,
+    ReadOnly,
+    ReadWrite,
+    ReadCreate,
+    WriteOnly,
+}
+
+impl Access {
+    pub fn is_readable(self) -> bool {
+        matches!(self, Self::ReadOnly | Self::ReadWrite | Self::ReadCreate)
+    }
+
+    pub fn is_writable(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadCreate | Self::WriteOnly)
+    }
+
+    /// Returns the access as it is written in a MIB.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NotAccessible => "not-accessible",
+            Self::AccessibleForNotify => "accessible-for-notify",
+            Self::ReadOnly => "read-only",
+            Self::ReadWrite => "read-write",
+            Self::ReadCreate => "read-create",
+            Self::WriteOnly => "write-only",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// A reference to a base type, textual convention or other type assignment.
+    Named(String),
+    /// An INTEGER, with its named numbers if it is an enumeration.
+    Integer(Vec<(String, i64)>),
+    OctetString(Option<usize>),
+    ObjectIdentifier,
+    /// BITS, with its named bits.
+    Bits(Vec<(String, i64)>),
+    Sequence(Vec<(String, Syntax)>),
+    SequenceOf(String),
+    /// A CHOICE, which only appears in the definitions of the SMI itself.
+    Choice,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OidComponent {
+    Name(String),
+    Number(u32),
+    /// A `name(number)` component, which defines `name` as a side effect.
+    NameAndNumber(String, u32),
+}
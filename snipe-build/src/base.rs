@@ -0,0 +1,39 @@
+//! The built-in SMI modules, which define the root of the OID tree, the base types and the textual conventions from
+//! RFC 2579.
+
+use std::path::Path;
+
+use crate::{ast::Module, parser::parse};
+
+const MODULES: &[(&str, &str)] = &[
+    ("SNMPv2-SMI", include_str!("../mibs/SNMPv2-SMI.mib")),
+    ("SNMPv2-TC", include_str!("../mibs/SNMPv2-TC.mib")),
+    ("SNMPv2-CONF", include_str!("../mibs/SNMPv2-CONF.mib")),
+    ("RFC1155-SMI", include_str!("../mibs/RFC1155-SMI.mib")),
+    ("RFC-1212", include_str!("../mibs/RFC-1212.mib")),
+    ("RFC-1215", include_str!("../mibs/RFC-1215.mib")),
+];
+
+/// Parses the built-in modules.
+pub fn modules() -> Vec<Module> {
+    MODULES
+        .iter()
+        .flat_map(|(name, source)| {
+            parse(Path::new(name), source).expect("the built-in MIB modules are valid")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modules_parse() {
+        let names = modules().into_iter().map(|x| x.name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            MODULES.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+        );
+    }
+}
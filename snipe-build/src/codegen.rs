@@ -0,0 +1,1107 @@
+//! Generates the struct and `declare_oid!` invocations for a resolved module, along with the implementations of the
+//! traits they declare.
+
+use std::collections::{HashMap, HashSet};
+
+use convert_case::{Case, Casing};
+
+use crate::{
+    ast::*,
+    resolve::{BaseType, Resolver},
+    Error,
+};
+
+const IMPL_GENERICS: &str = "impl<'a, I: ::snipe::SnmpInterface + Send + Sync>";
+
+/// Generates the code for the given module. If `shared_types` is set, textual conventions from other modules are
+/// referred to through `super`, so each module is expected to be placed in a sibling Rust module named after it in
+/// snake case. Otherwise, they are replaced by their underlying types.
+pub fn generate(resolver: &Resolver, module: &Module, shared_types: bool) -> Result<String, Error> {
+    let mib_name = module.name.to_case(Case::Pascal);
+    let mut ret = format!(
+        "::snipe::__private_api_declare_mib_struct!({:?});\n",
+        module.name
+    );
+
+    for definition in &module.definitions {
+        let DefinitionKind::Type(syntax) = &definition.kind else {
+            continue;
+        };
+        if is_structured(syntax) {
+            continue;
+        }
+        let doc = format!("The {} textual convention.", definition.name);
+        if let Some(x) = enumeration(&doc, &type_name(&definition.name), syntax) {
+            ret.push_str(&x);
+            continue;
+        }
+        ret.push_str(&format!(
+            "\n::snipe::snmp_newtype! {{\n    /// {doc}\n    pub struct {}({});\n}}\n",
+            type_name(&definition.name),
+            rust_type(resolver, module, definition.line, syntax, shared_types)?
+        ));
+    }
+
+    let mut value_types = HashMap::new();
+    for definition in &module.definitions {
+        let DefinitionKind::ObjectType(object) = &definition.kind else {
+            continue;
+        };
+        if object.access == Access::NotAccessible {
+            continue;
+        }
+
+        let enum_name = definition.name.to_case(Case::Pascal);
+        let doc = format!("The values of {}.", definition.name);
+        let value_type = match enumeration(&doc, &enum_name, &object.syntax) {
+            Some(x) => {
+                ret.push_str(&x);
+                enum_name.clone()
+            }
+            None => rust_type(
+                resolver,
+                module,
+                definition.line,
+                &object.syntax,
+                shared_types,
+            )?,
+        };
+        value_types.insert(definition.name.as_str(), value_type.clone());
+        // accessible-for-notify objects only appear in notifications
+        if object.access == Access::AccessibleForNotify {
+            continue;
+        }
+        let mut oid = resolver.oid(module, definition)?;
+        let index = match table_index(resolver, module, object)? {
+            None => {
+                // scalars are read and written through their single instance
+                oid.push(0);
+                String::new()
+            }
+            Some(index) => format!(", index = {}", index_tuple(index)),
+        };
+        let oid = oid.iter().map(u32::to_string).collect::<Vec<_>>().join(".");
+        ret.push_str(&format!(
+            "\n::snipe::declare_oid!({mib_name}, {:?}, {value_type}, oid = \"{oid}\", access = {}{index});\n",
+            definition.name,
+            object.access.name()
+        ));
+    }
+
+    for definition in &module.definitions {
+        if let DefinitionKind::ObjectType(ObjectType {
+            syntax: Syntax::SequenceOf(_),
+            ..
+        }) = &definition.kind
+        {
+            ret.push_str(&table(
+                resolver,
+                module,
+                &mib_name,
+                definition,
+                &value_types,
+                shared_types,
+            )?);
+        }
+    }
+
+    for definition in &module.definitions {
+        if let DefinitionKind::Notification(notification) = &definition.kind {
+            ret.push_str(&notification_type(
+                resolver,
+                module,
+                definition,
+                notification,
+                &value_types,
+            )?);
+        }
+    }
+    Ok(ret)
+}
+
+/// Generates a type holding the objects of a notification, which can be read from a received notification.
+fn notification_type(
+    resolver: &Resolver,
+    module: &Module,
+    definition: &Definition,
+    notification: &Notification,
+    value_types: &HashMap<&str, String>,
+) -> Result<String, Error> {
+    let mut fields = String::new();
+    let mut objects = vec![];
+    for name in &notification.objects {
+        let Some((
+            object_module,
+            object_definition @ Definition {
+                kind: DefinitionKind::ObjectType(object),
+                ..
+            },
+        )) = resolver.lookup(module, name)
+        else {
+            return Err(Error::UndefinedSymbol(
+                module.path.clone(),
+                definition.line,
+                name.clone(),
+            ));
+        };
+        // objects from other modules use their underlying types, as their own types may not have been generated
+        let value_type = match value_types.get(name.as_str()) {
+            Some(x) if object_module.name == module.name => x.clone(),
+            _ => value_type(&resolver.base_type(
+                object_module,
+                object_definition.line,
+                &object.syntax,
+            )?),
+        };
+        let oid = resolver
+            .oid(object_module, object_definition)?
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let field = name.to_case(Case::Snake);
+        let value = format!("<::snipe::prelude::DefaultConverter as ::snipe::prelude::SnmpConverter<{value_type}>>::try_from_snmp(value)?");
+        match table_index(resolver, object_module, object)? {
+            None => {
+                fields.push_str(&format!("    pub {field}: Option<{value_type}>,\n"));
+                objects.push(format!(
+                    "if let Some([0]) = oid.strip_prefix(&[{oid}][..]) {{\n            self.{field} = Some({value});\n        }}"
+                ));
+            }
+            Some(index) => {
+                let index = index_tuple(index);
+                fields.push_str(&format!(
+                    "    pub {field}: Option<({index}, {value_type})>,\n"
+                ));
+                objects.push(format!(
+                    "if let Some(instance) = oid.strip_prefix(&[{oid}][..]) {{\n            let index = <::snipe::prelude::DefaultConverter as ::snipe::prelude::OidConverter<{index}>>::try_from_oid(instance)?.converted;\n            self.{field} = Some((index, {value}));\n        }}"
+                ));
+            }
+        }
+    }
+    let (parameters, body) = if objects.is_empty() {
+        (
+            "_: &[u32], _: ::snipe::smi::v2::ObjectSyntax",
+            String::new(),
+        )
+    } else {
+        (
+            "oid: &[u32], value: ::snipe::smi::v2::ObjectSyntax",
+            format!("        {}\n", objects.join(" else ")),
+        )
+    };
+    let oid = resolver
+        .oid(module, definition)?
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(format!(
+        r#"
+/// The objects of the {name} notification. Objects the agent didn't send are `None`.
+#[derive(Clone, Debug, Default)]
+pub struct {type_name} {{
+{fields}}}
+
+impl ::snipe::notification::NotificationType for {type_name} {{
+    const OID: ::snipe::asn::types::ConstOid = ::snipe::asn::types::ConstOid(&[{oid}]);
+
+    fn set_object(&mut self, {parameters}) -> Result<(), ::snipe::Error> {{
+{body}        Ok(())
+    }}
+}}
+"#,
+        name = definition.name,
+        type_name = definition.name.to_case(Case::Pascal),
+    ))
+}
+
+/// Generates the row type and index type for a conceptual table, and a method on the MIB's struct which fetches every
+/// row. If the table's rows extend those of another table, a merged row type is generated along with a method which
+/// fetches both tables and joins them.
+fn table(
+    resolver: &Resolver,
+    module: &Module,
+    mib_name: &str,
+    table: &Definition,
+    value_types: &HashMap<&str, String>,
+    shared_types: bool,
+) -> Result<String, Error> {
+    let is_child_of = |parent: &Definition, child: &Definition| match &child.kind {
+        DefinitionKind::ObjectType(object) => {
+            object.oid.first() == Some(&OidComponent::Name(parent.name.clone()))
+        }
+        _ => false,
+    };
+    let Some(entry) = module.definitions.iter().find(|x| is_child_of(table, x)) else {
+        return Ok(String::new());
+    };
+    let Some(index) = entry_index(resolver, module, entry)? else {
+        return Ok(String::new());
+    };
+
+    let row_name = entry.name.to_case(Case::Pascal);
+    let mut fields = String::new();
+    let mut columns = String::new();
+    let mut status_column = None;
+    let mut created_columns = String::new();
+    for column in module.definitions.iter().filter(|x| is_child_of(entry, x)) {
+        let DefinitionKind::ObjectType(object) = &column.kind else {
+            continue;
+        };
+        if !object.access.is_readable() && !object.access.is_writable() {
+            continue;
+        }
+        let Some(value_type) = value_types.get(column.name.as_str()) else {
+            continue;
+        };
+        let field = column.name.to_case(Case::Snake);
+        let number = resolver
+            .oid(module, column)?
+            .last()
+            .copied()
+            .unwrap_or_default();
+        fields.push_str(&format!("    pub {field}: Option<{value_type}>,\n"));
+        columns.push_str(&format!(
+            "            {number} => self.{field} = Some(<::snipe::prelude::DefaultConverter as ::snipe::prelude::SnmpConverter<{value_type}>>::try_from_snmp(value)?),\n"
+        ));
+        if object.access == Access::ReadCreate && is_row_status(resolver, module, &object.syntax) {
+            status_column = Some(number);
+        } else if object.access.is_writable() {
+            created_columns.push_str(&format!(
+                "        if let Some(x) = row.{field} {{\n            columns.push(({number}, <::snipe::prelude::DefaultConverter as ::snipe::prelude::SnmpConverter<{value_type}>>::try_to_snmp(x)?));\n        }}\n"
+            ));
+        }
+    }
+    let entry_oid = resolver
+        .oid(module, entry)?
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut ret = format!(
+        r#"
+/// The index of {table_name}.
+pub type {row_name}Index = {index};
+
+/// A row of {table_name}. Columns the agent didn't return are `None`.
+#[derive(Clone, Debug, Default)]
+pub struct {row_name} {{
+{fields}}}
+
+impl ::snipe::TableRow for {row_name} {{
+    fn set_column(&mut self, column: u32, value: ::snipe::smi::v2::ObjectSyntax) -> Result<(), ::snipe::Error> {{
+        match column {{
+{columns}            _ => {{}}
+        }}
+        Ok(())
+    }}
+}}
+
+{IMPL_GENERICS} {mib_name}<'a, I> {{
+    /// Fetches every row of {table_name}.
+    pub async fn {method}(&mut self) -> Result<Vec<({row_name}Index, {row_name})>, ::snipe::Error> {{
+        ::snipe::fetch_table::<_, _, ::snipe::prelude::DefaultConverter>(
+            ::snipe::GetSnmpInterface::snmp_interface(self),
+            &[{entry_oid}],
+        )
+        .await
+    }}
+}}
+"#,
+        table_name = table.name,
+        index = index_tuple(index),
+        method = table.name.to_case(Case::Snake),
+    );
+
+    if let Some(status_column) = status_column {
+        let (row, columns) = if created_columns.is_empty() {
+            ("_row", "let columns = vec![];\n".to_string())
+        } else {
+            (
+                "row",
+                format!("let mut columns = vec![];\n{created_columns}"),
+            )
+        };
+        ret.push_str(&format!(
+            r#"
+{IMPL_GENERICS} {mib_name}<'a, I> {{
+    /// Creates a row of {table_name} with the given columns, which is then made active.
+    pub async fn create_{entry_method}(&mut self, index: {row_name}Index, {row}: {row_name}) -> Result<(), ::snipe::Error> {{
+        {columns}        ::snipe::create_row::<_, ::snipe::prelude::DefaultConverter>(
+            ::snipe::GetSnmpInterface::snmp_interface(self),
+            &[{entry_oid}],
+            {status_column},
+            index,
+            columns,
+        )
+        .await
+    }}
+
+    /// Destroys a row of {table_name}.
+    pub async fn destroy_{entry_method}(&mut self, index: {row_name}Index) -> Result<(), ::snipe::Error> {{
+        ::snipe::destroy_row::<_, ::snipe::prelude::DefaultConverter>(
+            ::snipe::GetSnmpInterface::snmp_interface(self),
+            &[{entry_oid}],
+            {status_column},
+            index,
+        )
+        .await
+    }}
+}}
+"#,
+            table_name = table.name,
+            entry_method = entry.name.to_case(Case::Snake),
+        ));
+    }
+
+    let Some((joined_module, joined)) = joined_entry(resolver, module, entry)? else {
+        return Ok(ret);
+    };
+    let joined_name = joined.name.to_case(Case::Pascal);
+    let joined_row = if joined_module.name == module.name {
+        joined_name
+    } else if shared_types && !resolver.is_base(joined_module) {
+        format!(
+            "super::{}::{joined_name}",
+            joined_module.name.to_case(Case::Snake)
+        )
+    } else {
+        // the other table's row type is only generated alongside its module
+        return Ok(ret);
+    };
+    let relation = match &entry.kind {
+        DefinitionKind::ObjectType(ObjectType {
+            augments: Some(_), ..
+        }) => "augments",
+        _ => "shares the index of",
+    };
+    let joined_oid = resolver
+        .oid(joined_module, joined)?
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    ret.push_str(&format!(
+        r#"
+/// A row of {joined_table} joined with the row of {table_name}, which {relation} it. The row of {table_name} is `None`
+/// if the agent didn't return one.
+#[derive(Clone, Debug, Default)]
+pub struct {row_name}Joined {{
+    pub {joined_field}: {joined_row},
+    pub {field}: Option<{row_name}>,
+}}
+
+{IMPL_GENERICS} {mib_name}<'a, I> {{
+    /// Fetches every row of {joined_table}, joined with the row of {table_name} which {relation} it.
+    pub async fn {method}_joined(&mut self) -> Result<Vec<({row_name}Index, {row_name}Joined)>, ::snipe::Error> {{
+        let rows = ::snipe::fetch_joined_table::<_, _, _, ::snipe::prelude::DefaultConverter>(
+            ::snipe::GetSnmpInterface::snmp_interface(self),
+            &[{joined_oid}],
+            &[{entry_oid}],
+        )
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(index, {joined_field}, {field})| (index, {row_name}Joined {{ {joined_field}, {field} }}))
+            .collect())
+    }}
+}}
+"#,
+        joined_table = parent(joined).unwrap_or(&joined.name),
+        table_name = table.name,
+        joined_field = joined.name.to_case(Case::Snake),
+        field = entry.name.to_case(Case::Snake),
+        method = table.name.to_case(Case::Snake),
+    ));
+    Ok(ret)
+}
+
+/// Returns the Rust type of an index, which is a tuple if there is more than one index object.
+fn index_tuple(index: Vec<String>) -> String {
+    match index.as_slice() {
+        [x] => x.clone(),
+        x => format!("({})", x.join(", ")),
+    }
+}
+
+/// Returns the index types of the table an object is a column of, or `None` if the object is a scalar.
+fn table_index(
+    resolver: &Resolver,
+    module: &Module,
+    object: &ObjectType,
+) -> Result<Option<Vec<String>>, Error> {
+    let Some(OidComponent::Name(parent)) = object.oid.first() else {
+        return Ok(None);
+    };
+    let Some((module, entry)) = resolver.lookup(module, parent) else {
+        return Ok(None);
+    };
+    entry_index(resolver, module, entry)
+}
+
+fn entry_index(
+    resolver: &Resolver,
+    module: &Module,
+    entry: &Definition,
+) -> Result<Option<Vec<String>>, Error> {
+    let Some(index) = index_objects(resolver, module, entry)? else {
+        return Ok(None);
+    };
+    let mut ret = vec![];
+    for object in index {
+        let DefinitionKind::ObjectType(index) = &object.definition.kind else {
+            unreachable!("index objects are object types");
+        };
+        let base_type = resolver.base_type(object.module, object.definition.line, &index.syntax)?;
+        ret.push(index_type(&base_type, object.implied));
+    }
+    Ok(Some(ret))
+}
+
+/// An object in an INDEX clause.
+#[derive(PartialEq, Eq)]
+struct IndexObject<'a> {
+    module: &'a Module,
+    definition: &'a Definition,
+    implied: bool,
+}
+
+/// Returns the objects in an entry's INDEX clause, following AUGMENTS. Returns `None` if the definition isn't an entry.
+fn index_objects<'a>(
+    resolver: &'a Resolver,
+    module: &'a Module,
+    entry: &'a Definition,
+) -> Result<Option<Vec<IndexObject<'a>>>, Error> {
+    let DefinitionKind::ObjectType(object) = &entry.kind else {
+        return Ok(None);
+    };
+    if let Some(augments) = &object.augments {
+        let Some((module, augmented)) = resolver.lookup(module, augments) else {
+            return Err(Error::UndefinedSymbol(
+                module.path.clone(),
+                entry.line,
+                augments.clone(),
+            ));
+        };
+        return index_objects(resolver, module, augmented);
+    }
+    if object.index.is_empty() {
+        return Ok(None);
+    }
+
+    let mut ret = vec![];
+    for part in &object.index {
+        match resolver.lookup(module, &part.name) {
+            Some((
+                index_module,
+                definition @ Definition {
+                    kind: DefinitionKind::ObjectType(_),
+                    ..
+                },
+            )) => ret.push(IndexObject {
+                module: index_module,
+                definition,
+                implied: part.implied,
+            }),
+            _ => {
+                return Err(Error::UndefinedSymbol(
+                    module.path.clone(),
+                    entry.line,
+                    part.name.clone(),
+                ))
+            }
+        }
+    }
+    Ok(Some(ret))
+}
+
+/// Returns the entry whose rows an entry's rows extend, which is either the entry it AUGMENTS, or another table's
+/// entry with the same INDEX clause whose columns it is indexed by.
+fn joined_entry<'a>(
+    resolver: &'a Resolver,
+    module: &'a Module,
+    entry: &'a Definition,
+) -> Result<Option<(&'a Module, &'a Definition)>, Error> {
+    let DefinitionKind::ObjectType(object) = &entry.kind else {
+        return Ok(None);
+    };
+    if let Some(augments) = &object.augments {
+        return Ok(resolver.lookup(module, augments));
+    }
+    let Some(index) = index_objects(resolver, module, entry)? else {
+        return Ok(None);
+    };
+    let Some(parent) = parent(index[0].definition) else {
+        return Ok(None);
+    };
+    let Some((parent_module, parent)) = resolver.lookup(index[0].module, parent) else {
+        return Ok(None);
+    };
+    if parent_module.name == module.name && parent.name == entry.name {
+        return Ok(None);
+    }
+    Ok(index_objects(resolver, parent_module, parent)?
+        .filter(|x| *x == index)
+        .map(|_| (parent_module, parent)))
+}
+
+/// Returns the name of the definition an object type's OID is relative to, such as the entry a column belongs to.
+fn parent(definition: &Definition) -> Option<&str> {
+    match &definition.kind {
+        DefinitionKind::ObjectType(ObjectType { oid, .. }) => match oid.first() {
+            Some(OidComponent::Name(x)) => Some(x),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the Rust type used for values of the given syntax, as seen from within the given module. Textual conventions
+/// from modules which aren't built in get a type of their own, which every importer shares if `shared_types` is set.
+fn rust_type(
+    resolver: &Resolver,
+    module: &Module,
+    line: usize,
+    syntax: &Syntax,
+    shared_types: bool,
+) -> Result<String, Error> {
+    if let Syntax::Named(name) = syntax {
+        if let Some((
+            source,
+            Definition {
+                kind: DefinitionKind::Type(inner),
+                ..
+            },
+        )) = resolver.lookup(module, name)
+        {
+            if source.name == module.name && !is_structured(inner) {
+                return Ok(type_name(name));
+            }
+            if resolver.is_base(source) && is_enumeration(inner) {
+                return Ok(format!("::snipe::rfc2579::{}", type_name(name)));
+            }
+            if shared_types && !resolver.is_base(source) && !is_structured(inner) {
+                return Ok(format!(
+                    "super::{}::{}",
+                    source.name.to_case(Case::Snake),
+                    type_name(name)
+                ));
+            }
+        }
+    }
+    Ok(value_type(&resolver.base_type(module, line, syntax)?))
+}
+
+/// Whether a syntax refers to the RowStatus textual convention, which controls the creation and deletion of rows.
+fn is_row_status(resolver: &Resolver, module: &Module, syntax: &Syntax) -> bool {
+    let Syntax::Named(name) = syntax else {
+        return false;
+    };
+    resolver
+        .lookup(module, name)
+        .is_some_and(|(source, definition)| {
+            source.name == "SNMPv2-TC" && definition.name == "RowStatus"
+        })
+}
+
+/// Whether a syntax is an INTEGER with named numbers.
+fn is_enumeration(syntax: &Syntax) -> bool {
+    matches!(syntax, Syntax::Integer(x) if !x.is_empty())
+}
+
+/// Generates an enum for an enumerated INTEGER syntax, or returns `None` if the syntax isn't one.
+fn enumeration(doc: &str, name: &str, syntax: &Syntax) -> Option<String> {
+    let Syntax::Integer(values) = syntax else {
+        return None;
+    };
+    let mut names = HashSet::new();
+    let variants = values
+        .iter()
+        .filter_map(|(name, value)| {
+            let mut variant = name.to_case(Case::Pascal);
+            // values named "unknown" are covered by the Unknown variant every enum has
+            if variant == "Unknown" {
+                return None;
+            }
+            // Self is the only keyword starting with a capital letter
+            if variant == "Self" {
+                variant.push('_');
+            }
+            // names which only differ in case from an earlier one are told apart by their value
+            while !names.insert(variant.clone()) {
+                variant = format!("{variant}{value}");
+            }
+            Some(format!("        {variant} = {value},\n"))
+        })
+        .collect::<String>();
+    if variants.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "\n::snipe::snmp_enum! {{\n    /// {doc}\n    pub enum {name} {{\n{variants}    }}\n}}\n"
+    ))
+}
+
+/// Whether a type assignment is for a SEQUENCE or CHOICE, which don't get a Rust type.
+fn is_structured(syntax: &Syntax) -> bool {
+    matches!(
+        syntax,
+        Syntax::Sequence(_) | Syntax::SequenceOf(_) | Syntax::Choice
+    )
+}
+
+/// Returns the Rust name of an ASN.1 type, which may contain hyphens.
+fn type_name(name: &str) -> String {
+    name.replace('-', "")
+}
+
+/// Returns the Rust type used for values of the given base type.
+fn value_type(base_type: &BaseType) -> String {
+    match base_type {
+        BaseType::Integer32 => "i32",
+        BaseType::Gauge32 => "::snipe::smi::v1::Gauge",
+        BaseType::Counter32 => "::snipe::smi::v1::Counter",
+        BaseType::Counter64 => "u64",
+        BaseType::TimeTicks => "::snipe::smi::v1::TimeTicks",
+        BaseType::IpAddress => "::snipe::smi::v1::IpAddress",
+        BaseType::NetworkAddress => "::snipe::smi::v1::NetworkAddress",
+        BaseType::Opaque => "::snipe::smi::v1::Opaque",
+        BaseType::OctetString(_) | BaseType::Bits => "::snipe::asn::types::OctetString",
+        BaseType::ObjectIdentifier => "::snipe::asn::types::ObjectIdentifier",
+    }
+    .into()
+}
+
+/// Returns the Rust type used for an index of the given base type, as described in RFC 2578 section 7.7. IMPLIED only
+/// changes the encoding of variable-length types.
+fn index_type(base_type: &BaseType, implied: bool) -> String {
+    match base_type {
+        BaseType::Integer32 | BaseType::Gauge32 | BaseType::Counter32 | BaseType::TimeTicks => {
+            "u32".into()
+        }
+        BaseType::Counter64 => "u64".into(),
+        BaseType::OctetString(Some(len)) => {
+            format!("::snipe::prelude::FixedLengthOctetString<{len}>")
+        }
+        BaseType::OctetString(None) | BaseType::Bits if implied => {
+            "::snipe::prelude::Implied<::snipe::asn::types::OctetString>".into()
+        }
+        BaseType::ObjectIdentifier if implied => {
+            "::snipe::prelude::Implied<::snipe::asn::types::ObjectIdentifier>".into()
+        }
+        x => value_type(x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{base, parser::parse};
+
+    const SOURCE: &str = r#"
+TEST-MIB DEFINITIONS ::= BEGIN
+
+IMPORTS
+    OBJECT-TYPE, Counter32, mib-2 FROM SNMPv2-SMI
+    DisplayString, MacAddress FROM SNMPv2-TC;
+
+test OBJECT IDENTIFIER ::= { mib-2 9999 }
+
+testName OBJECT-TYPE
+    SYNTAX DisplayString
+    MAX-ACCESS read-write
+    STATUS current
+    DESCRIPTION "A scalar."
+    ::= { test 1 }
+
+testTable OBJECT-TYPE
+    SYNTAX SEQUENCE OF TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    DESCRIPTION "A table."
+    ::= { test 2 }
+
+testEntry OBJECT-TYPE
+    SYNTAX TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    DESCRIPTION "A row."
+    INDEX { testIndex, testAddress }
+    ::= { testTable 1 }
+
+TestEntry ::= SEQUENCE {
+    testIndex Integer32,
+    testAddress MacAddress,
+    testPackets Counter32
+}
+
+testIndex OBJECT-TYPE
+    SYNTAX Integer32 (1..2147483647)
+    MAX-ACCESS not-accessible
+    STATUS current
+    DESCRIPTION "The index."
+    ::= { testEntry 1 }
+
+testAddress OBJECT-TYPE
+    SYNTAX MacAddress
+    MAX-ACCESS not-accessible
+    STATUS current
+    DESCRIPTION "The address."
+    ::= { testEntry 2 }
+
+testPackets OBJECT-TYPE
+    SYNTAX Counter32
+    MAX-ACCESS read-only
+    STATUS current
+    DESCRIPTION "A column."
+    ::= { testEntry 3 }
+
+END
+"#;
+
+    #[test]
+    fn generate_module() {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        modules.extend(parse(Path::new("TEST-MIB"), SOURCE).unwrap());
+        let resolver = Resolver::new(modules, base_modules).unwrap();
+        let code = generate(&resolver, resolver.module("TEST-MIB").unwrap(), true).unwrap();
+        assert_eq!(
+            code,
+            r#"::snipe::__private_api_declare_mib_struct!("TEST-MIB");
+
+::snipe::declare_oid!(TestMib, "testName", ::snipe::asn::types::OctetString, oid = "1.3.6.1.2.1.9999.1.0", access = read-write);
+
+::snipe::declare_oid!(TestMib, "testPackets", ::snipe::smi::v1::Counter, oid = "1.3.6.1.2.1.9999.2.1.3", access = read-only, index = (u32, ::snipe::prelude::FixedLengthOctetString<6>));
+
+/// The index of testTable.
+pub type TestEntryIndex = (u32, ::snipe::prelude::FixedLengthOctetString<6>);
+
+/// A row of testTable. Columns the agent didn't return are `None`.
+#[derive(Clone, Debug, Default)]
+pub struct TestEntry {
+    pub test_packets: Option<::snipe::smi::v1::Counter>,
+}
+
+impl ::snipe::TableRow for TestEntry {
+    fn set_column(&mut self, column: u32, value: ::snipe::smi::v2::ObjectSyntax) -> Result<(), ::snipe::Error> {
+        match column {
+            3 => self.test_packets = Some(<::snipe::prelude::DefaultConverter as ::snipe::prelude::SnmpConverter<::snipe::smi::v1::Counter>>::try_from_snmp(value)?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl<'a, I: ::snipe::SnmpInterface + Send + Sync> TestMib<'a, I> {
+    /// Fetches every row of testTable.
+    pub async fn test_table(&mut self) -> Result<Vec<(TestEntryIndex, TestEntry)>, ::snipe::Error> {
+        ::snipe::fetch_table::<_, _, ::snipe::prelude::DefaultConverter>(
+            ::snipe::GetSnmpInterface::snmp_interface(self),
+            &[1, 3, 6, 1, 2, 1, 9999, 2, 1],
+        )
+        .await
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn enumerations() {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        modules.extend(
+            parse(
+                Path::new("TEST-MIB"),
+                r#"
+TEST-MIB DEFINITIONS ::= BEGIN
+IMPORTS OBJECT-TYPE, mib-2 FROM SNMPv2-SMI TruthValue FROM SNMPv2-TC;
+Mode ::= TEXTUAL-CONVENTION
+    STATUS current
+    SYNTAX INTEGER { unknown(0), fast(1), slow-start(2) }
+testMode OBJECT-TYPE
+    SYNTAX Mode
+    MAX-ACCESS read-only
+    STATUS current
+    ::= { mib-2 9999 1 }
+testStatus OBJECT-TYPE
+    SYNTAX INTEGER { up(1), down(2) }
+    MAX-ACCESS read-only
+    STATUS current
+    ::= { mib-2 9999 2 }
+testEnabled OBJECT-TYPE
+    SYNTAX TruthValue
+    MAX-ACCESS read-only
+    STATUS current
+    ::= { mib-2 9999 3 }
+END
+"#,
+            )
+            .unwrap(),
+        );
+        let resolver = Resolver::new(modules, base_modules).unwrap();
+        let code = generate(&resolver, resolver.module("TEST-MIB").unwrap(), true).unwrap();
+        assert!(code.contains(
+            "::snipe::snmp_enum! {\n    /// The Mode textual convention.\n    pub enum Mode {\n        Fast = 1,\n        SlowStart = 2,\n    }\n}\n"
+        ));
+        assert!(code.contains("::snipe::declare_oid!(TestMib, \"testMode\", Mode, oid = \"1.3.6.1.2.1.9999.1.0\", access = read-only);"));
+        assert!(code.contains(
+            "::snipe::snmp_enum! {\n    /// The values of testStatus.\n    pub enum TestStatus {\n        Up = 1,\n        Down = 2,\n    }\n}\n"
+        ));
+        assert!(
+            code.contains("::snipe::declare_oid!(TestMib, \"testStatus\", TestStatus, oid = \"1.3.6.1.2.1.9999.2.0\", access = read-only);")
+        );
+        assert!(
+            code.contains("::snipe::declare_oid!(TestMib, \"testEnabled\", ::snipe::rfc2579::TruthValue, oid = \"1.3.6.1.2.1.9999.3.0\", access = read-only);")
+        );
+    }
+
+    #[test]
+    fn enumeration_names() {
+        let syntax = Syntax::Integer(vec![
+            ("self".to_string(), 1),
+            ("dot1q".to_string(), 2),
+            ("dot1Q".to_string(), 3),
+            ("dot1-q".to_string(), 4),
+        ]);
+        let code = enumeration("The values of testType.", "TestType", &syntax).unwrap();
+        let variants = code
+            .lines()
+            .filter(|x| x.ends_with(','))
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        // dot1q, dot1Q and dot1-q are all Dot1Q in Pascal case
+        assert_eq!(
+            variants,
+            ["Self_ = 1,", "Dot1Q = 2,", "Dot1Q3 = 3,", "Dot1Q4 = 4,"]
+        );
+    }
+
+    #[test]
+    fn implied_index() {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        modules.extend(
+            parse(
+                Path::new("TEST-MIB"),
+                r#"
+TEST-MIB DEFINITIONS ::= BEGIN
+IMPORTS OBJECT-TYPE, Integer32, mib-2 FROM SNMPv2-SMI;
+SnmpAdminString ::= OCTET STRING (SIZE (0..255))
+testTable OBJECT-TYPE
+    SYNTAX SEQUENCE OF TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { mib-2 9999 1 }
+testEntry OBJECT-TYPE
+    SYNTAX TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    INDEX { testGroup, IMPLIED testName }
+    ::= { testTable 1 }
+testGroup OBJECT-TYPE
+    SYNTAX Integer32 (1..100)
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { testEntry 1 }
+testName OBJECT-TYPE
+    SYNTAX SnmpAdminString
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { testEntry 2 }
+testValue OBJECT-TYPE
+    SYNTAX Integer32
+    MAX-ACCESS read-only
+    STATUS current
+    ::= { testEntry 3 }
+END
+"#,
+            )
+            .unwrap(),
+        );
+        let resolver = Resolver::new(modules, base_modules).unwrap();
+        let code = generate(&resolver, resolver.module("TEST-MIB").unwrap(), true).unwrap();
+        assert!(code.contains(
+            ", access = read-only, index = (u32, ::snipe::prelude::Implied<::snipe::asn::types::OctetString>));\n"
+        ));
+        assert!(code.contains(
+            "pub type TestEntryIndex = (u32, ::snipe::prelude::Implied<::snipe::asn::types::OctetString>);\n"
+        ));
+    }
+
+    #[test]
+    fn joined_tables() {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        modules.extend(
+            parse(
+                Path::new("TEST-MIB"),
+                r#"
+TEST-MIB DEFINITIONS ::= BEGIN
+IMPORTS OBJECT-TYPE, Integer32, Counter32, mib-2 FROM SNMPv2-SMI;
+testTable OBJECT-TYPE
+    SYNTAX SEQUENCE OF TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { mib-2 9999 1 }
+testEntry OBJECT-TYPE
+    SYNTAX TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    INDEX { testIndex }
+    ::= { testTable 1 }
+testIndex OBJECT-TYPE
+    SYNTAX Integer32 (1..100)
+    MAX-ACCESS read-only
+    STATUS current
+    ::= { testEntry 1 }
+END
+TEST-EXT-MIB DEFINITIONS ::= BEGIN
+IMPORTS OBJECT-TYPE, Counter64, mib-2 FROM SNMPv2-SMI testEntry, testIndex FROM TEST-MIB;
+testXTable OBJECT-TYPE
+    SYNTAX SEQUENCE OF TestXEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { mib-2 9999 2 }
+testXEntry OBJECT-TYPE
+    SYNTAX TestXEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    AUGMENTS { testEntry }
+    ::= { testXTable 1 }
+testHCPackets OBJECT-TYPE
+    SYNTAX Counter64
+    MAX-ACCESS read-only
+    STATUS current
+    ::= { testXEntry 1 }
+testExtTable OBJECT-TYPE
+    SYNTAX SEQUENCE OF TestExtEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { mib-2 9999 3 }
+testExtEntry OBJECT-TYPE
+    SYNTAX TestExtEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    INDEX { testIndex }
+    ::= { testExtTable 1 }
+testExtName OBJECT-TYPE
+    SYNTAX OCTET STRING
+    MAX-ACCESS read-only
+    STATUS current
+    ::= { testExtEntry 1 }
+END
+"#,
+            )
+            .unwrap(),
+        );
+        let resolver = Resolver::new(modules, base_modules).unwrap();
+        let code = generate(&resolver, resolver.module("TEST-EXT-MIB").unwrap(), true).unwrap();
+        assert!(code.contains(
+            "::snipe::declare_oid!(TestExtMib, \"testHCPackets\", u64, oid = \"1.3.6.1.2.1.9999.2.1.1\", access = read-only, index = u32);\n"
+        ));
+        assert!(code.contains(
+            "/// A row of testTable joined with the row of testXTable, which augments it. The row of testXTable is `None`\n/// if the agent didn't return one.\n#[derive(Clone, Debug, Default)]\npub struct TestXEntryJoined {\n    pub test_entry: super::test_mib::TestEntry,\n    pub test_x_entry: Option<TestXEntry>,\n}\n"
+        ));
+        assert!(code.contains(
+            "    pub async fn test_x_table_joined(&mut self) -> Result<Vec<(TestXEntryIndex, TestXEntryJoined)>, ::snipe::Error> {\n        let rows = ::snipe::fetch_joined_table::<_, _, _, ::snipe::prelude::DefaultConverter>(\n            ::snipe::GetSnmpInterface::snmp_interface(self),\n            &[1, 3, 6, 1, 2, 1, 9999, 1, 1],\n            &[1, 3, 6, 1, 2, 1, 9999, 2, 1],\n        )\n"
+        ));
+        assert!(code.contains("pub struct TestExtEntryJoined {\n    pub test_entry: super::test_mib::TestEntry,\n    pub test_ext_entry: Option<TestExtEntry>,\n}\n"));
+        assert!(code.contains("which shares the index of it."));
+
+        // the other table's row type isn't available when the module is expanded on its own
+        let code = generate(&resolver, resolver.module("TEST-EXT-MIB").unwrap(), false).unwrap();
+        assert!(!code.contains("Joined"));
+    }
+
+    #[test]
+    fn access() {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        modules.extend(
+            parse(
+                Path::new("TEST-MIB"),
+                r#"
+TEST-MIB DEFINITIONS ::= BEGIN
+IMPORTS OBJECT-TYPE, NOTIFICATION-TYPE, Integer32, mib-2 FROM SNMPv2-SMI RowStatus FROM SNMPv2-TC;
+testReason OBJECT-TYPE
+    SYNTAX Integer32
+    MAX-ACCESS accessible-for-notify
+    STATUS current
+    ::= { mib-2 9999 1 }
+testTable OBJECT-TYPE
+    SYNTAX SEQUENCE OF TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { mib-2 9999 2 }
+testEntry OBJECT-TYPE
+    SYNTAX TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    INDEX { testIndex }
+    ::= { testTable 1 }
+testIndex OBJECT-TYPE
+    SYNTAX Integer32 (1..100)
+    MAX-ACCESS not-accessible
+    STATUS current
+    ::= { testEntry 1 }
+testValue OBJECT-TYPE
+    SYNTAX Integer32
+    MAX-ACCESS read-create
+    STATUS current
+    ::= { testEntry 2 }
+testStatus OBJECT-TYPE
+    SYNTAX RowStatus
+    MAX-ACCESS read-create
+    STATUS current
+    ::= { testEntry 3 }
+testEvent NOTIFICATION-TYPE
+    OBJECTS { testReason, testValue }
+    STATUS current
+    ::= { mib-2 9999 0 1 }
+END
+"#,
+            )
+            .unwrap(),
+        );
+        let resolver = Resolver::new(modules, base_modules).unwrap();
+        let code = generate(&resolver, resolver.module("TEST-MIB").unwrap(), true).unwrap();
+        assert!(!code.contains("\"testReason\""));
+        assert!(!code.contains("\"testIndex\""));
+        assert!(code.contains("::snipe::declare_oid!(TestMib, \"testValue\", i32, oid = \"1.3.6.1.2.1.9999.2.1.2\", access = read-create, index = u32);"));
+        assert!(code.contains(
+            "    /// Creates a row of testTable with the given columns, which is then made active.\n    pub async fn create_test_entry(&mut self, index: TestEntryIndex, row: TestEntry) -> Result<(), ::snipe::Error> {\n        let mut columns = vec![];\n        if let Some(x) = row.test_value {\n            columns.push((2, <::snipe::prelude::DefaultConverter as ::snipe::prelude::SnmpConverter<i32>>::try_to_snmp(x)?));\n        }\n        ::snipe::create_row::<_, ::snipe::prelude::DefaultConverter>(\n            ::snipe::GetSnmpInterface::snmp_interface(self),\n            &[1, 3, 6, 1, 2, 1, 9999, 2, 1],\n            3,\n"
+        ));
+        assert!(code.contains("    pub async fn destroy_test_entry(&mut self, index: TestEntryIndex) -> Result<(), ::snipe::Error> {\n"));
+        assert!(code.contains(
+            r#"
+/// The objects of the testEvent notification. Objects the agent didn't send are `None`.
+#[derive(Clone, Debug, Default)]
+pub struct TestEvent {
+    pub test_reason: Option<i32>,
+    pub test_value: Option<(u32, i32)>,
+}
+
+impl ::snipe::notification::NotificationType for TestEvent {
+    const OID: ::snipe::asn::types::ConstOid = ::snipe::asn::types::ConstOid(&[1, 3, 6, 1, 2, 1, 9999, 0, 1]);
+
+    fn set_object(&mut self, oid: &[u32], value: ::snipe::smi::v2::ObjectSyntax) -> Result<(), ::snipe::Error> {
+        if let Some([0]) = oid.strip_prefix(&[1, 3, 6, 1, 2, 1, 9999, 1][..]) {
+            self.test_reason = Some(<::snipe::prelude::DefaultConverter as ::snipe::prelude::SnmpConverter<i32>>::try_from_snmp(value)?);
+        } else if let Some(instance) = oid.strip_prefix(&[1, 3, 6, 1, 2, 1, 9999, 2, 1, 2][..]) {
+            let index = <::snipe::prelude::DefaultConverter as ::snipe::prelude::OidConverter<u32>>::try_from_oid(instance)?.converted;
+            self.test_value = Some((index, <::snipe::prelude::DefaultConverter as ::snipe::prelude::SnmpConverter<i32>>::try_from_snmp(value)?));
+        }
+        Ok(())
+    }
+}
+"#
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+//! Splits MIB source into tokens, as described in X.208 section 5 with the relaxations common to real-world MIBs.
+
+use std::path::Path;
+
+use crate::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// An identifier or keyword, such as `ifIndex`, `OBJECT-TYPE` or `mib-2`.
+    Ident(String),
+    Number(u64),
+    /// A negative number, which only appears in ranges and enumerations.
+    NegativeNumber(i64),
+    /// A quoted string, with the quotes removed.
+    String(String),
+    /// A binary or hexadecimal string such as `'00'H`, which only appears in DEFVAL clauses.
+    BinaryString(String),
+    /// `::=`
+    Assign,
+    /// `..`
+    Range,
+    Punct(char),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+}
+
+/// Tokenizes the contents of the given MIB file.
+pub fn tokenize(path: &Path, source: &str) -> Result<Vec<Token>, Error> {
+    let mut ret = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        let start = line;
+        let kind = match c {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            // comments run to the end of the line or the next "--"
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                    if c == '-' && chars.peek() == Some(&'-') {
+                        chars.next();
+                        break;
+                    }
+                }
+                continue;
+            }
+            '-' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let number = take_while(&mut chars, |c| c.is_ascii_digit());
+                let number = number.parse::<i64>().map_err(|_| {
+                    Error::Syntax(path.into(), line, format!("invalid number -{number}"))
+                })?;
+                TokenKind::NegativeNumber(-number)
+            }
+            ':' if chars.peek() == Some(&':') => {
+                chars.next();
+                if chars.next() != Some('=') {
+                    return Err(Error::Syntax(path.into(), line, "expected ::=".into()));
+                }
+                TokenKind::Assign
+            }
+            '.' if chars.peek() == Some(&'.') => {
+                chars.next();
+                TokenKind::Range
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            string.push(c);
+                        }
+                        None => {
+                            return Err(Error::Syntax(
+                                path.into(),
+                                start,
+                                "unterminated string".into(),
+                            ))
+                        }
+                    }
+                }
+                TokenKind::String(string)
+            }
+            '\'' => {
+                let string = take_while(&mut chars, |c| c != '\'');
+                chars.next();
+                // the radix, 'H' or 'B'
+                chars.next();
+                TokenKind::BinaryString(string)
+            }
+            c if c.is_ascii_digit() => {
+                let number = format!("{c}{}", take_while(&mut chars, |c| c.is_ascii_digit()));
+                let number = number.parse().map_err(|_| {
+                    Error::Syntax(path.into(), line, format!("invalid number {number}"))
+                })?;
+                TokenKind::Number(number)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut ident = c.to_string();
+                loop {
+                    ident.push_str(&take_while(&mut chars, |c| {
+                        c.is_ascii_alphanumeric() || c == '_'
+                    }));
+                    // hyphens are part of identifiers, but two in a row start a comment
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some('-')
+                        && lookahead.next().is_some_and(|c| c.is_ascii_alphanumeric())
+                    {
+                        chars.next();
+                        ident.push('-');
+                    } else {
+                        break;
+                    }
+                }
+                TokenKind::Ident(ident)
+            }
+            // MACRO definitions use punctuation which doesn't otherwise appear in MIBs
+            c if c.is_ascii_punctuation() => TokenKind::Punct(c),
+            c => {
+                return Err(Error::Syntax(
+                    path.into(),
+                    line,
+                    format!("unexpected character {c:?}"),
+                ))
+            }
+        };
+        ret.push(Token { kind, line: start });
+    }
+    Ok(ret)
+}
+
+fn take_while(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    predicate: impl Fn(char) -> bool,
+) -> String {
+    let mut ret = String::new();
+    while let Some(&c) = chars.peek() {
+        if !predicate(c) {
+            break;
+        }
+        ret.push(c);
+        chars.next();
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(Path::new("TEST-MIB"), source)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.kind)
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            kinds("ifIndex OBJECT-TYPE -- a comment\n SYNTAX Integer32 (-1..2147483647) -- ends -- ::= { ifEntry 1 }"),
+            vec![
+                TokenKind::Ident("ifIndex".into()),
+                TokenKind::Ident("OBJECT-TYPE".into()),
+                TokenKind::Ident("SYNTAX".into()),
+                TokenKind::Ident("Integer32".into()),
+                TokenKind::Punct('('),
+                TokenKind::NegativeNumber(-1),
+                TokenKind::Range,
+                TokenKind::Number(2147483647),
+                TokenKind::Punct(')'),
+                TokenKind::Assign,
+                TokenKind::Punct('{'),
+                TokenKind::Ident("ifEntry".into()),
+                TokenKind::Number(1),
+                TokenKind::Punct('}'),
+            ]
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let tokens = tokenize(Path::new("TEST-MIB"), "a\n\"multi\nline\"\n'0F'H b").unwrap();
+        let lines = tokens.iter().map(|x| x.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 2, 4, 4]);
+        assert_eq!(tokens[2].kind, TokenKind::BinaryString("0F".into()));
+    }
+}
@@ -1,8 +1,374 @@
+//! Compiles SMIv1 and SMIv2 MIB modules into a struct per module and `declare_oid!` invocations for its objects,
+//! along with the implementations of the traits they declare, so that MIB objects can be read and written with the
+//! correct OIDs and types.
+//!
+//! This is used by `declare_mib!`, and can also be called from a build script:
+//!
+//! ```no_run
+//! // in build.rs
+//! snipe_build::MibCompiler::new()
+//!     .with_mib("mibs/IF-MIB.mib")
+//!     .with_search_path("mibs")
+//!     .compile()
+//!     .unwrap();
+//! ```
+//!
+//! The generated code is written to `mibs.rs` in `OUT_DIR`, and can then be included with
+//! `include!(concat!(env!("OUT_DIR"), "/mibs.rs"));`. Each MIB module becomes a Rust module named after it in snake
+//! case, so IF-MIB's objects are found in `if_mib`. Modules imported from the search path are compiled too, and
+//! textual conventions are shared by every module which imports them.
+
+mod ast;
+mod base;
+mod codegen;
+mod lexer;
+mod parser;
+mod resolve;
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use convert_case::{Case, Casing};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{}:{}: {}", .0.display(), .1, .2)]
+    Syntax(PathBuf, usize, String),
+    #[error("{}:{}: {} is not defined", .0.display(), .1, .2)]
+    UndefinedSymbol(PathBuf, usize, String),
+    #[error("{}:{}: the OID of {} depends on itself", .0.display(), .1, .2)]
+    Cycle(PathBuf, usize, String),
+    #[error("{}:{}: {} is a type, not an OID", .0.display(), .1, .2)]
+    NotAnOid(PathBuf, usize, String),
+    #[error("{}:{}: the SYNTAX is not supported", .0.display(), .1)]
+    UnsupportedSyntax(PathBuf, usize),
+    #[error("{}:{}: module {} was not found in the search path", .0.display(), .1, .2)]
+    MissingModule(PathBuf, usize, String),
+    #[error("module {} was not found in the search path", .0)]
+    ModuleNotFound(String),
+    #[error("{} is not defined in {}", .0, .1)]
+    SymbolNotFound(String, String),
+    #[error("{}:{}: {} is not defined in {}", .0.display(), .1, .2, .3)]
+    ImportNotFound(PathBuf, usize, String, String),
+    #[error("failed to read {}: {}", .0.display(), .1)]
+    Read(PathBuf, std::io::Error),
+    #[error("failed to write {}: {}", .0.display(), .1)]
+    Write(PathBuf, std::io::Error),
+    #[error("OUT_DIR is not set (is this being called from a build script?)")]
+    OutDir,
+}
+
+impl Error {
+    /// The MIB file and line the error was found at, if it was found in one.
+    pub fn location(&self) -> Option<(&Path, usize)> {
+        match self {
+            Error::Syntax(path, line, ..)
+            | Error::UndefinedSymbol(path, line, ..)
+            | Error::Cycle(path, line, ..)
+            | Error::NotAnOid(path, line, ..)
+            | Error::UnsupportedSyntax(path, line)
+            | Error::MissingModule(path, line, ..)
+            | Error::ImportNotFound(path, line, ..) => Some((path, *line)),
+            _ => None,
+        }
+    }
+
+    /// The error's message, without the location given by [`Error::location`].
+    pub fn message(&self) -> String {
+        let message = self.to_string();
+        match self.location() {
+            Some((path, line)) => {
+                let prefix = format!("{}:{line}: ", path.display());
+                message.strip_prefix(&prefix).unwrap_or(&message).into()
+            }
+            None => message,
+        }
+    }
+}
+
+/// The file extensions tried when looking for an imported module in the search path.
+const EXTENSIONS: &[&str] = &["", ".mib", ".txt", ".my"];
+
+/// Compiles MIB modules into Rust code.
+#[derive(Default)]
+pub struct MibCompiler {
+    mibs: Vec<PathBuf>,
+    modules: Vec<String>,
+    search_path: Vec<PathBuf>,
+}
+
+impl MibCompiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a MIB file to compile. Every module in the file is compiled.
+    pub fn with_mib(mut self, path: impl Into<PathBuf>) -> Self {
+        self.mibs.push(path.into());
+        self
+    }
+
+    /// Adds a module to compile, which is found in the search path by name.
+    pub fn with_module(mut self, name: impl Into<String>) -> Self {
+        self.modules.push(name.into());
+        self
+    }
+
+    /// Adds a directory to search for imported modules. A module is found in a file named after it, optionally with
+    /// a `.mib`, `.txt` or `.my` extension. The SMI modules themselves (such as SNMPv2-SMI and SNMPv2-TC) are built
+    /// in and never searched for.
+    pub fn with_search_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.search_path.push(path.into());
+        self
+    }
+
+    /// Generates the code for the added MIB files and every module they import. Each module is placed in a Rust
+    /// module named after it in snake case.
+    pub fn generate(&self) -> Result<String, Error> {
+        generate(&self.load()?.0)
+    }
+
+    /// Generates the code for the added MIB files and modules only, to be expanded in place by `declare_mib!`.
+    /// Textual conventions from imported modules are replaced by their underlying types. The files which were read
+    /// are returned alongside the code.
+    pub fn generate_inline(&self) -> Result<(String, Vec<PathBuf>), Error> {
+        let (resolver, names) = self.load()?;
+        let mut code = String::new();
+        for name in names {
+            let module = resolver.module(&name).expect("the module was loaded");
+            code.push_str(&codegen::generate(&resolver, module, false)?);
+        }
+        Ok((code, loaded_files(&resolver)))
+    }
+
+    /// Resolves the OID of a symbol in one of the added MIB files or modules. The files which were read are returned
+    /// alongside the OID.
+    pub fn oid(&self, module: &str, name: &str) -> Result<(Vec<u32>, Vec<PathBuf>), Error> {
+        let (resolver, _) = self.load()?;
+        let not_found = || Error::SymbolNotFound(name.into(), module.into());
+        let module = resolver.module(module).ok_or_else(not_found)?;
+        let (module, definition) = resolver.lookup(module, name).ok_or_else(not_found)?;
+        let oid = resolver.oid(module, definition)?;
+        Ok((oid, loaded_files(&resolver)))
+    }
+
+    /// Generates the code for the added MIB files and every module they import, and writes it to `mibs.rs` in
+    /// `OUT_DIR`. Cargo is told to rerun the build script if any of the loaded files change.
+    pub fn compile(&self) -> Result<(), Error> {
+        let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").ok_or(Error::OutDir)?);
+        let (resolver, _) = self.load()?;
+        let paths = resolver
+            .loaded_modules()
+            .iter()
+            .map(|x| &x.path)
+            .chain(&self.search_path)
+            .collect::<BTreeSet<_>>();
+        for path in paths {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        let path = out_dir.join("mibs.rs");
+        std::fs::write(&path, generate(&resolver)?).map_err(|e| Error::Write(path, e))
+    }
+
+    /// Loads the added MIB files and modules, and then the modules they import from the search path. The names of
+    /// the modules which were added are returned alongside the resolver.
+    fn load(&self) -> Result<(resolve::Resolver, Vec<String>), Error> {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        let mut names = vec![];
+        for path in &self.mibs {
+            names.extend(load_file(path, &mut modules)?);
+        }
+        for name in &self.modules {
+            if !modules.iter().any(|x| &x.name == name) {
+                let path = self
+                    .find(name)
+                    .ok_or_else(|| Error::ModuleNotFound(name.clone()))?;
+                load_file(&path, &mut modules)?;
+            }
+            if !modules.iter().any(|x| &x.name == name) {
+                return Err(Error::ModuleNotFound(name.clone()));
+            }
+            names.push(name.clone());
+        }
+
+        let mut i = base_modules;
+        while i < modules.len() {
+            let imports = modules[i].imports.clone();
+            for import in imports {
+                if modules.iter().any(|x| x.name == import.module) {
+                    continue;
+                }
+                if let Some(path) = self.find(&import.module) {
+                    load_file(&path, &mut modules)?;
+                }
+                if !modules.iter().any(|x| x.name == import.module) {
+                    return Err(Error::MissingModule(
+                        modules[i].path.clone(),
+                        import.line,
+                        import.module,
+                    ));
+                }
+            }
+            i += 1;
+        }
+        Ok((resolve::Resolver::new(modules, base_modules)?, names))
+    }
+
+    fn find(&self, module: &str) -> Option<PathBuf> {
+        self.search_path.iter().find_map(|dir| {
+            EXTENSIONS
+                .iter()
+                .map(|extension| dir.join(format!("{module}{extension}")))
+                .find(|x| x.is_file())
+        })
+    }
+}
+
+/// Returns the files the loaded modules were read from, each once.
+fn loaded_files(resolver: &resolve::Resolver) -> Vec<PathBuf> {
+    resolver
+        .loaded_modules()
+        .iter()
+        .map(|x| x.path.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Parses a MIB file, adding the modules in it which haven't already been loaded. Returns the names of the modules in
+/// the file.
+fn load_file(path: &Path, modules: &mut Vec<ast::Module>) -> Result<Vec<String>, Error> {
+    let source = std::fs::read_to_string(path).map_err(|e| Error::Read(path.into(), e))?;
+    let parsed = parser::parse(path, &source)?;
+    if parsed.is_empty() {
+        return Err(Error::Syntax(
+            path.into(),
+            1,
+            "expected a MIB module".into(),
+        ));
+    }
+    let names = parsed.iter().map(|x| x.name.clone()).collect();
+    for module in parsed {
+        if !modules.iter().any(|x| x.name == module.name) {
+            modules.push(module);
+        }
+    }
+    Ok(names)
+}
+
+fn generate(resolver: &resolve::Resolver) -> Result<String, Error> {
+    let mut ret = "// Generated by snipe-build. Do not edit.\n".to_string();
+    for module in resolver.loaded_modules() {
+        ret.push_str(&format!(
+            "\n/// The {} MIB module.\npub mod {} {{\n",
+            module.name,
+            module.name.to_case(Case::Snake)
+        ));
+        for line in codegen::generate(resolver, module, true)?.lines() {
+            if !line.is_empty() {
+                ret.push_str("    ");
+                ret.push_str(line);
+            }
+            ret.push('\n');
+        }
+        ret.push_str("}\n");
+    }
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_file() {
+        let error = MibCompiler::new()
+            .with_mib("does-not-exist.mib")
+            .generate()
+            .unwrap_err();
+        assert!(matches!(error, Error::Read(..)));
+    }
+
+    #[test]
+    fn search_path() {
+        let dir = std::env::temp_dir().join(format!("snipe-build-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("TEST-TC-MIB.txt"),
+            "TEST-TC-MIB DEFINITIONS ::= BEGIN\nIMPORTS Integer32 FROM SNMPv2-SMI;\nTestIndex ::= TEXTUAL-CONVENTION\n    STATUS current\n    SYNTAX Integer32 (1..100)\nEND\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("test.mib"),
+            "TEST-MIB DEFINITIONS ::= BEGIN\nIMPORTS OBJECT-TYPE, mib-2 FROM SNMPv2-SMI\n    TestIndex FROM TEST-TC-MIB;\ntestIndex OBJECT-TYPE\n    SYNTAX TestIndex\n    MAX-ACCESS read-only\n    STATUS current\n    ::= { mib-2 9999 }\nEND\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("missing.mib"),
+            "MISSING-MIB DEFINITIONS ::= BEGIN\nIMPORTS\n    Missing FROM MISSING-TC-MIB;\nEND\n",
+        )
+        .unwrap();
+
+        let code = MibCompiler::new()
+            .with_mib(dir.join("test.mib"))
+            .with_search_path(&dir)
+            .generate()
+            .unwrap();
+        assert!(code.contains("pub mod test_tc_mib {\n    ::snipe::__private_api_declare_mib_struct!(\"TEST-TC-MIB\");\n\n    ::snipe::snmp_newtype! {\n        /// The TestIndex textual convention.\n        pub struct TestIndex(i32);\n    }\n}"));
+        assert!(
+            code.contains("::snipe::declare_oid!(TestMib, \"testIndex\", super::test_tc_mib::TestIndex, oid = \"1.3.6.1.2.1.9999.0\", access = read-only);")
+        );
+
+        // TEST-TC-MIB is loaded after the module importing it, but files are listed in order
+        let (_, files) = MibCompiler::new()
+            .with_mib(dir.join("test.mib"))
+            .with_search_path(&dir)
+            .generate_inline()
+            .unwrap();
+        assert_eq!(
+            files,
+            vec![dir.join("TEST-TC-MIB.txt"), dir.join("test.mib")]
+        );
+
+        let error = MibCompiler::new()
+            .with_mib(dir.join("missing.mib"))
+            .with_search_path(&dir)
+            .generate()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{}:3: module MISSING-TC-MIB was not found in the search path",
+                dir.join("missing.mib").display()
+            )
+        );
+        assert_eq!(
+            error.location(),
+            Some((dir.join("missing.mib").as_path(), 3))
+        );
+        assert_eq!(
+            error.message(),
+            "module MISSING-TC-MIB was not found in the search path"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn oid() {
+        let (oid, files) = MibCompiler::new()
+            .with_module("SNMPv2-SMI")
+            .oid("SNMPv2-SMI", "enterprises")
+            .unwrap();
+        assert_eq!(oid, vec![1, 3, 6, 1, 4, 1]);
+        assert!(files.is_empty());
+
+        let error = MibCompiler::new()
+            .with_module("SNMPv2-SMI")
+            .oid("SNMPv2-SMI", "sysDescr")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "sysDescr is not defined in SNMPv2-SMI");
     }
 }
@@ -0,0 +1,663 @@
+//! Parses the subset of ASN.1 used by SMIv1 (RFC 1155, RFC 1212 and RFC 1215) and SMIv2 (RFC 2578 and RFC 2579)
+//! MIB modules. Clauses which don't affect code generation, such as DESCRIPTION, are parsed and then discarded.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    ast::*,
+    lexer::{tokenize, Token, TokenKind},
+    Error,
+};
+
+/// Parses all of the modules in the given MIB file.
+pub fn parse(path: &Path, source: &str) -> Result<Vec<Module>, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(path, source)?,
+        pos: 0,
+        path: path.into(),
+    };
+    let mut ret = vec![];
+    while !parser.is_eof() {
+        ret.push(parser.module()?);
+    }
+    Ok(ret)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    path: PathBuf,
+}
+
+impl Parser {
+    fn is_eof(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map(|x| x.line)
+            .unwrap_or(1)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, Error> {
+        Err(Error::Syntax(
+            self.path.clone(),
+            self.line(),
+            message.into(),
+        ))
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|x| &x.kind)
+    }
+
+    fn peek_ident(&self) -> Option<&str> {
+        match self.peek() {
+            Some(TokenKind::Ident(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> Result<TokenKind, Error> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.kind.clone())
+            }
+            None => self.error("unexpected end of file"),
+        }
+    }
+
+    /// Consumes the next token if it is the given punctuation.
+    fn eat_punct(&mut self, c: char) -> bool {
+        let ret = self.peek() == Some(&TokenKind::Punct(c));
+        if ret {
+            self.pos += 1;
+        }
+        ret
+    }
+
+    /// Consumes the next token if it is the given keyword.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let ret = self.peek_ident() == Some(keyword);
+        if ret {
+            self.pos += 1;
+        }
+        ret
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), Error> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            self.error(format!("expected {c:?}"))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            self.error(format!("expected {keyword}"))
+        }
+    }
+
+    fn expect_assign(&mut self) -> Result<(), Error> {
+        match self.next()? {
+            TokenKind::Assign => Ok(()),
+            _ => {
+                self.pos -= 1;
+                self.error("expected ::=")
+            }
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, Error> {
+        match self.next()? {
+            TokenKind::Ident(x) => Ok(x),
+            _ => {
+                self.pos -= 1;
+                self.error("expected an identifier")
+            }
+        }
+    }
+
+    fn expect_number(&mut self) -> Result<i64, Error> {
+        match self.next()? {
+            TokenKind::Number(x) => i64::try_from(x).or_else(|_| self.error("number is too large")),
+            TokenKind::NegativeNumber(x) => Ok(x),
+            _ => {
+                self.pos -= 1;
+                self.error("expected a number")
+            }
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, Error> {
+        match self.next()? {
+            TokenKind::String(x) => Ok(x),
+            _ => {
+                self.pos -= 1;
+                self.error("expected a string")
+            }
+        }
+    }
+
+    /// Skips a balanced group of tokens, starting at the given opening punctuation.
+    fn skip_group(&mut self, open: char, close: char) -> Result<(), Error> {
+        self.expect_punct(open)?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                TokenKind::Punct(c) if c == open => depth += 1,
+                TokenKind::Punct(c) if c == close => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn module(&mut self) -> Result<Module, Error> {
+        let name = self.expect_ident()?;
+        // RFC 1155 allows an OID after the module name
+        if self.peek() == Some(&TokenKind::Punct('{')) {
+            self.skip_group('{', '}')?;
+        }
+        self.expect_keyword("DEFINITIONS")?;
+        self.expect_assign()?;
+        self.expect_keyword("BEGIN")?;
+
+        let mut imports = vec![];
+        if self.eat_keyword("IMPORTS") {
+            let mut symbols = vec![];
+            let mut line = self.line();
+            while !self.eat_punct(';') {
+                let symbol = self.expect_ident()?;
+                if self.eat_keyword("FROM") {
+                    symbols.push(symbol);
+                    imports.push(Import {
+                        module: self.expect_ident()?,
+                        symbols: std::mem::take(&mut symbols),
+                        line,
+                    });
+                    line = self.line();
+                } else {
+                    symbols.push(symbol);
+                    self.eat_punct(',');
+                }
+            }
+        }
+        if self.eat_keyword("EXPORTS") {
+            while !self.eat_punct(';') {
+                self.next()?;
+            }
+        }
+
+        let mut definitions = vec![];
+        while !self.eat_keyword("END") {
+            definitions.push(self.definition()?);
+        }
+
+        Ok(Module {
+            name,
+            path: self.path.clone(),
+            imports,
+            definitions,
+        })
+    }
+
+    fn definition(&mut self) -> Result<Definition, Error> {
+        let line = self.line();
+        let name = self.expect_ident()?;
+        let kind = if name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            if self.eat_keyword("MACRO") {
+                self.expect_assign()?;
+                self.expect_keyword("BEGIN")?;
+                while !self.eat_keyword("END") {
+                    self.next()?;
+                }
+                return Ok(Definition {
+                    name,
+                    line,
+                    kind: DefinitionKind::Macro,
+                });
+            }
+            self.expect_assign()?;
+            if self.eat_keyword("TEXTUAL-CONVENTION") {
+                while !self.eat_keyword("SYNTAX") {
+                    self.next()?;
+                }
+            }
+            DefinitionKind::Type(self.syntax()?)
+        } else {
+            match self.expect_ident()?.as_str() {
+                "OBJECT" => {
+                    self.expect_keyword("IDENTIFIER")?;
+                    self.expect_assign()?;
+                    DefinitionKind::Node(self.oid()?)
+                }
+                "OBJECT-TYPE" => DefinitionKind::ObjectType(self.object_type()?),
+                "NOTIFICATION-TYPE" => DefinitionKind::Notification(self.notification_type()?),
+                "TRAP-TYPE" => DefinitionKind::Notification(self.trap_type()?),
+                // the remaining macros (MODULE-IDENTITY, OBJECT-GROUP and so on) only matter for their OID
+                _ => {
+                    loop {
+                        match self.next()? {
+                            TokenKind::Assign => break,
+                            TokenKind::Punct('{') => {
+                                self.pos -= 1;
+                                self.skip_group('{', '}')?;
+                            }
+                            _ => {}
+                        }
+                    }
+                    DefinitionKind::Node(self.oid()?)
+                }
+            }
+        };
+        Ok(Definition { name, line, kind })
+    }
+
+    fn object_type(&mut self) -> Result<ObjectType, Error> {
+        let mut syntax = None;
+        let mut access = None;
+        let mut index = vec![];
+        let mut augments = None;
+        loop {
+            match self.next()? {
+                TokenKind::Assign => break,
+                TokenKind::Ident(clause) => match clause.as_str() {
+                    "SYNTAX" => syntax = Some(self.syntax()?),
+                    "MAX-ACCESS" | "ACCESS" => access = Some(self.access()?),
+                    "UNITS" | "DESCRIPTION" | "REFERENCE" => {
+                        self.expect_string()?;
+                    }
+                    "STATUS" => {
+                        self.expect_ident()?;
+                    }
+                    "INDEX" => {
+                        self.expect_punct('{')?;
+                        loop {
+                            let implied = self.eat_keyword("IMPLIED");
+                            index.push(IndexPart {
+                                name: self.expect_ident()?,
+                                implied,
+                            });
+                            if !self.eat_punct(',') {
+                                break;
+                            }
+                        }
+                        self.expect_punct('}')?;
+                    }
+                    "AUGMENTS" => {
+                        self.expect_punct('{')?;
+                        augments = Some(self.expect_ident()?);
+                        self.expect_punct('}')?;
+                    }
+                    "DEFVAL" => self.skip_group('{', '}')?,
+                    _ => {
+                        self.pos -= 1;
+                        return self.error(format!("unexpected OBJECT-TYPE clause {clause}"));
+                    }
+                },
+                _ => {
+                    self.pos -= 1;
+                    return self.error("expected an OBJECT-TYPE clause");
+                }
+            }
+        }
+        let Some(syntax) = syntax else {
+            return self.error("OBJECT-TYPE has no SYNTAX clause");
+        };
+        let Some(access) = access else {
+            return self.error("OBJECT-TYPE has no MAX-ACCESS clause");
+        };
+        Ok(ObjectType {
+            syntax,
+            access,
+            index,
+            augments,
+            oid: self.oid()?,
+        })
+    }
+
+    fn access(&mut self) -> Result<Access, Error> {
+        Ok(match self.expect_ident()?.as_str() {
+            "not-accessible" => Access::NotAccessible,
+            "accessible-for-notify" => Access::AccessibleForNotify,
+            "read-only" => Access::ReadOnly,
+            "read-write" => Access::ReadWrite,
+            "read-create" => Access::ReadCreate,
+            "write-only" => Access::WriteOnly,
+            x => {
+                self.pos -= 1;
+                return self.error(format!("unknown access {x}"));
+            }
+        })
+    }
+
+    fn notification_type(&mut self) -> Result<Notification, Error> {
+        let mut objects = vec![];
+        loop {
+            match self.next()? {
+                TokenKind::Assign => break,
+                TokenKind::Ident(clause) if clause == "OBJECTS" => objects = self.name_list()?,
+                _ => {}
+            }
+        }
+        Ok(Notification {
+            objects,
+            oid: self.oid()?,
+        })
+    }
+
+    fn trap_type(&mut self) -> Result<Notification, Error> {
+        let mut enterprise = None;
+        let mut objects = vec![];
+        loop {
+            match self.next()? {
+                TokenKind::Assign => break,
+                TokenKind::Ident(clause) if clause == "ENTERPRISE" => {
+                    enterprise = Some(self.expect_ident()?)
+                }
+                TokenKind::Ident(clause) if clause == "VARIABLES" => objects = self.name_list()?,
+                _ => {}
+            }
+        }
+        let Some(enterprise) = enterprise else {
+            return self.error("TRAP-TYPE has no ENTERPRISE clause");
+        };
+        let number = self.expect_number()?;
+        let number = u32::try_from(number).or_else(|_| self.error("invalid trap number"))?;
+        // RFC 3584 3.1 (3)
+        Ok(Notification {
+            objects,
+            oid: vec![
+                OidComponent::Name(enterprise),
+                OidComponent::Number(0),
+                OidComponent::Number(number),
+            ],
+        })
+    }
+
+    fn name_list(&mut self) -> Result<Vec<String>, Error> {
+        self.expect_punct('{')?;
+        let mut ret = vec![];
+        loop {
+            ret.push(self.expect_ident()?);
+            if !self.eat_punct(',') {
+                break;
+            }
+        }
+        self.expect_punct('}')?;
+        Ok(ret)
+    }
+
+    fn oid(&mut self) -> Result<Vec<OidComponent>, Error> {
+        self.expect_punct('{')?;
+        let mut ret = vec![];
+        while !self.eat_punct('}') {
+            match self.next()? {
+                TokenKind::Ident(name) => {
+                    if self.eat_punct('(') {
+                        let number = self.expect_number()?;
+                        let number = u32::try_from(number)
+                            .or_else(|_| self.error("invalid OID component"))?;
+                        self.expect_punct(')')?;
+                        ret.push(OidComponent::NameAndNumber(name, number));
+                    } else {
+                        ret.push(OidComponent::Name(name));
+                    }
+                }
+                TokenKind::Number(number) => {
+                    let number =
+                        u32::try_from(number).or_else(|_| self.error("invalid OID component"))?;
+                    ret.push(OidComponent::Number(number));
+                }
+                _ => {
+                    self.pos -= 1;
+                    return self.error("expected an OID component");
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn syntax(&mut self) -> Result<Syntax, Error> {
+        // tags such as [APPLICATION 0] IMPLICIT only matter for encoding
+        if self.peek() == Some(&TokenKind::Punct('[')) {
+            self.skip_group('[', ']')?;
+            if !self.eat_keyword("IMPLICIT") {
+                self.eat_keyword("EXPLICIT");
+            }
+        }
+
+        let name = self.expect_ident()?;
+        let ret = match name.as_str() {
+            "INTEGER" => {
+                let values = if self.peek() == Some(&TokenKind::Punct('{')) {
+                    self.named_numbers()?
+                } else {
+                    vec![]
+                };
+                Syntax::Integer(values)
+            }
+            "BITS" => Syntax::Bits(self.named_numbers()?),
+            "OCTET" => {
+                self.expect_keyword("STRING")?;
+                return Ok(Syntax::OctetString(self.constraint()?));
+            }
+            "OBJECT" => {
+                self.expect_keyword("IDENTIFIER")?;
+                Syntax::ObjectIdentifier
+            }
+            "SEQUENCE" => {
+                if self.eat_keyword("OF") {
+                    return Ok(Syntax::SequenceOf(self.expect_ident()?));
+                }
+                self.expect_punct('{')?;
+                let mut fields = vec![];
+                while !self.eat_punct('}') {
+                    let field = self.expect_ident()?;
+                    fields.push((field, self.syntax()?));
+                    self.eat_punct(',');
+                }
+                Syntax::Sequence(fields)
+            }
+            "CHOICE" => {
+                self.skip_group('{', '}')?;
+                Syntax::Choice
+            }
+            _ => {
+                // enumerations may also refine a named type, such as Integer32
+                if self.peek() == Some(&TokenKind::Punct('{')) {
+                    self.skip_group('{', '}')?;
+                }
+                Syntax::Named(name)
+            }
+        };
+        self.constraint()?;
+        Ok(ret)
+    }
+
+    fn named_numbers(&mut self) -> Result<Vec<(String, i64)>, Error> {
+        self.expect_punct('{')?;
+        let mut ret = vec![];
+        loop {
+            let name = self.expect_ident()?;
+            self.expect_punct('(')?;
+            let number = self.expect_number()?;
+            self.expect_punct(')')?;
+            ret.push((name, number));
+            if !self.eat_punct(',') {
+                break;
+            }
+        }
+        self.expect_punct('}')?;
+        Ok(ret)
+    }
+
+    /// Skips a range or size constraint, returning the size if it is fixed to a single value.
+    fn constraint(&mut self) -> Result<Option<usize>, Error> {
+        if self.peek() != Some(&TokenKind::Punct('(')) {
+            return Ok(None);
+        }
+        let start = self.pos;
+        self.skip_group('(', ')')?;
+        let fixed = match &self.tokens[start..self.pos] {
+            [_, size, _, number, _, _] if size.kind == TokenKind::Ident("SIZE".into()) => {
+                match number.kind {
+                    TokenKind::Number(x) => usize::try_from(x).ok(),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        Ok(fixed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+TEST-MIB DEFINITIONS ::= BEGIN
+
+IMPORTS
+    MODULE-IDENTITY, OBJECT-TYPE, Integer32, mib-2 FROM SNMPv2-SMI
+    DisplayString FROM SNMPv2-TC;
+
+testMIB MODULE-IDENTITY
+    LAST-UPDATED "202001010000Z"
+    ORGANIZATION "snipe"
+    CONTACT-INFO "snipe"
+    DESCRIPTION "A test MIB."
+    REVISION "202001010000Z"
+    DESCRIPTION "Initial revision."
+    ::= { mib-2 9999 }
+
+testObjects OBJECT IDENTIFIER ::= { testMIB 1 }
+
+Status ::= TEXTUAL-CONVENTION
+    STATUS current
+    DESCRIPTION "A status."
+    SYNTAX INTEGER { up(1), down(2) }
+
+testTable OBJECT-TYPE
+    SYNTAX SEQUENCE OF TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    DESCRIPTION "A table."
+    ::= { testObjects 1 }
+
+testEntry OBJECT-TYPE
+    SYNTAX TestEntry
+    MAX-ACCESS not-accessible
+    STATUS current
+    DESCRIPTION "A row."
+    INDEX { testIndex, IMPLIED testName }
+    ::= { testTable 1 }
+
+TestEntry ::= SEQUENCE {
+    testIndex Integer32,
+    testName DisplayString,
+    testAddress OCTET STRING
+}
+
+testIndex OBJECT-TYPE
+    SYNTAX Integer32 (1..2147483647)
+    MAX-ACCESS not-accessible
+    STATUS current
+    DESCRIPTION "The index."
+    ::= { testEntry 1 }
+
+testAddress OBJECT-TYPE
+    SYNTAX OCTET STRING (SIZE (6))
+    MAX-ACCESS read-write
+    STATUS current
+    DESCRIPTION "An address."
+    DEFVAL { '000000000000'H }
+    ::= { testEntry 3 }
+
+END
+"#;
+
+    #[test]
+    fn module() {
+        let modules = parse(Path::new("TEST-MIB"), SOURCE).unwrap();
+        assert_eq!(modules.len(), 1);
+        let module = &modules[0];
+        assert_eq!(module.name, "TEST-MIB");
+        assert_eq!(module.imports.len(), 2);
+        assert_eq!(module.imports[1].module, "SNMPv2-TC");
+        assert_eq!(module.imports[1].symbols, vec!["DisplayString".to_string()]);
+
+        let names = module
+            .definitions
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "testMIB",
+                "testObjects",
+                "Status",
+                "testTable",
+                "testEntry",
+                "TestEntry",
+                "testIndex",
+                "testAddress"
+            ]
+        );
+        assert_eq!(
+            module.definitions[0].kind,
+            DefinitionKind::Node(vec![
+                OidComponent::Name("mib-2".into()),
+                OidComponent::Number(9999)
+            ])
+        );
+        assert_eq!(
+            module.definitions[2].kind,
+            DefinitionKind::Type(Syntax::Integer(vec![("up".into(), 1), ("down".into(), 2)]))
+        );
+
+        let DefinitionKind::ObjectType(entry) = &module.definitions[4].kind else {
+            panic!("expected an OBJECT-TYPE");
+        };
+        assert_eq!(
+            entry.index,
+            vec![
+                IndexPart {
+                    name: "testIndex".into(),
+                    implied: false
+                },
+                IndexPart {
+                    name: "testName".into(),
+                    implied: true
+                }
+            ]
+        );
+
+        let DefinitionKind::ObjectType(address) = &module.definitions[7].kind else {
+            panic!("expected an OBJECT-TYPE");
+        };
+        assert_eq!(address.syntax, Syntax::OctetString(Some(6)));
+        assert_eq!(address.access, Access::ReadWrite);
+        assert_eq!(module.definitions[7].line, 52);
+    }
+
+    #[test]
+    fn syntax_error() {
+        let error = parse(
+            Path::new("TEST-MIB"),
+            "TEST-MIB DEFINITIONS ::= BEGIN\ntest OBJECT-TYPE\n  SYNTAX Integer32\n  MAX-ACCESS sideways\n  ::= { test 1 }\nEND",
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "TEST-MIB:4: unknown access sideways");
+    }
+}
@@ -0,0 +1,351 @@
+//! Resolves the symbols in parsed MIB modules to OIDs and SMI base types.
+
+use std::collections::HashSet;
+
+use crate::{ast::*, Error};
+
+/// The SMI base type a syntax ultimately refers to, after following textual conventions and type assignments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BaseType {
+    Integer32,
+    /// Gauge32 or Unsigned32 in SMIv2, or Gauge in SMIv1.
+    Gauge32,
+    Counter32,
+    Counter64,
+    TimeTicks,
+    IpAddress,
+    NetworkAddress,
+    Opaque,
+    /// An OCTET STRING, with its length if it is fixed by a size constraint.
+    OctetString(Option<usize>),
+    ObjectIdentifier,
+    Bits,
+}
+
+impl BaseType {
+    /// Returns the base type for the name of one of the SMI's application types.
+    fn application(name: &str) -> Option<Self> {
+        Some(match name {
+            "Integer32" => Self::Integer32,
+            "Gauge32" | "Unsigned32" | "Gauge" => Self::Gauge32,
+            "Counter32" | "Counter" => Self::Counter32,
+            "Counter64" => Self::Counter64,
+            "TimeTicks" => Self::TimeTicks,
+            "IpAddress" => Self::IpAddress,
+            "NetworkAddress" => Self::NetworkAddress,
+            "Opaque" => Self::Opaque,
+            _ => return None,
+        })
+    }
+}
+
+/// Resolves symbols across a set of parsed modules.
+pub struct Resolver {
+    modules: Vec<Module>,
+    base_modules: usize,
+}
+
+impl Resolver {
+    /// Creates a resolver over the given modules, where the first `base_modules` modules are the built-in SMI
+    /// definitions. Every import must refer to a symbol defined in one of the modules. Modules may import from each
+    /// other in a cycle, as some published MIBs do; only the definitions themselves must not depend on each other.
+    pub fn new(modules: Vec<Module>, base_modules: usize) -> Result<Self, Error> {
+        let ret = Self {
+            modules,
+            base_modules,
+        };
+        for module in &ret.modules {
+            for import in &module.imports {
+                let Some(source) = ret.module(&import.module) else {
+                    return Err(Error::MissingModule(
+                        module.path.clone(),
+                        import.line,
+                        import.module.clone(),
+                    ));
+                };
+                // BITS is an ASN.1 keyword, but SMIv2 modules import it from SNMPv2-SMI regardless
+                if let Some(symbol) = import
+                    .symbols
+                    .iter()
+                    .find(|x| *x != "BITS" && source.definition(x).is_none())
+                {
+                    return Err(Error::ImportNotFound(
+                        module.path.clone(),
+                        import.line,
+                        symbol.clone(),
+                        import.module.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.modules.iter().find(|x| x.name == name)
+    }
+
+    /// Returns the modules which aren't built in, in the order they were loaded.
+    pub fn loaded_modules(&self) -> &[Module] {
+        &self.modules[self.base_modules..]
+    }
+
+    /// Whether the given module is one of the built-in SMI definitions.
+    pub fn is_base(&self, module: &Module) -> bool {
+        self.modules[..self.base_modules]
+            .iter()
+            .any(|x| x.name == module.name)
+    }
+
+    /// Looks up the definition of a symbol as seen from within the given module, following its imports. Symbols from
+    /// the built-in modules are visible even if they weren't imported, as many MIBs rely on that.
+    pub fn lookup<'a>(
+        &'a self,
+        module: &'a Module,
+        name: &str,
+    ) -> Option<(&'a Module, &'a Definition)> {
+        if let Some(definition) = module.definition(name) {
+            return Some((module, definition));
+        }
+        let imported = module
+            .imports
+            .iter()
+            .find(|x| x.symbols.iter().any(|x| x == name))
+            .and_then(|x| self.module(&x.module));
+        imported
+            .into_iter()
+            .chain(&self.modules[..self.base_modules])
+            .find_map(|module| {
+                module
+                    .definition(name)
+                    .map(|definition| (module, definition))
+            })
+    }
+
+    /// Resolves the OID of a definition.
+    pub fn oid(&self, module: &Module, definition: &Definition) -> Result<Vec<u32>, Error> {
+        self.oid_inner(module, definition, &mut HashSet::new())
+    }
+
+    fn oid_inner(
+        &self,
+        module: &Module,
+        definition: &Definition,
+        visiting: &mut HashSet<(String, String)>,
+    ) -> Result<Vec<u32>, Error> {
+        let key = (module.name.clone(), definition.name.clone());
+        if !visiting.insert(key.clone()) {
+            return Err(Error::Cycle(
+                module.path.clone(),
+                definition.line,
+                definition.name.clone(),
+            ));
+        }
+        let components = match &definition.kind {
+            DefinitionKind::Node(x) => x,
+            DefinitionKind::ObjectType(x) => &x.oid,
+            DefinitionKind::Notification(x) => &x.oid,
+            DefinitionKind::Type(_) | DefinitionKind::Macro => {
+                return Err(Error::NotAnOid(
+                    module.path.clone(),
+                    definition.line,
+                    definition.name.clone(),
+                ))
+            }
+        };
+
+        let mut ret = vec![];
+        for (i, component) in components.iter().enumerate() {
+            match component {
+                OidComponent::Number(x) | OidComponent::NameAndNumber(_, x) => ret.push(*x),
+                OidComponent::Name(name) if i == 0 => match name.as_str() {
+                    "ccitt" => ret.push(0),
+                    "iso" => ret.push(1),
+                    "joint-iso-ccitt" => ret.push(2),
+                    _ => {
+                        let Some((parent_module, parent)) = self.lookup(module, name) else {
+                            return Err(Error::UndefinedSymbol(
+                                module.path.clone(),
+                                definition.line,
+                                name.clone(),
+                            ));
+                        };
+                        ret.extend(self.oid_inner(parent_module, parent, visiting)?);
+                    }
+                },
+                OidComponent::Name(name) => {
+                    return Err(Error::Syntax(
+                        module.path.clone(),
+                        definition.line,
+                        format!("{name} must have a number"),
+                    ))
+                }
+            }
+        }
+        visiting.remove(&key);
+        Ok(ret)
+    }
+
+    /// Resolves a syntax to its base type, following textual conventions and type assignments.
+    pub fn base_type(
+        &self,
+        module: &Module,
+        line: usize,
+        syntax: &Syntax,
+    ) -> Result<BaseType, Error> {
+        let mut module = module;
+        let mut line = line;
+        let mut syntax = syntax;
+        // bounds the number of textual conventions followed, in case they refer to each other
+        for _ in 0..64 {
+            let name = match syntax {
+                Syntax::Integer(_) => return Ok(BaseType::Integer32),
+                Syntax::OctetString(x) => return Ok(BaseType::OctetString(*x)),
+                Syntax::ObjectIdentifier => return Ok(BaseType::ObjectIdentifier),
+                Syntax::Bits(_) => return Ok(BaseType::Bits),
+                Syntax::Named(name) => name,
+                Syntax::Sequence(_) | Syntax::SequenceOf(_) | Syntax::Choice => break,
+            };
+            if let Some(x) = BaseType::application(name) {
+                return Ok(x);
+            }
+            match self.lookup(module, name) {
+                Some((
+                    next_module,
+                    Definition {
+                        kind: DefinitionKind::Type(next_syntax),
+                        line: next_line,
+                        ..
+                    },
+                )) => {
+                    module = next_module;
+                    line = *next_line;
+                    syntax = next_syntax;
+                }
+                _ => {
+                    return Err(Error::UndefinedSymbol(
+                        module.path.clone(),
+                        line,
+                        name.clone(),
+                    ))
+                }
+            }
+        }
+        Err(Error::UnsupportedSyntax(module.path.clone(), line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{base, parser::parse};
+
+    fn resolver(source: &str) -> Resolver {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        modules.extend(parse(Path::new("TEST-MIB"), source).unwrap());
+        Resolver::new(modules, base_modules).unwrap()
+    }
+
+    #[test]
+    fn oid() {
+        let resolver = resolver(
+            "TEST-MIB DEFINITIONS ::= BEGIN\ntest OBJECT IDENTIFIER ::= { mib-2 9999 }\nchild OBJECT IDENTIFIER ::= { test 1 }\nEND",
+        );
+        let module = resolver.module("TEST-MIB").unwrap();
+        let (module, child) = resolver.lookup(module, "child").unwrap();
+        assert_eq!(
+            resolver.oid(module, child).unwrap(),
+            vec![1, 3, 6, 1, 2, 1, 9999, 1]
+        );
+
+        let (module, internet) = resolver
+            .lookup(resolver.module("RFC1155-SMI").unwrap(), "internet")
+            .unwrap();
+        assert_eq!(resolver.oid(module, internet).unwrap(), vec![1, 3, 6, 1]);
+    }
+
+    #[test]
+    fn cycle() {
+        let resolver = resolver(
+            "TEST-MIB DEFINITIONS ::= BEGIN\na OBJECT IDENTIFIER ::= { b 1 }\nb OBJECT IDENTIFIER ::= { a 1 }\nEND",
+        );
+        let module = resolver.module("TEST-MIB").unwrap();
+        let (module, a) = resolver.lookup(module, "a").unwrap();
+        assert_eq!(
+            resolver.oid(module, a).unwrap_err().to_string(),
+            "TEST-MIB:2: the OID of a depends on itself"
+        );
+    }
+
+    #[test]
+    fn imports() {
+        let mut modules = base::modules();
+        let base_modules = modules.len();
+        modules.extend(
+            parse(
+                Path::new("TEST-MIB"),
+                "TEST-MIB DEFINITIONS ::= BEGIN\nIMPORTS Missing FROM SNMPv2-TC;\nEND",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            Resolver::new(modules, base_modules)
+                .err()
+                .unwrap()
+                .to_string(),
+            "TEST-MIB:2: Missing is not defined in SNMPv2-TC"
+        );
+
+        // modules importing each other are fine as long as their definitions don't depend on each other
+        let mut modules = base::modules();
+        modules.extend(
+            parse(
+                Path::new("TEST-MIB"),
+                "A DEFINITIONS ::= BEGIN\nIMPORTS b FROM B;\na OBJECT IDENTIFIER ::= { b 1 }\nEND\nB DEFINITIONS ::= BEGIN\nIMPORTS a FROM A;\nb OBJECT IDENTIFIER ::= { mib-2 1 }\nc OBJECT IDENTIFIER ::= { a 1 }\nEND",
+            )
+            .unwrap(),
+        );
+        let resolver = Resolver::new(modules, base_modules).unwrap();
+        let (module, c) = resolver.lookup(resolver.module("B").unwrap(), "c").unwrap();
+        assert_eq!(
+            resolver.oid(module, c).unwrap(),
+            vec![1, 3, 6, 1, 2, 1, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn base_type() {
+        let resolver = resolver(
+            "TEST-MIB DEFINITIONS ::= BEGIN\nName ::= TEXTUAL-CONVENTION\n SYNTAX DisplayString\nEND",
+        );
+        let module = resolver.module("TEST-MIB").unwrap();
+        assert_eq!(
+            resolver
+                .base_type(module, 1, &Syntax::Named("Name".into()))
+                .unwrap(),
+            BaseType::OctetString(None)
+        );
+        assert_eq!(
+            resolver
+                .base_type(module, 1, &Syntax::Named("MacAddress".into()))
+                .unwrap(),
+            BaseType::OctetString(Some(6))
+        );
+        assert_eq!(
+            resolver
+                .base_type(module, 1, &Syntax::Named("TimeStamp".into()))
+                .unwrap(),
+            BaseType::TimeTicks
+        );
+        assert_eq!(
+            resolver
+                .base_type(module, 7, &Syntax::Named("Missing".into()))
+                .unwrap_err()
+                .to_string(),
+            "TEST-MIB:7: Missing is not defined"
+        );
+    }
+}
@@ -1,12 +1,240 @@
+use std::path::{Path, PathBuf};
+
 use convert_case::{Case, Casing};
+use proc_macro2::Literal;
 use quote::quote;
+use snipe_build::MibCompiler;
 use syn::{
-    parse::Parse, parse_macro_input, punctuated::Punctuated, spanned::Spanned, Ident, Index, Lit,
-    Token, Type,
+    ext::IdentExt, parse::Parse, parse_macro_input, punctuated::Punctuated, Ident, Index, Lit,
+    LitStr, Token, Type,
 };
 
+/// Generates a struct for the given MIB module, with accessors for each of its objects. The MIB file is found relative
+/// to the crate's root or, by module name, in the directories listed in the `SNIPE_MIB_PATH` environment variable.
+/// Imported modules are found in the same way, or alongside the MIB file. The crate is rebuilt when any of the files
+/// read or `SNIPE_MIB_PATH` change.
 #[proc_macro]
 pub fn declare_mib(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(item as LitStr);
+    let mib_name = input.value();
+    let root = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+    let path = root.join(&mib_name);
+
+    let mut compiler = MibCompiler::new();
+    if path.is_file() {
+        compiler = compiler.with_mib(&path);
+        if let Some(dir) = path.parent() {
+            compiler = compiler.with_search_path(dir);
+        }
+    } else {
+        compiler = compiler.with_module(mib_name.strip_suffix(".mib").unwrap_or(&mib_name));
+    }
+    let (code, files) = match with_search_path(compiler, &root).generate_inline() {
+        Ok(x) => x,
+        Err(e) => return mib_error(input.span(), e).to_compile_error().into(),
+    };
+    let code = match code.parse::<proc_macro2::TokenStream>() {
+        Ok(x) => x,
+        Err(e) => return syn::Error::new(input.span(), e).to_compile_error().into(),
+    };
+    let tracking = tracking(&tracked_files(&files));
+    quote! {
+        #tracking
+        #code
+    }
+    .into()
+}
+
+/// Adds the crate's root and the directories in `SNIPE_MIB_PATH` to a MIB compiler's search path.
+fn with_search_path(mut compiler: MibCompiler, root: &Path) -> MibCompiler {
+    compiler = compiler.with_search_path(root);
+    if let Some(paths) = std::env::var_os("SNIPE_MIB_PATH") {
+        for path in std::env::split_paths(&paths) {
+            compiler = compiler.with_search_path(root.join(path));
+        }
+    }
+    compiler
+}
+
+/// Reports an error from the MIB compiler on the given span. Errors found in a MIB file point at the file and line on a
+/// line of their own, in the form rustc uses, so that terminals and editors link to them.
+fn mib_error(span: proc_macro2::Span, error: snipe_build::Error) -> syn::Error {
+    let message = match error.location() {
+        Some((path, line)) => format!("{}\n  --> {}:{line}", error.message(), path.display()),
+        None => error.to_string(),
+    };
+    syn::Error::new(span, message)
+}
+
+/// Returns an item which makes the crate rebuild when the given MIB files or `SNIPE_MIB_PATH` change. Files are read
+/// with `include_bytes!`, as MIBs aren't always valid UTF-8, and `option_env!` has rustc track the variable.
+fn tracking(files: &[String]) -> proc_macro2::TokenStream {
+    quote! {
+        const _: (&[&[u8]], Option<&str>) = (
+            &[#(include_bytes!(#files)),*],
+            option_env!("SNIPE_MIB_PATH"),
+        );
+    }
+}
+
+/// Returns the paths of the given files to pass to `include_bytes!`, so that the crate is rebuilt when they change.
+fn tracked_files(files: &[PathBuf]) -> Vec<String> {
+    files
+        .iter()
+        .filter_map(|x| x.canonicalize().ok())
+        .map(|x| x.display().to_string())
+        .collect()
+}
+
+/// An OID given to `oid!` or `declare_oid!`: either in dotted notation, quoted or not, or as a symbol in a MIB module
+/// followed by any further sub-identifiers.
+enum OidLiteral {
+    Dotted(Vec<u32>, proc_macro2::Span),
+    Symbolic {
+        module: String,
+        name: String,
+        suffix: Vec<u32>,
+        span: proc_macro2::Span,
+    },
+}
+
+impl Parse for OidLiteral {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let span = input.span();
+        if input.peek(LitStr) {
+            let literal = input.parse::<LitStr>()?;
+            return parse_dotted(&literal.value())
+                .map(|x| Self::Dotted(x, span))
+                .ok_or_else(|| {
+                    syn::Error::new(span, "expected sub-identifiers separated by dots")
+                });
+        }
+        // unquoted sub-identifiers such as 1.3.6 are split into float literals and punctuation, so they are parsed as
+        // a string
+        let rest = |input: syn::parse::ParseStream| -> syn::Result<String> {
+            Ok(input
+                .parse::<proc_macro2::TokenStream>()?
+                .to_string()
+                .replace(' ', ""))
+        };
+        if input.peek(syn::LitInt) || input.peek(syn::LitFloat) {
+            let rest = rest(input)?;
+            return parse_dotted(&rest)
+                .map(|x| Self::Dotted(x, span))
+                .ok_or_else(|| syn::Error::new(span, format!("invalid sub-identifiers {rest}")));
+        }
+        let module = hyphenated(input)?;
+        input.parse::<Token![::]>()?;
+        let name = hyphenated(input)?;
+        let suffix_span = input.span();
+        let rest = rest(input)?;
+        let suffix = match rest.strip_prefix('.') {
+            Some(x) => parse_dotted(x).ok_or_else(|| {
+                syn::Error::new(suffix_span, format!("invalid sub-identifiers {rest}"))
+            })?,
+            None if rest.is_empty() => vec![],
+            None => return Err(syn::Error::new(suffix_span, format!("unexpected {rest}"))),
+        };
+        Ok(Self::Symbolic {
+            module,
+            name,
+            suffix,
+            span,
+        })
+    }
+}
+
+impl OidLiteral {
+    /// Resolves and checks the OID. The paths of the MIB files which were read are returned alongside it, to be
+    /// passed to [`tracking`].
+    fn resolve(self) -> syn::Result<(Vec<u32>, Vec<String>)> {
+        let (oid, files, span) = match self {
+            Self::Dotted(oid, span) => (oid, vec![], span),
+            Self::Symbolic {
+                module,
+                name,
+                suffix,
+                span,
+            } => {
+                let root =
+                    PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+                let compiler = with_search_path(MibCompiler::new().with_module(&module), &root);
+                let (mut oid, files) = compiler
+                    .oid(&module, &name)
+                    .map_err(|e| mib_error(span, e))?;
+                oid.extend(suffix);
+                (oid, tracked_files(&files), span)
+            }
+        };
+        // X.690 8.19.4: the first two arcs are encoded together, so they are limited in range
+        let valid = match oid.as_slice() {
+            [0 | 1, second, ..] => *second < 40,
+            [2, _, ..] => true,
+            _ => false,
+        };
+        if !valid {
+            return Err(syn::Error::new(
+                span,
+                "an OID starts with 0, 1 or 2, followed by at least one more sub-identifier (below 40 after 0 or 1)",
+            ));
+        }
+        Ok((oid, files))
+    }
+}
+
+/// Parses a MIB name such as SNMPv2-MIB or mib-2, which Rust splits into identifiers, hyphens and numbers.
+fn hyphenated(input: syn::parse::ParseStream) -> syn::Result<String> {
+    let mut ret = Ident::parse_any(input)?.to_string();
+    while input.peek(Token![-]) {
+        input.parse::<Token![-]>()?;
+        ret.push('-');
+        if input.peek(syn::LitInt) {
+            ret.push_str(&input.parse::<syn::LitInt>()?.to_string());
+        } else {
+            ret.push_str(&Ident::parse_any(input)?.to_string());
+        }
+    }
+    Ok(ret)
+}
+
+/// Parses sub-identifiers separated by dots.
+fn parse_dotted(value: &str) -> Option<Vec<u32>> {
+    value.split('.').map(|x| x.parse().ok()).collect()
+}
+
+/// Expands to a `ConstOid` for an OID given in dotted notation, or as a symbol in a MIB module followed by any further
+/// sub-identifiers. The OID is checked at compile time:
+///
+/// ```ignore
+/// const SYS_DESCR: ConstOid = oid!("1.3.6.1.2.1.1.1.0");
+/// const IP_ADDRESS: ConstOid = oid!(EXAMPLE-MIB::ipAddress.0);
+/// ```
+///
+/// MIB modules are found in the same way as for `declare_mib!`.
+#[proc_macro]
+pub fn oid(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(item as OidLiteral);
+    let (oid, files) = match input.resolve() {
+        Ok(x) => x,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let oid = oid.iter().map(|x| Literal::u32_unsuffixed(*x));
+    if files.is_empty() {
+        quote! { ::snipe::asn::types::ConstOid(&[#(#oid),*]) }.into()
+    } else {
+        let tracking = tracking(&files);
+        quote! {
+            {
+                #tracking
+                ::snipe::asn::types::ConstOid(&[#(#oid),*])
+            }
+        }
+        .into()
+    }
+}
+
+#[proc_macro]
+pub fn __private_api_declare_mib_struct(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(item as Lit);
     let input_span = input.span();
     if let Lit::Str(mib_name_lit) = input {
@@ -35,11 +263,11 @@ pub fn declare_mib(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 fn #method_name<'a>(&'a mut self) -> #struct_name<'a, Self::Interface>;
             }
 
-            impl<T: SnmpInterface> #get_trait_name for T {
+            impl<T: ::snipe::SnmpInterface> #get_trait_name for T {
                 type Interface = T;
 
                 fn #method_name<'a>(&'a mut self) -> #struct_name<'a, Self::Interface> {
-                    #struct_name(self.snmp_interface())
+                    #struct_name(::snipe::GetSnmpInterface::snmp_interface(self))
                 }
             }
         }
@@ -49,27 +277,148 @@ pub fn declare_mib(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     }
 }
 
+/// The MAX-ACCESS (or ACCESS) of an object, which decides the traits `declare_oid!` generates.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    NotAccessible,
+    AccessibleForNotify,
+    ReadOnly,
+    ReadWrite,
+    ReadCreate,
+    WriteOnly,
+}
+
+impl Access {
+    fn is_readable(self) -> bool {
+        matches!(self, Self::ReadOnly | Self::ReadWrite | Self::ReadCreate)
+    }
+
+    fn is_writable(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadCreate | Self::WriteOnly)
+    }
+}
+
+impl Parse for Access {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let span = input.span();
+        // hyphenated names such as read-only are a series of identifiers, one of which may be a keyword
+        let mut name = Ident::parse_any(input)?.to_string();
+        while input.peek(Token![-]) {
+            input.parse::<Token![-]>()?;
+            name.push('-');
+            name.push_str(&Ident::parse_any(input)?.to_string());
+        }
+        Ok(match name.as_str() {
+            "not-accessible" => Self::NotAccessible,
+            "accessible-for-notify" => Self::AccessibleForNotify,
+            "read-only" => Self::ReadOnly,
+            "read-write" => Self::ReadWrite,
+            "read-create" => Self::ReadCreate,
+            "write-only" => Self::WriteOnly,
+            _ => return Err(syn::Error::new(span, format!("unknown access {name}"))),
+        })
+    }
+}
+
 struct DeclareOid {
+    /// The MIB struct to implement the traits for.
+    mib: Option<Ident>,
     oid_name: Lit,
     _separator: Token![,],
     type_name: Type,
+    access: Option<Access>,
+    oid: Option<OidLiteral>,
+    index: Option<Type>,
+    converter: Option<Type>,
+    index_converter: Option<Type>,
 }
 
 impl Parse for DeclareOid {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        Ok(Self {
-            oid_name: input.parse()?,
-            _separator: input.parse()?,
-            type_name: input.parse()?,
-        })
+        let mib = if input.peek(Ident) {
+            let mib = input.parse()?;
+            input.parse::<Token![,]>()?;
+            Some(mib)
+        } else {
+            None
+        };
+        let oid_name = input.parse()?;
+        let _separator = input.parse()?;
+        let type_name = input.parse()?;
+        let mut ret = Self {
+            mib,
+            oid_name,
+            _separator,
+            type_name,
+            access: None,
+            oid: None,
+            index: None,
+            converter: None,
+            index_converter: None,
+        };
+        while input.parse::<Option<Token![,]>>()?.is_some() {
+            if input.is_empty() {
+                break;
+            }
+            let key = input.parse::<Ident>()?;
+            input.parse::<Token![=]>()?;
+            let key_name = key.to_string();
+            if key_name != "access" && ret.mib.is_none() {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!("{key} can only be given along with a MIB struct"),
+                ));
+            }
+            match key_name.as_str() {
+                "access" => ret.access = Some(input.parse()?),
+                "oid" => {
+                    // the OID runs up to the next option
+                    let mut tokens = proc_macro2::TokenStream::new();
+                    while !input.is_empty() && !input.peek(Token![,]) {
+                        tokens.extend([input.parse::<proc_macro2::TokenTree>()?]);
+                    }
+                    ret.oid = Some(syn::parse2(tokens)?);
+                }
+                "index" => ret.index = Some(input.parse()?),
+                "converter" => ret.converter = Some(input.parse()?),
+                "index_converter" => ret.index_converter = Some(input.parse()?),
+                _ => return Err(syn::Error::new(key.span(), format!("unknown option {key}"))),
+            }
+        }
+        if let (Some(mib), None) = (&ret.mib, &ret.oid) {
+            return Err(syn::Error::new(
+                mib.span(),
+                "an oid must be given along with a MIB struct",
+            ));
+        }
+        Ok(ret)
     }
 }
 
+/// Declares the traits for reading and writing an object, which are implemented for a MIB's struct with the object's
+/// OID and converter:
+///
+/// ```ignore
+/// declare_oid!("ifAlias", OctetString, access = read-write);
+/// ```
+///
+/// The `Read` traits are only generated for readable objects and the `Write` traits only for writable ones, according
+/// to `access`, so that setting a read-only object doesn't compile. Without `access`, every trait is generated.
+///
+/// Given a MIB struct and the object's OID, the traits are also implemented for the struct, with `Index` set to `index`
+/// for the `Indexed` traits, and the converters defaulting to `DefaultConverter`:
+///
+/// ```ignore
+/// declare_oid!(ExampleMib, "ipAddress", IpAddress, oid = 1.3.6.1.4.1, access = read-write, index = (u32, IpAddress));
+/// ```
+///
+/// The OID is given in any form accepted by `oid!`.
 #[proc_macro]
 pub fn declare_oid(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(item as DeclareOid);
     let oid_name_span = input.oid_name.span();
     let type_name = input.type_name;
+    let access = input.access.unwrap_or(Access::ReadWrite);
     if let Lit::Str(oid_name_lit) = input.oid_name {
         let oid_name = oid_name_lit.value();
         let pascal_mib_name = oid_name.to_case(Case::Pascal);
@@ -88,68 +437,130 @@ pub fn declare_oid(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
         let get_method_name: Ident = Ident::new(snake_oid_name.as_str(), oid_name_span);
         let set_method_name: Ident =
             Ident::new(format!("set_{snake_oid_name}").as_str(), oid_name_span);
-        quote! {
-            #[async_trait::async_trait]
-            pub trait #read_trait_name: Sized + ::snipe::GetSnmpInterface {
-                type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
-                const OID: ::snipe::asn::types::ConstOid;
-                async fn #get_method_name(&mut self) -> Result<#type_name, ::snipe::Error> {
-                    <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_from_snmp(
-                        ::snipe::SnmpInterface::read(
-                            snipe::GetSnmpInterface::snmp_interface(self),
-                            Self::OID.into()
-                        ).await?
-                    )
+        let read_traits = access.is_readable().then(|| {
+            quote! {
+                #[async_trait::async_trait]
+                pub trait #read_trait_name: Sized + ::snipe::GetSnmpInterface {
+                    type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
+                    const OID: ::snipe::asn::types::ConstOid;
+                    async fn #get_method_name(&mut self) -> Result<#type_name, ::snipe::Error> {
+                        <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_from_snmp(
+                            ::snipe::SnmpInterface::read(
+                                snipe::GetSnmpInterface::snmp_interface(self),
+                                Self::OID.into()
+                            ).await?
+                        )
+                    }
+                }
+                #[async_trait::async_trait]
+                pub trait #read_indexed_trait_name: Sized + ::snipe::GetSnmpInterface {
+                    type Index: Send + Sync;
+                    type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
+                    type IndexConverter: ::snipe::prelude::OidConverter<Self::Index>;
+                    const OID: ::snipe::asn::types::ConstOid;
+                    async fn #get_method_name(&mut self, index: Self::Index) -> Result<#type_name, ::snipe::Error> {
+                        <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_from_snmp(
+                            ::snipe::SnmpInterface::read(
+                                snipe::GetSnmpInterface::snmp_interface(self),
+                                ::snipe::append_index::<_, Self::IndexConverter>(
+                                    Self::OID.into(),
+                                    index
+                                )?
+                            ).await?
+                        )
+                    }
                 }
             }
-            #[async_trait::async_trait]
-            pub trait #read_indexed_trait_name: Sized + ::snipe::GetSnmpInterface {
-                type Index: Send + Sync;
-                type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
-                type IndexConverter: ::snipe::prelude::OidConverter<Self::Index>;
-                const OID: ::snipe::asn::types::ConstOid;
-                async fn #get_method_name(&mut self, index: Self::Index) -> Result<#type_name, ::snipe::Error> {
-                    <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_from_snmp(
-                        ::snipe::SnmpInterface::read(
+        });
+        let write_traits = access.is_writable().then(|| {
+            quote! {
+                #[async_trait::async_trait]
+                pub trait #write_trait_name: Sized + ::snipe::GetSnmpInterface {
+                    type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
+                    const OID: ::snipe::asn::types::ConstOid;
+                    async fn #set_method_name(&mut self, value: #type_name) -> Result<(), ::snipe::Error> {
+                        ::snipe::SnmpInterface::write(
+                            snipe::GetSnmpInterface::snmp_interface(self),
+                            Self::OID.into(),
+                            <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_to_snmp(value)?
+                        ).await
+                    }
+                }
+                #[async_trait::async_trait]
+                pub trait #write_indexed_trait_name: Sized + ::snipe::GetSnmpInterface {
+                    type Index: Send + Sync;
+                    type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
+                    type IndexConverter: ::snipe::prelude::OidConverter<Self::Index>;
+                    const OID: ::snipe::asn::types::ConstOid;
+                    async fn #set_method_name(&mut self, index: Self::Index, value: #type_name) -> Result<(), ::snipe::Error> {
+                        ::snipe::SnmpInterface::write(
                             snipe::GetSnmpInterface::snmp_interface(self),
                             ::snipe::append_index::<_, Self::IndexConverter>(
                                 Self::OID.into(),
                                 index
-                            )?
-                        ).await?
-                    )
-                }
-            }
-            #[async_trait::async_trait]
-            pub trait #write_trait_name: Sized + ::snipe::GetSnmpInterface {
-                type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
-                const OID: ::snipe::asn::types::ConstOid;
-                async fn #set_method_name(&mut self, value: #type_name) -> Result<(), ::snipe::Error> {
-                    ::snipe::SnmpInterface::write(
-                        snipe::GetSnmpInterface::snmp_interface(self),
-                        Self::OID.into(),
-                        <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_to_snmp(value)?
-                    ).await
+                            )?,
+                            <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_to_snmp(value)?
+                        ).await
+                    }
                 }
             }
-            #[async_trait::async_trait]
-            pub trait #write_indexed_trait_name: Sized + ::snipe::GetSnmpInterface {
-                type Index: Send + Sync;
-                type Converter: ::snipe::prelude::SnmpConverter<#type_name>;
-                type IndexConverter: ::snipe::prelude::OidConverter<Self::Index>;
-                const OID: ::snipe::asn::types::ConstOid;
-                async fn #set_method_name(&mut self, index: Self::Index, value: #type_name) -> Result<(), ::snipe::Error> {
-                    ::snipe::SnmpInterface::write(
-                        snipe::GetSnmpInterface::snmp_interface(self),
-                        ::snipe::append_index::<_, Self::IndexConverter>(
-                            Self::OID.into(),
-                            index
-                        )?,
-                        <Self::Converter as ::snipe::prelude::SnmpConverter<#type_name>>::try_to_snmp(value)?
-                    ).await
+        });
+        let impls = match (input.mib, input.oid) {
+            (Some(mib), Some(oid)) => {
+                let (oid, files) = match oid.resolve() {
+                    Ok(x) => x,
+                    Err(e) => return e.to_compile_error().into(),
+                };
+                let oid = oid.iter().map(|x| Literal::u32_unsuffixed(*x));
+                let oid = quote! { ::snipe::asn::types::ConstOid(&[#(#oid),*]) };
+                let converter = input
+                    .converter
+                    .map(|x| quote! { #x })
+                    .unwrap_or_else(|| quote! { ::snipe::prelude::DefaultConverter });
+                let (read, write, index_items) = match input.index {
+                    Some(index) => {
+                        let index_converter = input
+                            .index_converter
+                            .map(|x| quote! { #x })
+                            .unwrap_or_else(|| quote! { ::snipe::prelude::DefaultConverter });
+                        (
+                            read_indexed_trait_name,
+                            write_indexed_trait_name,
+                            quote! {
+                                type Index = #index;
+                                type IndexConverter = #index_converter;
+                            },
+                        )
+                    }
+                    None => (read_trait_name, write_trait_name, quote! {}),
+                };
+                let traits = [
+                    access.is_readable().then_some(read),
+                    access.is_writable().then_some(write),
+                ];
+                let impls = traits.into_iter().flatten().map(|trait_name| {
+                    quote! {
+                        impl<'a, I: ::snipe::SnmpInterface + Send + Sync> #trait_name for #mib<'a, I> {
+                            #index_items
+                            type Converter = #converter;
+                            const OID: ::snipe::asn::types::ConstOid = #oid;
+                        }
+                    }
+                });
+                let files = (!files.is_empty()).then(|| tracking(&files));
+                quote! {
+                    #(#impls)*
+                    #files
                 }
             }
-        }.into()
+            _ => quote! {},
+        };
+        quote! {
+            #read_traits
+            #write_traits
+            #impls
+        }
+        .into()
     } else {
         proc_macro::TokenStream::new()
     }
@@ -221,3 +632,94 @@ pub fn __private_api_tuple_oid_converter_impl(
         proc_macro::TokenStream::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(input: &str) -> syn::Result<Vec<u32>> {
+        syn::parse_str::<OidLiteral>(input)?
+            .resolve()
+            .map(|(oid, _)| oid)
+    }
+
+    #[test]
+    fn dotted_oids() {
+        assert_eq!(resolve("\"1.3.6.1.2.1\"").unwrap(), [1, 3, 6, 1, 2, 1]);
+        assert_eq!(resolve("1.3.6.1.2.1").unwrap(), [1, 3, 6, 1, 2, 1]);
+        assert_eq!(resolve("2.999").unwrap(), [2, 999]);
+        assert!(resolve("\"1.3.x\"").is_err());
+        assert!(resolve("1.3..6").is_err());
+    }
+
+    #[test]
+    fn symbolic_oids() {
+        assert_eq!(
+            resolve("SNMPv2-SMI::enterprises.0").unwrap(),
+            [1, 3, 6, 1, 4, 1, 0]
+        );
+        assert_eq!(
+            resolve("SNMPv2-SMI::enterprises").unwrap(),
+            [1, 3, 6, 1, 4, 1]
+        );
+        assert!(resolve("SNMPv2-SMI::enterprises.x").is_err());
+        assert!(resolve("SNMPv2-SMI::enterprises 0").is_err());
+        assert!(resolve("SNMPv2-SMI::noSuchObject").is_err());
+    }
+
+    #[test]
+    fn first_arcs() {
+        for input in ["\"3.1\"", "1.40", "0.40.1", "\"1\""] {
+            let error = resolve(input).unwrap_err().to_string();
+            assert!(
+                error.starts_with("an OID starts with 0, 1 or 2"),
+                "{input}: {error}"
+            );
+        }
+        assert_eq!(resolve("1.39").unwrap(), [1, 39]);
+    }
+
+    fn declare_oid(input: &str) -> syn::Result<DeclareOid> {
+        syn::parse_str(input)
+    }
+
+    #[test]
+    fn declare_oid_for_mib_struct() {
+        let input = declare_oid(
+            "ExampleMib, \"ipAddress\", IpAddress, oid = 1.3.6.1.4.1, access = read-write, index = (u32, IpAddress)",
+        )
+        .unwrap();
+        assert_eq!(input.mib.unwrap().to_string(), "ExampleMib");
+        assert!(matches!(input.oid_name, Lit::Str(x) if x.value() == "ipAddress"));
+        assert!(matches!(input.access, Some(Access::ReadWrite)));
+        assert!(matches!(input.oid, Some(OidLiteral::Dotted(x, _)) if x == [1, 3, 6, 1, 4, 1]));
+        let index = input.index.unwrap();
+        assert_eq!(quote!(#index).to_string(), "(u32 , IpAddress)");
+        assert!(input.converter.is_none() && input.index_converter.is_none());
+
+        let input = declare_oid("\"ifAlias\", OctetString, access = read-only").unwrap();
+        assert!(input.mib.is_none() && input.oid.is_none());
+        assert!(matches!(input.access, Some(Access::ReadOnly)));
+    }
+
+    #[test]
+    fn declare_oid_errors() {
+        let error = |input: &str| declare_oid(input).err().unwrap().to_string();
+        assert_eq!(
+            error("\"ipAddress\", IpAddress, oid = 1.3.6.1.4.1"),
+            "oid can only be given along with a MIB struct"
+        );
+        assert_eq!(
+            error("ExampleMib, \"ipAddress\", IpAddress, access = read-write"),
+            "an oid must be given along with a MIB struct"
+        );
+        assert_eq!(
+            error("ExampleMib, \"ipAddress\", IpAddress, oid = 1.3.6.1.4.1, colour = red"),
+            "unknown option colour"
+        );
+        assert_eq!(
+            error("ExampleMib, \"ipAddress\", IpAddress, oid = 1.3.6.1.4.1, access = readable"),
+            "unknown access readable"
+        );
+    }
+}
@@ -0,0 +1,1848 @@
+//! An SNMP agent (command responder), which answers Get, GetNext, GetBulk and Set requests by dispatching them to
+//! handlers registered on OID subtrees.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    mem::discriminant,
+    net::SocketAddr,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use rasn::types::{ObjectIdentifier, OctetString};
+use rasn_smi::{
+    v1::Counter,
+    v2::{ApplicationSyntax, ObjectSyntax},
+};
+use rasn_snmp::{
+    v1 as snmp_v1,
+    v2::{
+        BulkPdu, GetNextRequest, GetRequest, Pdu, Pdus, Report, Response, SetRequest, VarBind,
+        VarBindList, VarBindValue,
+    },
+    v2c,
+    v3::{self, ScopedPdu},
+};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use crate::{
+    notification::MAX_DATAGRAM_SIZE,
+    prelude::{DefaultConverter, OidConverter, SnmpConverter},
+    rfc3412::{message_version, request_id, MessageFlags, SnmpVersion},
+    rfc3414::{
+        encode_response, process_authoritative, LocalEngine, UsmStats, UsmUser, UsmUserStore,
+    },
+    rfc3415::{AccessDenied, SecurityLevel, SecurityModel, Vacm, View, ViewType},
+    rfc3584::{v1_to_v2_value, v2_to_v1_value},
+};
+
+// snmpUnknownContexts.0, reported for requests naming a context the agent doesn't have (RFC 3413 section 3.2).
+const UNKNOWN_CONTEXTS: [u32; 10] = [1, 3, 6, 1, 6, 3, 12, 1, 5, 0];
+
+// The most variable bindings returned for a GetBulkRequest, keeping responses well within a UDP datagram.
+const MAX_BULK_VARBINDS: usize = 512;
+
+/// The error-status of a Response PDU, as defined in RFC 3416 section 3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorStatus {
+    TooBig = 1,
+    NoSuchName = 2,
+    BadValue = 3,
+    ReadOnly = 4,
+    GenErr = 5,
+    NoAccess = 6,
+    WrongType = 7,
+    WrongLength = 8,
+    WrongEncoding = 9,
+    WrongValue = 10,
+    NoCreation = 11,
+    InconsistentValue = 12,
+    ResourceUnavailable = 13,
+    CommitFailed = 14,
+    UndoFailed = 15,
+    AuthorizationError = 16,
+    NotWritable = 17,
+    InconsistentName = 18,
+}
+
+impl ErrorStatus {
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    /// The equivalent SNMPv1 error-status, as described in RFC 3584 section 4.4.
+    pub fn to_v1(self) -> ErrorStatus {
+        match self {
+            ErrorStatus::WrongValue
+            | ErrorStatus::WrongEncoding
+            | ErrorStatus::WrongType
+            | ErrorStatus::WrongLength
+            | ErrorStatus::InconsistentValue => ErrorStatus::BadValue,
+            ErrorStatus::NoAccess
+            | ErrorStatus::NotWritable
+            | ErrorStatus::NoCreation
+            | ErrorStatus::InconsistentName
+            | ErrorStatus::AuthorizationError => ErrorStatus::NoSuchName,
+            ErrorStatus::ResourceUnavailable
+            | ErrorStatus::CommitFailed
+            | ErrorStatus::UndoFailed => ErrorStatus::GenErr,
+            x => x,
+        }
+    }
+}
+
+/// Identifies a variable binding of a SetRequest, so that handlers keep what they save for each write apart, even when
+/// a request sets the same instance more than once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SetId {
+    /// A number unique to the request within the process.
+    pub request: u64,
+    /// The position of the variable binding within the request, starting from 0.
+    pub varbind: usize,
+}
+
+impl SetId {
+    // The first variable binding of a new request, numbered across all agents and subagents.
+    pub(crate) fn new_request() -> Self {
+        static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+        Self {
+            request: NEXT_REQUEST.fetch_add(1, Ordering::Relaxed),
+            varbind: 0,
+        }
+    }
+
+    pub(crate) fn varbind(self, varbind: usize) -> Self {
+        Self { varbind, ..self }
+    }
+}
+
+/// Serves the instances within the OID subtree it is registered on. OIDs are given to and returned by handlers relative
+/// to that subtree.
+#[async_trait::async_trait]
+pub trait Handler: Send + Sync {
+    /// Returns the value of the given instance, or [`VarBindValue::NoSuchObject`]/[`VarBindValue::NoSuchInstance`] if
+    /// it doesn't exist.
+    async fn get(&self, oid: &[u32]) -> Result<VarBindValue, ErrorStatus>;
+
+    /// Returns the first instance following the given OID in lexicographic order along with its value, or `None` if
+    /// there are no more instances in the subtree.
+    async fn get_next(&self, oid: &[u32]) -> Result<Option<(Vec<u32>, ObjectSyntax)>, ErrorStatus>;
+
+    /// Checks that the given instance can be set to the given value, reserving anything needed to do so. This is the
+    /// first phase of a SetRequest, and no instance is changed unless every variable binding passes it. Nothing is
+    /// writable by default.
+    async fn test(&self, id: SetId, oid: &[u32], value: &ObjectSyntax) -> Result<(), ErrorStatus> {
+        let _ = (id, oid, value);
+        Err(ErrorStatus::NotWritable)
+    }
+
+    /// Sets the value of the given instance, once every variable binding has passed [`Handler::test`]. The previous
+    /// value must be kept under `id` until [`Handler::cleanup`] in case the set has to be undone.
+    async fn commit(&self, id: SetId, oid: &[u32], value: ObjectSyntax) -> Result<(), ErrorStatus> {
+        let _ = (id, oid, value);
+        Err(ErrorStatus::CommitFailed)
+    }
+
+    /// Restores the value the given instance had before [`Handler::commit`], after another variable binding in the
+    /// same request failed to commit.
+    async fn undo(&self, id: SetId, oid: &[u32]) -> Result<(), ErrorStatus> {
+        let _ = (id, oid);
+        Err(ErrorStatus::UndoFailed)
+    }
+
+    /// Releases anything kept for the given instance by [`Handler::test`] and [`Handler::commit`], once the request
+    /// has succeeded or failed.
+    async fn cleanup(&self, id: SetId, oid: &[u32]) {
+        let _ = (id, oid);
+    }
+}
+
+type Getter<T> = Box<dyn Fn() -> T + Send + Sync>;
+type Setter<T> = Box<dyn Fn(T) -> Result<(), ErrorStatus> + Send + Sync>;
+
+/// A handler for a scalar object, registered on the object's OID. Its single instance is `.0`, with values converted
+/// using the converter `C`.
+pub struct Scalar<T, C = DefaultConverter> {
+    get: Getter<T>,
+    set: Option<Setter<T>>,
+    previous: Mutex<HashMap<SetId, T>>,
+    converter: PhantomData<fn() -> C>,
+}
+
+impl<T> Scalar<T>
+where
+    DefaultConverter: SnmpConverter<T>,
+{
+    /// A read-only scalar whose value is given by `get`, converted with the [`DefaultConverter`].
+    pub fn new(get: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        Self::with_converter(get)
+    }
+}
+
+impl<T, C: SnmpConverter<T>> Scalar<T, C> {
+    /// A read-only scalar whose value is given by `get`, converted with the converter `C`.
+    pub fn with_converter(get: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        Self {
+            get: Box::new(get),
+            set: None,
+            previous: Mutex::new(HashMap::new()),
+            converter: PhantomData,
+        }
+    }
+
+    /// Makes the scalar writable, with values written to it passed to `set`. A set is undone by passing `set` the
+    /// scalar's previous value.
+    pub fn with_set(
+        mut self,
+        set: impl Fn(T) -> Result<(), ErrorStatus> + Send + Sync + 'static,
+    ) -> Self {
+        self.set = Some(Box::new(set));
+        self
+    }
+
+    fn value(&self) -> Result<ObjectSyntax, ErrorStatus> {
+        C::try_to_snmp((self.get)()).map_err(|_| ErrorStatus::GenErr)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Send + 'static, C: SnmpConverter<T> + 'static> Handler for Scalar<T, C> {
+    async fn get(&self, oid: &[u32]) -> Result<VarBindValue, ErrorStatus> {
+        match oid {
+            [0] => Ok(VarBindValue::Value(self.value()?)),
+            [] => Ok(VarBindValue::NoSuchObject),
+            _ => Ok(VarBindValue::NoSuchInstance),
+        }
+    }
+
+    async fn get_next(&self, oid: &[u32]) -> Result<Option<(Vec<u32>, ObjectSyntax)>, ErrorStatus> {
+        if oid < &[0][..] {
+            Ok(Some((vec![0], self.value()?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn test(&self, _: SetId, oid: &[u32], value: &ObjectSyntax) -> Result<(), ErrorStatus> {
+        match (&self.set, oid) {
+            (Some(_), [0]) => C::try_from_snmp(value.clone())
+                .map(drop)
+                .map_err(|_| ErrorStatus::WrongType),
+            (Some(_), _) => Err(ErrorStatus::NoCreation),
+            (None, _) => Err(ErrorStatus::NotWritable),
+        }
+    }
+
+    async fn commit(&self, id: SetId, _: &[u32], value: ObjectSyntax) -> Result<(), ErrorStatus> {
+        let set = self.set.as_ref().ok_or(ErrorStatus::CommitFailed)?;
+        let value = C::try_from_snmp(value).map_err(|_| ErrorStatus::CommitFailed)?;
+        let previous = (self.get)();
+        set(value)?;
+        lock(&self.previous).insert(id, previous);
+        Ok(())
+    }
+
+    async fn undo(&self, id: SetId, _: &[u32]) -> Result<(), ErrorStatus> {
+        let previous = lock(&self.previous).remove(&id);
+        match (&self.set, previous) {
+            (Some(set), Some(previous)) => set(previous).map_err(|_| ErrorStatus::UndoFailed),
+            _ => Err(ErrorStatus::UndoFailed),
+        }
+    }
+
+    async fn cleanup(&self, id: SetId, _: &[u32]) {
+        lock(&self.previous).remove(&id);
+    }
+}
+
+/// A conceptual row of a [`Table`].
+pub trait Row {
+    /// Returns the value of the given column (the sub-identifier following the table's entry OID), or `None` if the
+    /// row has no such column.
+    fn column(&self, column: u32) -> Result<Option<ObjectSyntax>, ErrorStatus>;
+}
+
+type ColumnSetter<I> = Box<dyn Fn(I, u32, ObjectSyntax) -> Result<(), ErrorStatus> + Send + Sync>;
+type ColumnTest<I> = Box<dyn Fn(&I, u32, &ObjectSyntax) -> Result<(), ErrorStatus> + Send + Sync>;
+
+/// The rows of a [`Table`], kept in the lexicographic order of their index OIDs so that GetNext and GetBulk requests
+/// start from the requested index instead of going through every row. Indices are converted to OIDs using the converter
+/// `C`, as described in RFC 1212 section 4.1.6.
+///
+/// Clones share the same rows, so the application keeps one to add, change and remove rows while the table is served.
+pub struct TableRows<I, R, C = DefaultConverter> {
+    rows: Arc<RwLock<BTreeMap<Vec<u32>, R>>>,
+    converter: PhantomData<fn(I) -> C>,
+}
+
+impl<I, R, C> Clone for TableRows<I, R, C> {
+    fn clone(&self) -> Self {
+        Self {
+            rows: self.rows.clone(),
+            converter: PhantomData,
+        }
+    }
+}
+
+impl<I, R> TableRows<I, R>
+where
+    DefaultConverter: OidConverter<I>,
+{
+    /// An empty table, with indices converted using the [`DefaultConverter`].
+    pub fn new() -> Self {
+        Self::with_converter()
+    }
+}
+
+impl<I, R> Default for TableRows<I, R>
+where
+    DefaultConverter: OidConverter<I>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, R, C: OidConverter<I>> TableRows<I, R, C> {
+    /// An empty table, with indices converted using the converter `C`.
+    pub fn with_converter() -> Self {
+        Self {
+            rows: Arc::new(RwLock::new(BTreeMap::new())),
+            converter: PhantomData,
+        }
+    }
+
+    /// Adds a row, returning the row it replaced if there already was one with the same index.
+    pub fn insert(&self, index: I, row: R) -> Result<Option<R>, crate::Error> {
+        let index = C::try_to_oid(index)?.to_vec();
+        Ok(write(&self.rows).insert(index, row))
+    }
+
+    /// Removes the row with the given index, returning it if there was one.
+    pub fn remove(&self, index: I) -> Result<Option<R>, crate::Error> {
+        let index = C::try_to_oid(index)?;
+        Ok(write(&self.rows).remove(&index[..]))
+    }
+
+    /// Changes the row with the given index in place, returning the result of `f`, or `None` if there is no such row.
+    pub fn update<T>(
+        &self,
+        index: I,
+        f: impl FnOnce(&mut R) -> T,
+    ) -> Result<Option<T>, crate::Error> {
+        let index = C::try_to_oid(index)?;
+        Ok(write(&self.rows).get_mut(&index[..]).map(f))
+    }
+}
+
+/// A handler for a conceptual table, registered on the OID of the table's entry (e.g. ifEntry). Instances are
+/// identified by a column followed by the row's index, whose rows are given by [`TableRows`].
+///
+/// GetNext and GetBulk requests are answered in lexicographic order, walking each column in turn.
+pub struct Table<I, R, C = DefaultConverter> {
+    columns: Vec<u32>,
+    rows: TableRows<I, R, C>,
+    set: Option<ColumnSetter<I>>,
+    test: Option<ColumnTest<I>>,
+    previous: Mutex<HashMap<SetId, ObjectSyntax>>,
+}
+
+impl<I, R: Row, C: OidConverter<I>> Table<I, R, C> {
+    /// A read-only table with the given columns and rows.
+    pub fn new(columns: impl IntoIterator<Item = u32>, rows: TableRows<I, R, C>) -> Self {
+        let mut columns = columns.into_iter().collect::<Vec<_>>();
+        columns.sort_unstable();
+        columns.dedup();
+        Self {
+            columns,
+            rows,
+            set: None,
+            test: None,
+            previous: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Makes the columns of existing rows writable, with the index, column and value of each write passed to `set`. A
+    /// set is undone by passing `set` the column's previous value. Rows can't be created.
+    ///
+    /// Values whose syntax differs from the column's current value are rejected with wrongType before `set` is called.
+    /// Use [`Table::with_test`] to check the values themselves.
+    pub fn with_set(
+        mut self,
+        set: impl Fn(I, u32, ObjectSyntax) -> Result<(), ErrorStatus> + Send + Sync + 'static,
+    ) -> Self {
+        self.set = Some(Box::new(set));
+        self
+    }
+
+    /// Checks the index, column and value of each write with `test` before any is set, so that values `set` would
+    /// refuse are reported as e.g. wrongValue or wrongLength rather than commitFailed.
+    pub fn with_test(
+        mut self,
+        test: impl Fn(&I, u32, &ObjectSyntax) -> Result<(), ErrorStatus> + Send + Sync + 'static,
+    ) -> Self {
+        self.test = Some(Box::new(test));
+        self
+    }
+
+    // Parses an instance's OID into its column, index OID and index.
+    fn parse<'a>(&self, oid: &'a [u32]) -> Option<(u32, &'a [u32], I)> {
+        let (column, index) = oid.split_first()?;
+        match C::try_from_oid(index) {
+            Ok(x) if x.num_consumed == index.len() && self.columns.contains(column) => {
+                Some((*column, index, x.converted))
+            }
+            _ => None,
+        }
+    }
+
+    // The current value of an instance, if it exists.
+    fn value(&self, column: u32, index: &[u32]) -> Result<Option<ObjectSyntax>, ErrorStatus> {
+        match read(&self.rows.rows).get(index) {
+            Some(row) => row.column(column),
+            None => Ok(None),
+        }
+    }
+
+    fn set_column(&self, oid: &[u32], value: ObjectSyntax) -> Result<(), ErrorStatus> {
+        let set = self.set.as_ref().ok_or(ErrorStatus::NotWritable)?;
+        let (column, _, index) = self.parse(oid).ok_or(ErrorStatus::NoCreation)?;
+        set(index, column, value)
+    }
+}
+
+#[async_trait::async_trait]
+impl<I: 'static, R: Row + Send + Sync + 'static, C: OidConverter<I> + 'static> Handler
+    for Table<I, R, C>
+{
+    async fn get(&self, oid: &[u32]) -> Result<VarBindValue, ErrorStatus> {
+        if !oid.first().is_some_and(|x| self.columns.contains(x)) {
+            return Ok(VarBindValue::NoSuchObject);
+        }
+        // only well-formed indices can name an instance
+        let value = match self.parse(oid) {
+            Some((column, index, _)) => self.value(column, index)?,
+            None => None,
+        };
+        Ok(value.map_or(VarBindValue::NoSuchInstance, VarBindValue::Value))
+    }
+
+    async fn get_next(&self, oid: &[u32]) -> Result<Option<(Vec<u32>, ObjectSyntax)>, ErrorStatus> {
+        let rows = read(&self.rows.rows);
+        for &column in &self.columns {
+            // rows following the given OID within this column, or the whole column if it follows the OID
+            let after = match oid.split_first() {
+                Some((first, _)) if column < *first => continue,
+                Some((first, index)) if column == *first => index,
+                _ => &[],
+            };
+            let candidates = rows.range::<[u32], _>((Bound::Excluded(after), Bound::Unbounded));
+            for (index, row) in candidates {
+                if let Some(value) = row.column(column)? {
+                    return Ok(Some(([&[column][..], index].concat(), value)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    async fn test(&self, _: SetId, oid: &[u32], value: &ObjectSyntax) -> Result<(), ErrorStatus> {
+        if self.set.is_none() {
+            return Err(ErrorStatus::NotWritable);
+        }
+        let (column, index_oid, index) = self.parse(oid).ok_or(ErrorStatus::NoCreation)?;
+        let current = self
+            .value(column, index_oid)?
+            .ok_or(ErrorStatus::NoCreation)?;
+        if !same_syntax(&current, value) {
+            return Err(ErrorStatus::WrongType);
+        }
+        match &self.test {
+            Some(test) => test(&index, column, value),
+            None => Ok(()),
+        }
+    }
+
+    async fn commit(&self, id: SetId, oid: &[u32], value: ObjectSyntax) -> Result<(), ErrorStatus> {
+        let previous = match self.parse(oid) {
+            Some((column, index, _)) => self.value(column, index)?,
+            None => None,
+        };
+        let previous = previous.ok_or(ErrorStatus::CommitFailed)?;
+        self.set_column(oid, value)?;
+        lock(&self.previous).insert(id, previous);
+        Ok(())
+    }
+
+    async fn undo(&self, id: SetId, oid: &[u32]) -> Result<(), ErrorStatus> {
+        let previous = lock(&self.previous)
+            .remove(&id)
+            .ok_or(ErrorStatus::UndoFailed)?;
+        self.set_column(oid, previous)
+            .map_err(|_| ErrorStatus::UndoFailed)
+    }
+
+    async fn cleanup(&self, id: SetId, _: &[u32]) {
+        lock(&self.previous).remove(&id);
+    }
+}
+
+/// Handlers registered on non-overlapping OID subtrees, shared by the agent and the AgentX subagent.
+#[derive(Default)]
+pub(crate) struct Handlers(BTreeMap<Vec<u32>, Box<dyn Handler>>);
+
+impl Handlers {
+    pub(crate) fn insert(
+        &mut self,
+        oid: Vec<u32>,
+        handler: Box<dyn Handler>,
+    ) -> Result<(), crate::Error> {
+        if self.overlaps(&oid) {
+            return Err(crate::Error::OverlappingHandler);
+        }
+        self.0.insert(oid, handler);
+        Ok(())
+    }
+
+    /// Whether a handler is registered on a subtree overlapping the one rooted at the given OID.
+    pub(crate) fn overlaps(&self, oid: &[u32]) -> bool {
+        self.0
+            .keys()
+            .any(|x| x.starts_with(oid) || oid.starts_with(x))
+    }
+
+    /// Finds the handler whose subtree contains the given OID, and the length of its subtree's OID.
+    pub(crate) fn find(&self, oid: &[u32]) -> Option<(usize, &dyn Handler)> {
+        // subtrees don't overlap, so only the last subtree before the OID can contain it
+        self.0
+            .range(..=oid.to_vec())
+            .next_back()
+            .filter(|(base, _)| oid.starts_with(base))
+            .map(|(base, handler)| (base.len(), handler.as_ref()))
+    }
+
+    /// Finds the first instance following the given OID across all handlers, along with its value. Instances for
+    /// which `skip` returns true are passed over.
+    pub(crate) async fn next(
+        &self,
+        oid: &[u32],
+        skip: &(dyn Fn(&[u32], &ObjectSyntax) -> bool + Sync),
+    ) -> Result<Option<(Vec<u32>, ObjectSyntax)>, ErrorStatus> {
+        let mut oid = oid.to_vec();
+        for (base, handler) in &self.0 {
+            loop {
+                let next = if oid.starts_with(base) {
+                    handler.get_next(&oid[base.len()..]).await?
+                } else if base[..] > oid[..] {
+                    handler.get_next(&[]).await?
+                } else {
+                    None
+                };
+                let (suffix, value) = match next {
+                    Some(x) => x,
+                    None => break,
+                };
+                let name = base.iter().chain(&suffix).copied().collect::<Vec<_>>();
+                if skip(&name, &value) {
+                    oid = name;
+                    continue;
+                }
+                return Ok(Some((name, value)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// The result of processing a request's variable bindings: the response's variable bindings, or the error-status and
+// (1-based) error-index.
+type VarBindsResult = Result<VarBindList, (ErrorStatus, u32)>;
+
+// The principal a request was received from, as described in RFC 3411 section 3.2.
+struct Principal<'a> {
+    security_model: SecurityModel,
+    security_name: &'a [u8],
+    security_level: SecurityLevel,
+    context_name: &'a [u8],
+}
+
+// What a request may access: the view selected for it when access control is enabled, and whether it was sent with
+// SNMPv1, which can't carry Counter64 values.
+struct Scope<'a> {
+    view: Option<View<'a>>,
+    v1: bool,
+}
+
+impl Scope<'_> {
+    fn contains(&self, oid: &[u32]) -> bool {
+        match &self.view {
+            Some(view) => view.contains(oid),
+            None => true,
+        }
+    }
+}
+
+/// Answers SNMP requests on a UDP port.
+///
+/// SNMPv1 and SNMPv2c requests are only answered for the configured communities, and SNMPv3 requests for the configured
+/// users, with the agent acting as the authoritative engine (RFC 3414 section 3.2). Every OID is accessible to them
+/// unless access control is configured with [`Agent::with_vacm`]. Without access control, the agent only has the
+/// default context, and SNMPv3 requests naming any other context get an snmpUnknownContexts Report.
+pub struct Agent {
+    socket: UdpSocket,
+    communities: Vec<OctetString>,
+    users: UsmUserStore,
+    engine: LocalEngine,
+    stats: UsmStats,
+    unknown_contexts: AtomicU32,
+    handlers: Handlers,
+    vacm: Option<Vacm>,
+    buf: Box<[u8]>,
+}
+
+impl Agent {
+    /// Binds an agent to the given address, with a randomly generated engine ID. Use [`Agent::with_engine`] to give the
+    /// agent a persistent engine ID.
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, crate::Error> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr).await?,
+            communities: vec![],
+            users: UsmUserStore::new(),
+            engine: LocalEngine::new(LocalEngine::random_engine_id(), 1)?,
+            stats: UsmStats::default(),
+            unknown_contexts: AtomicU32::new(0),
+            handlers: Handlers::default(),
+            vacm: None,
+            buf: vec![0_u8; MAX_DATAGRAM_SIZE].into_boxed_slice(),
+        })
+    }
+
+    /// Answers SNMPv1 and SNMPv2c requests sent with the given community.
+    pub fn with_community(mut self, community: impl Into<OctetString>) -> Self {
+        self.communities.push(community.into());
+        self
+    }
+
+    /// Answers SNMPv3 requests sent by the given user.
+    pub fn with_user(mut self, user: UsmUser) -> Result<Self, crate::Error> {
+        self.users.insert_wildcard(user)?;
+        Ok(self)
+    }
+
+    pub fn with_engine(mut self, engine: LocalEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Checks every variable binding against the given access control configuration. Community-based requests are
+    /// checked using the community as their security name.
+    pub fn with_vacm(mut self, vacm: Vacm) -> Self {
+        self.vacm = Some(vacm);
+        self
+    }
+
+    /// Registers a handler for the subtree rooted at the given OID. Subtrees of different handlers must not overlap.
+    pub fn with_handler(
+        mut self,
+        oid: impl Into<ObjectIdentifier>,
+        handler: impl Handler + 'static,
+    ) -> Result<Self, crate::Error> {
+        self.handlers
+            .insert(oid.into().to_vec(), Box::new(handler))?;
+        Ok(self)
+    }
+
+    pub fn engine(&self) -> &LocalEngine {
+        &self.engine
+    }
+
+    pub fn stats(&self) -> &UsmStats {
+        &self.stats
+    }
+
+    /// The number of requests dropped because they named a context unknown to the agent (snmpUnknownContexts).
+    pub fn unknown_contexts(&self) -> u32 {
+        self.unknown_contexts.load(Ordering::Relaxed)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, crate::Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Answers requests until receiving one fails. Requests which can't be processed are dropped, as described in
+    /// RFC 3412 section 7.2.
+    pub async fn run(&mut self) -> Result<(), crate::Error> {
+        loop {
+            let (len, source) = self.socket.recv_from(&mut self.buf[..]).await?;
+            if let Some(reply) = self.process(&self.buf[..len]).await {
+                // a reply which can't be sent, such as to an unreachable requester, only affects that request
+                let _ = self.socket.send_to(&reply, source).await;
+            }
+        }
+    }
+
+    pub(crate) async fn process(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let reply = match message_version(data).ok()? {
+            SnmpVersion::V1 => self.process_v1(data).await,
+            SnmpVersion::V2c => self.process_v2c(data).await,
+            SnmpVersion::V3 => self.process_v3(data).await,
+        };
+        reply.ok().flatten()
+    }
+
+    fn check_community(&self, community: &OctetString) -> Result<(), crate::Error> {
+        if self.communities.contains(community) {
+            Ok(())
+        } else {
+            Err(crate::Error::UnknownCommunity)
+        }
+    }
+
+    async fn process_v1(&self, data: &[u8]) -> Result<Option<Vec<u8>>, crate::Error> {
+        let msg: snmp_v1::Message<snmp_v1::Pdus> =
+            rasn::ber::decode(data).map_err(crate::Error::AsnDecode)?;
+        self.check_community(&msg.community)?;
+        let (pdu, request): (_, fn(Pdu) -> Pdus) = match &msg.data {
+            snmp_v1::Pdus::GetRequest(x) => (&x.0, |x| Pdus::GetRequest(GetRequest(x))),
+            snmp_v1::Pdus::GetNextRequest(x) => (&x.0, |x| Pdus::GetNextRequest(GetNextRequest(x))),
+            snmp_v1::Pdus::SetRequest(x) => (&x.0, |x| Pdus::SetRequest(SetRequest(x))),
+            _ => return Err(crate::Error::UnexpectedPdu),
+        };
+        let request = request(Pdu {
+            request_id: pdu.request_id.clone().try_into()?,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings: v1_to_v2_varbinds(pdu),
+        });
+        let principal = Principal {
+            security_model: SecurityModel::V1,
+            security_name: &msg.community,
+            security_level: SecurityLevel::NoAuthNoPriv,
+            context_name: &[],
+        };
+        let result = self.execute(&request, &principal).await?;
+
+        let result = result.and_then(|varbinds| {
+            varbinds
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| match v2_to_v1_value(x.value) {
+                    Ok(value) => Ok(snmp_v1::VarBind {
+                        name: x.name,
+                        value,
+                    }),
+                    // exceptions are reported as noSuchName by SNMPv1 (RFC 3584 section 4.2.1)
+                    Err(_) => Err((ErrorStatus::NoSuchName, i as u32 + 1)),
+                })
+                .collect()
+        });
+        let (error_status, error_index, variable_bindings) = match result {
+            Ok(varbinds) => (0, 0, varbinds),
+            Err((status, index)) => (status.to_v1().code(), index, pdu.variable_bindings.clone()),
+        };
+        let encode = |pdu| {
+            rasn::ber::encode(&snmp_v1::Message {
+                version: msg.version.clone(),
+                community: msg.community.clone(),
+                data: snmp_v1::Pdus::GetResponse(snmp_v1::GetResponse(pdu)),
+            })
+            .map_err(crate::Error::AsnEncode)
+        };
+        let reply = encode(snmp_v1::Pdu {
+            request_id: pdu.request_id.clone(),
+            error_status: error_status.into(),
+            error_index: error_index.into(),
+            variable_bindings,
+        })?;
+        if reply.len() <= MAX_DATAGRAM_SIZE {
+            return Ok(Some(reply));
+        }
+        encode(snmp_v1::Pdu {
+            request_id: pdu.request_id.clone(),
+            error_status: ErrorStatus::TooBig.code().into(),
+            error_index: 0.into(),
+            variable_bindings: vec![],
+        })
+        .map(Some)
+    }
+
+    async fn process_v2c(&self, data: &[u8]) -> Result<Option<Vec<u8>>, crate::Error> {
+        let msg: v2c::Message<Pdus> = rasn::ber::decode(data).map_err(crate::Error::AsnDecode)?;
+        self.check_community(&msg.community)?;
+        let principal = Principal {
+            security_model: SecurityModel::V2c,
+            security_name: &msg.community,
+            security_level: SecurityLevel::NoAuthNoPriv,
+            context_name: &[],
+        };
+        let response = self.respond(&msg.data, &principal).await?;
+        let encode = |pdu| {
+            rasn::ber::encode(&v2c::Message {
+                version: msg.version.clone(),
+                community: msg.community.clone(),
+                data: Pdus::Response(Response(pdu)),
+            })
+            .map_err(crate::Error::AsnEncode)
+        };
+        encode_checked(response, MAX_DATAGRAM_SIZE, encode).map(Some)
+    }
+
+    async fn process_v3(&self, data: &[u8]) -> Result<Option<Vec<u8>>, crate::Error> {
+        let msg: v3::Message = rasn::ber::decode(data).map_err(crate::Error::AsnDecode)?;
+        let (user, scoped_pdu) = match process_authoritative(
+            data,
+            msg.clone(),
+            &self.engine,
+            &self.users,
+            &self.stats,
+        ) {
+            Ok(x) => x,
+            Err(dropped) => return Ok(dropped.report),
+        };
+        let principal = Principal {
+            security_model: SecurityModel::Usm,
+            security_name: &user.username,
+            security_level: MessageFlags::try_from(&msg.global_data.flags)?.into(),
+            context_name: &scoped_pdu.name,
+        };
+        let response = match self.respond(&scoped_pdu.data, &principal).await {
+            Err(crate::Error::UnknownContext) => {
+                return self.report_unknown_context(&msg, &user, &scoped_pdu);
+            }
+            x => x?,
+        };
+        let encode = |pdu| {
+            let scoped_pdu = ScopedPdu {
+                engine_id: scoped_pdu.engine_id.clone(),
+                name: scoped_pdu.name.clone(),
+                data: Pdus::Response(Response(pdu)),
+            };
+            encode_response(&self.engine, &msg, &user, scoped_pdu)
+        };
+        // responses must also fit within the largest message the requester accepts (RFC 3412 section 7.1 step 3)
+        let max_size = u32::try_from(msg.global_data.max_size.clone())
+            .map_or(MAX_DATAGRAM_SIZE, |x| (x as usize).min(MAX_DATAGRAM_SIZE));
+        encode_checked(response, max_size, encode).map(Some)
+    }
+
+    // RFC 3413 section 3.2 step 3: requests naming an unknown context are counted and answered with a Report, sent at
+    // the request's security level.
+    fn report_unknown_context(
+        &self,
+        msg: &v3::Message,
+        user: &UsmUser,
+        request: &ScopedPdu,
+    ) -> Result<Option<Vec<u8>>, crate::Error> {
+        let count = self.unknown_contexts.fetch_add(1, Ordering::Relaxed) + 1;
+        if !MessageFlags::try_from(&msg.global_data.flags)?.reportable {
+            return Ok(None);
+        }
+        let report = ScopedPdu {
+            engine_id: self.engine.engine_id().clone(),
+            name: request.name.clone(),
+            data: Pdus::Report(Report(Pdu {
+                request_id: request_id(&request.data),
+                error_status: Pdu::ERROR_STATUS_NO_ERROR,
+                error_index: 0,
+                variable_bindings: vec![VarBind {
+                    name: ObjectIdentifier::new_unchecked(UNKNOWN_CONTEXTS.to_vec().into()),
+                    value: VarBindValue::Value(ObjectSyntax::ApplicationWide(
+                        ApplicationSyntax::Counter(Counter(count)),
+                    )),
+                }],
+            })),
+        };
+        encode_response(&self.engine, msg, user, report).map(Some)
+    }
+
+    /// Answers a request PDU, returning the Response PDU.
+    async fn respond(
+        &self,
+        request: &Pdus,
+        principal: &Principal<'_>,
+    ) -> Result<Pdu, crate::Error> {
+        let result = self.execute(request, principal).await?;
+        let request = match request {
+            Pdus::GetRequest(x) => &x.0,
+            Pdus::GetNextRequest(x) => &x.0,
+            Pdus::SetRequest(x) => &x.0,
+            Pdus::GetBulkRequest(x) => {
+                return Ok(response_pdu(x.0.request_id, &x.0.variable_bindings, result))
+            }
+            _ => return Err(crate::Error::UnexpectedPdu),
+        };
+        Ok(response_pdu(
+            request.request_id,
+            &request.variable_bindings,
+            result,
+        ))
+    }
+
+    // Processes a request's variable bindings on behalf of the given principal.
+    async fn execute(
+        &self,
+        request: &Pdus,
+        principal: &Principal<'_>,
+    ) -> Result<VarBindsResult, crate::Error> {
+        let view_type = match request {
+            Pdus::SetRequest(_) => ViewType::Write,
+            _ => ViewType::Read,
+        };
+        let scope = match self.scope(principal, view_type) {
+            Ok(x) => x,
+            Err(AccessDenied::NoSuchContext) => return Err(crate::Error::UnknownContext),
+            Err(_) => return Ok(Err((ErrorStatus::AuthorizationError, 0))),
+        };
+        Ok(match request {
+            Pdus::GetRequest(x) => self.get(&x.0.variable_bindings, &scope).await,
+            Pdus::GetNextRequest(x) => self.get_next(&x.0.variable_bindings, &scope).await,
+            Pdus::GetBulkRequest(x) => self.get_bulk(&x.0, &scope).await,
+            Pdus::SetRequest(x) => self.set(&x.0.variable_bindings, &scope).await,
+            _ => return Err(crate::Error::UnexpectedPdu),
+        })
+    }
+
+    // Selects the view a principal may use, or why it may not access any (RFC 3413 section 3.2, step 3).
+    fn scope(
+        &self,
+        principal: &Principal<'_>,
+        view_type: ViewType,
+    ) -> Result<Scope<'_>, AccessDenied> {
+        let view = match &self.vacm {
+            Some(vacm) => Some(vacm.view(
+                principal.security_model,
+                principal.security_name,
+                principal.security_level,
+                view_type,
+                principal.context_name,
+            )?),
+            None if !principal.context_name.is_empty() => return Err(AccessDenied::NoSuchContext),
+            None => None,
+        };
+        Ok(Scope {
+            view,
+            v1: principal.security_model == SecurityModel::V1,
+        })
+    }
+
+    // Finds the first instance following the given OID, skipping instances outside the request's view and Counter64
+    // values for SNMPv1.
+    async fn next(&self, oid: &[u32], scope: &Scope<'_>) -> Result<Option<VarBind>, ErrorStatus> {
+        let skip = |name: &[u32], value: &ObjectSyntax| {
+            let big_counter = matches!(
+                value,
+                ObjectSyntax::ApplicationWide(ApplicationSyntax::BigCounter(_))
+            );
+            (scope.v1 && big_counter) || !scope.contains(name)
+        };
+        Ok(self
+            .handlers
+            .next(oid, &skip)
+            .await?
+            .map(|(name, value)| VarBind {
+                name: ObjectIdentifier::new_unchecked(name.into()),
+                value: VarBindValue::Value(value),
+            }))
+    }
+
+    async fn get(&self, varbinds: &[VarBind], scope: &Scope<'_>) -> VarBindsResult {
+        let mut ret = Vec::with_capacity(varbinds.len());
+        for (i, varbind) in varbinds.iter().enumerate() {
+            let value = match self.handlers.find(&varbind.name) {
+                Some((len, handler)) if scope.contains(&varbind.name) => {
+                    handler.get(&varbind.name[len..]).await
+                }
+                _ => Ok(VarBindValue::NoSuchObject),
+            };
+            let value = match value {
+                Ok(VarBindValue::Value(ObjectSyntax::ApplicationWide(
+                    ApplicationSyntax::BigCounter(_),
+                ))) if scope.v1 => Err(ErrorStatus::NoSuchName),
+                x => x,
+            };
+            ret.push(VarBind {
+                name: varbind.name.clone(),
+                value: value.map_err(|e| (e, i as u32 + 1))?,
+            });
+        }
+        Ok(ret)
+    }
+
+    async fn get_next(&self, varbinds: &[VarBind], scope: &Scope<'_>) -> VarBindsResult {
+        let mut ret = Vec::with_capacity(varbinds.len());
+        for (i, varbind) in varbinds.iter().enumerate() {
+            let next = self
+                .next(&varbind.name, scope)
+                .await
+                .map_err(|e| (e, i as u32 + 1))?;
+            ret.push(next.unwrap_or(VarBind {
+                name: varbind.name.clone(),
+                value: VarBindValue::EndOfMibView,
+            }));
+        }
+        Ok(ret)
+    }
+
+    // RFC 3416 section 4.2.3: the first non-repeaters variable bindings are treated as in a GetNextRequest, and the
+    // rest are walked for up to max-repetitions steps.
+    async fn get_bulk(&self, request: &BulkPdu, scope: &Scope<'_>) -> VarBindsResult {
+        let varbinds = &request.variable_bindings;
+        let non_repeaters = (request.non_repeaters as usize).min(varbinds.len());
+        let mut ret = self.get_next(&varbinds[..non_repeaters], scope).await?;
+
+        let mut names = varbinds[non_repeaters..]
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>();
+        for _ in 0..request.max_repetitions {
+            if names.is_empty() || ret.len() + names.len() > MAX_BULK_VARBINDS {
+                break;
+            }
+            let mut end_of_mib_view = true;
+            for (i, name) in names.iter_mut().enumerate() {
+                let next = self
+                    .next(name, scope)
+                    .await
+                    .map_err(|e| (e, (non_repeaters + i) as u32 + 1))?;
+                let varbind = match next {
+                    Some(x) => {
+                        end_of_mib_view = false;
+                        x
+                    }
+                    None => VarBind {
+                        name: name.clone(),
+                        value: VarBindValue::EndOfMibView,
+                    },
+                };
+                *name = varbind.name.clone();
+                ret.push(varbind);
+            }
+            if end_of_mib_view {
+                break;
+            }
+        }
+        Ok(ret)
+    }
+
+    // RFC 3416 section 4.2.5: every variable binding is tested before any is committed, and committed bindings are
+    // undone if a later one fails, so that a SetRequest is applied either entirely or not at all.
+    async fn set(&self, varbinds: &[VarBind], scope: &Scope<'_>) -> VarBindsResult {
+        let request = SetId::new_request();
+        let mut writes = Vec::with_capacity(varbinds.len());
+        let mut result = Ok(());
+        for (i, varbind) in varbinds.iter().enumerate() {
+            let error_index = i as u32 + 1;
+            let value = match &varbind.value {
+                VarBindValue::Value(x) => x,
+                _ => {
+                    result = Err((ErrorStatus::WrongType, error_index));
+                    break;
+                }
+            };
+            if !scope.contains(&varbind.name) {
+                result = Err((ErrorStatus::NoAccess, error_index));
+                break;
+            }
+            let (len, handler) = match self.handlers.find(&varbind.name) {
+                Some(x) => x,
+                None => {
+                    result = Err((ErrorStatus::NoCreation, error_index));
+                    break;
+                }
+            };
+            let id = request.varbind(i);
+            let oid = &varbind.name[len..];
+            if let Err(e) = handler.test(id, oid, value).await {
+                result = Err((e, error_index));
+                break;
+            }
+            writes.push((id, handler, oid, value));
+        }
+
+        if result.is_ok() {
+            for (i, (id, handler, oid, value)) in writes.iter().enumerate() {
+                if handler.commit(*id, oid, (*value).clone()).await.is_ok() {
+                    continue;
+                }
+                result = Err((ErrorStatus::CommitFailed, i as u32 + 1));
+                for (id, handler, oid, _) in writes[..i].iter().rev() {
+                    if handler.undo(*id, oid).await.is_err() {
+                        result = Err((ErrorStatus::UndoFailed, 0));
+                    }
+                }
+                break;
+            }
+        }
+
+        for (id, handler, oid, _) in &writes {
+            handler.cleanup(*id, oid).await;
+        }
+        result.map(|()| varbinds.to_vec())
+    }
+}
+
+// Whether two values have the same syntax, e.g. both are OCTET STRINGs or both are Gauge32s.
+fn same_syntax(a: &ObjectSyntax, b: &ObjectSyntax) -> bool {
+    match (a, b) {
+        (ObjectSyntax::Simple(a), ObjectSyntax::Simple(b)) => discriminant(a) == discriminant(b),
+        (ObjectSyntax::ApplicationWide(a), ObjectSyntax::ApplicationWide(b)) => {
+            discriminant(a) == discriminant(b)
+        }
+        _ => false,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn response_pdu(request_id: i32, request: &[VarBind], result: VarBindsResult) -> Pdu {
+    match result {
+        Ok(variable_bindings) => Pdu {
+            request_id,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings,
+        },
+        Err((status, error_index)) => Pdu {
+            request_id,
+            error_status: status.code(),
+            error_index,
+            variable_bindings: request.to_vec(),
+        },
+    }
+}
+
+fn v1_to_v2_varbinds(pdu: &snmp_v1::Pdu) -> VarBindList {
+    pdu.variable_bindings
+        .iter()
+        .map(|x| VarBind {
+            name: x.name.clone(),
+            value: v1_to_v2_value(x.value.clone()),
+        })
+        .collect()
+}
+
+// Encodes a response, replacing it with a tooBig response without variable bindings if it is larger than `max_size`
+// (RFC 3416 section 4.2.1). The request is dropped if even that is too large.
+fn encode_checked(
+    pdu: Pdu,
+    max_size: usize,
+    encode: impl Fn(Pdu) -> Result<Vec<u8>, crate::Error>,
+) -> Result<Vec<u8>, crate::Error> {
+    let request_id = pdu.request_id;
+    let reply = encode(pdu)?;
+    if reply.len() <= max_size {
+        return Ok(reply);
+    }
+    let reply = encode(Pdu {
+        request_id,
+        error_status: Pdu::ERROR_STATUS_TOO_BIG,
+        error_index: 0,
+        variable_bindings: vec![],
+    })?;
+    if reply.len() <= max_size {
+        Ok(reply)
+    } else {
+        Err(crate::Error::ResponseTooBig)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use rasn_smi::{
+        v1::Gauge,
+        v2::{Counter64, SimpleSyntax},
+    };
+    use rasn_snmp::{
+        v2::GetBulkRequest,
+        v3::{HeaderData, ScopedPduData, USMSecurityParameters},
+    };
+
+    use crate::{
+        rfc3414::{encode_outgoing, process_incoming, AuthProtocol, Credential, PrivProtocol},
+        rfc3415::{AccessEntry, ContextMatch},
+    };
+
+    const SYS_DESCR: [u32; 8] = [1, 3, 6, 1, 2, 1, 1, 1];
+    const SYS_CONTACT: [u32; 8] = [1, 3, 6, 1, 2, 1, 1, 4];
+    const BIG_COUNTER: [u32; 7] = [1, 3, 6, 1, 4, 1, 99];
+
+    struct BigCounter;
+
+    #[async_trait::async_trait]
+    impl Handler for BigCounter {
+        async fn get(&self, oid: &[u32]) -> Result<VarBindValue, ErrorStatus> {
+            match oid {
+                [0] => Ok(VarBindValue::Value(self.value())),
+                _ => Ok(VarBindValue::NoSuchInstance),
+            }
+        }
+
+        async fn get_next(
+            &self,
+            oid: &[u32],
+        ) -> Result<Option<(Vec<u32>, ObjectSyntax)>, ErrorStatus> {
+            Ok((oid < &[0][..]).then(|| (vec![0], self.value())))
+        }
+    }
+
+    impl BigCounter {
+        fn value(&self) -> ObjectSyntax {
+            ObjectSyntax::ApplicationWide(ApplicationSyntax::BigCounter(Counter64(1 << 40)))
+        }
+    }
+
+    fn oid(x: &[u32]) -> ObjectIdentifier {
+        ObjectIdentifier::new_unchecked(x.to_vec().into())
+    }
+
+    fn instance(x: &[u32]) -> ObjectIdentifier {
+        oid(&[x, &[0][..]].concat())
+    }
+
+    fn string(x: &str) -> VarBindValue {
+        VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::String(x.into())))
+    }
+
+    fn user() -> UsmUser {
+        UsmUser {
+            username: "manager".into(),
+            auth_protocol: AuthProtocol::Sha1,
+            auth_key: Credential::Password("authpassword".into()),
+            priv_protocol: PrivProtocol::Aes,
+            priv_key: Credential::Password("privpassword".into()),
+        }
+    }
+
+    async fn agent() -> (Agent, Arc<Mutex<OctetString>>) {
+        let contact = Arc::new(Mutex::new(OctetString::from("nobody")));
+        let (get_contact, set_contact) = (contact.clone(), contact.clone());
+        let agent = Agent::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_community("public")
+            .with_user(user())
+            .unwrap()
+            .with_handler(oid(&SYS_DESCR), Scalar::new(|| OctetString::from("snipe")))
+            .unwrap()
+            .with_handler(
+                oid(&SYS_CONTACT),
+                Scalar::new(move || get_contact.lock().unwrap().clone()).with_set(move |x| {
+                    *set_contact.lock().unwrap() = x;
+                    Ok(())
+                }),
+            )
+            .unwrap()
+            .with_handler(oid(&BIG_COUNTER), BigCounter)
+            .unwrap();
+        (agent, contact)
+    }
+
+    fn request(request_id: i32, names: &[ObjectIdentifier]) -> Pdu {
+        Pdu {
+            request_id,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings: names
+                .iter()
+                .map(|x| VarBind {
+                    name: x.clone(),
+                    value: VarBindValue::Unspecified,
+                })
+                .collect(),
+        }
+    }
+
+    async fn v2c(agent: &Agent, pdus: Pdus) -> Pdu {
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "public".into(),
+            data: pdus,
+        })
+        .unwrap();
+        let reply: v2c::Message<Pdus> =
+            rasn::ber::decode(&agent.process(&data).await.unwrap()).unwrap();
+        match reply.data {
+            Pdus::Response(x) => x.0,
+            _ => panic!("expected a response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn get() {
+        let (agent, _) = agent().await;
+        let names = [
+            instance(&SYS_DESCR),
+            oid(&[1, 3, 6, 1, 2, 1, 1, 1, 1]),
+            instance(&[1, 3, 6, 1, 2, 1, 1, 2]),
+        ];
+        let response = v2c(&agent, Pdus::GetRequest(GetRequest(request(1, &names)))).await;
+        assert_eq!(response.request_id, 1);
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_NO_ERROR);
+        let values = response
+            .variable_bindings
+            .into_iter()
+            .map(|x| x.value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                string("snipe"),
+                VarBindValue::NoSuchInstance,
+                VarBindValue::NoSuchObject
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_next() {
+        let (agent, _) = agent().await;
+        let names = [
+            oid(&[1, 3, 6, 1, 2, 1]),
+            instance(&SYS_DESCR),
+            instance(&BIG_COUNTER),
+        ];
+        let response = v2c(
+            &agent,
+            Pdus::GetNextRequest(GetNextRequest(request(2, &names))),
+        )
+        .await;
+        let varbinds = response.variable_bindings;
+        assert_eq!(varbinds[0].name, instance(&SYS_DESCR));
+        assert_eq!(varbinds[1].name, instance(&SYS_CONTACT));
+        assert_eq!(varbinds[1].value, string("nobody"));
+        assert_eq!(varbinds[2].name, instance(&BIG_COUNTER));
+        assert_eq!(varbinds[2].value, VarBindValue::EndOfMibView);
+    }
+
+    #[tokio::test]
+    async fn get_bulk() {
+        let (agent, _) = agent().await;
+        let response = v2c(
+            &agent,
+            Pdus::GetBulkRequest(GetBulkRequest(BulkPdu {
+                request_id: 3,
+                non_repeaters: 1,
+                max_repetitions: 5,
+                variable_bindings: request(3, &[oid(&[1, 3, 6, 1, 2, 1, 1, 2]), oid(&[1, 3])])
+                    .variable_bindings,
+            })),
+        )
+        .await;
+        let names = response
+            .variable_bindings
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                instance(&SYS_CONTACT),
+                instance(&SYS_DESCR),
+                instance(&SYS_CONTACT),
+                instance(&BIG_COUNTER),
+                instance(&BIG_COUNTER),
+            ]
+        );
+        assert_eq!(
+            response.variable_bindings[4].value,
+            VarBindValue::EndOfMibView
+        );
+    }
+
+    #[tokio::test]
+    async fn set() {
+        let (agent, contact) = agent().await;
+        let mut pdu = request(4, &[instance(&SYS_CONTACT)]);
+        pdu.variable_bindings[0].value = string("admin");
+        let response = v2c(&agent, Pdus::SetRequest(SetRequest(pdu))).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_NO_ERROR);
+        assert_eq!(*contact.lock().unwrap(), OctetString::from("admin"));
+
+        let mut pdu = request(5, &[instance(&SYS_CONTACT), instance(&SYS_DESCR)]);
+        pdu.variable_bindings[1].value = string("agent");
+        pdu.variable_bindings[0].value = string("root");
+        let response = v2c(&agent, Pdus::SetRequest(SetRequest(pdu))).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_NOT_WRITABLE);
+        assert_eq!(response.error_index, 2);
+        // nothing is committed unless every variable binding can be set
+        assert_eq!(*contact.lock().unwrap(), OctetString::from("admin"));
+    }
+
+    #[tokio::test]
+    async fn set_is_undone() {
+        const LOCATION: [u32; 8] = [1, 3, 6, 1, 2, 1, 1, 6];
+        let (agent, contact) = agent().await;
+        let agent = agent
+            .with_handler(
+                oid(&LOCATION),
+                Scalar::new(|| OctetString::from("here"))
+                    .with_set(|_: OctetString| Err(ErrorStatus::ResourceUnavailable)),
+            )
+            .unwrap();
+
+        let mut pdu = request(6, &[instance(&SYS_CONTACT), instance(&LOCATION)]);
+        pdu.variable_bindings[0].value = string("root");
+        pdu.variable_bindings[1].value = string("there");
+        let response = v2c(&agent, Pdus::SetRequest(SetRequest(pdu))).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_COMMIT_FAILED);
+        assert_eq!(response.error_index, 2);
+        assert_eq!(*contact.lock().unwrap(), OctetString::from("nobody"));
+    }
+
+    #[tokio::test]
+    async fn set_twice_is_undone() {
+        const LOCATION: [u32; 8] = [1, 3, 6, 1, 2, 1, 1, 6];
+        let (agent, contact) = agent().await;
+        let agent = agent
+            .with_handler(
+                oid(&LOCATION),
+                Scalar::new(|| OctetString::from("here"))
+                    .with_set(|_: OctetString| Err(ErrorStatus::ResourceUnavailable)),
+            )
+            .unwrap();
+
+        let names = [
+            instance(&SYS_CONTACT),
+            instance(&SYS_CONTACT),
+            instance(&LOCATION),
+        ];
+        let mut pdu = request(7, &names);
+        pdu.variable_bindings[0].value = string("root");
+        pdu.variable_bindings[1].value = string("admin");
+        pdu.variable_bindings[2].value = string("there");
+        let response = v2c(&agent, Pdus::SetRequest(SetRequest(pdu))).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_COMMIT_FAILED);
+        assert_eq!(response.error_index, 3);
+        assert_eq!(*contact.lock().unwrap(), OctetString::from("nobody"));
+    }
+
+    #[tokio::test]
+    async fn access_control() {
+        let (agent, _) = agent().await;
+        let agent = agent.with_vacm(
+            Vacm::new()
+                .with_group(SecurityModel::V2c, "public", "readers")
+                .with_access(AccessEntry {
+                    group_name: "readers".into(),
+                    context_prefix: OctetString::default(),
+                    context_match: ContextMatch::Exact,
+                    security_model: SecurityModel::Any,
+                    security_level: SecurityLevel::NoAuthNoPriv,
+                    read_view: "system".into(),
+                    write_view: OctetString::default(),
+                    notify_view: OctetString::default(),
+                })
+                .with_view("system", oid(&[1, 3, 6, 1, 2, 1, 1]), vec![], true)
+                .with_view("system", oid(&SYS_CONTACT), vec![], false),
+        );
+
+        let names = [instance(&SYS_DESCR), instance(&SYS_CONTACT)];
+        let response = v2c(&agent, Pdus::GetRequest(GetRequest(request(14, &names)))).await;
+        assert_eq!(response.variable_bindings[0].value, string("snipe"));
+        assert_eq!(
+            response.variable_bindings[1].value,
+            VarBindValue::NoSuchObject
+        );
+
+        // sysContact and the counter outside the view are skipped
+        let names = [instance(&SYS_DESCR)];
+        let response = v2c(
+            &agent,
+            Pdus::GetNextRequest(GetNextRequest(request(15, &names))),
+        )
+        .await;
+        assert_eq!(
+            response.variable_bindings[0].value,
+            VarBindValue::EndOfMibView
+        );
+
+        let mut pdu = request(16, &[instance(&SYS_CONTACT)]);
+        pdu.variable_bindings[0].value = string("root");
+        let response = v2c(&agent, Pdus::SetRequest(SetRequest(pdu))).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_AUTHORIZATION_ERROR);
+        assert_eq!(response.error_index, 0);
+    }
+
+    struct Entry {
+        name: &'static str,
+        speed: Option<u32>,
+    }
+
+    impl Row for Entry {
+        fn column(&self, column: u32) -> Result<Option<ObjectSyntax>, ErrorStatus> {
+            Ok(match column {
+                2 => Some(OctetString::from(self.name).into()),
+                3 => self.speed.map(|x| Gauge(x).into()),
+                _ => None,
+            })
+        }
+    }
+
+    fn table_rows() -> TableRows<(u32, String), Entry> {
+        let rows = TableRows::new();
+        // inserted out of order, as the table keeps them sorted
+        for (index, name, speed) in [
+            ((2, "a"), "c", Some(10)),
+            ((1, "b"), "b", None),
+            ((1, "a"), "a", Some(100)),
+        ] {
+            rows.insert((index.0, String::from(index.1)), Entry { name, speed })
+                .unwrap();
+        }
+        rows
+    }
+
+    #[tokio::test]
+    async fn table() {
+        const ENTRY: [u32; 6] = [1, 3, 6, 1, 4, 1];
+        let rows = table_rows();
+        let agent = Agent::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_community("public")
+            .with_handler(oid(&ENTRY), Table::new([3, 2], rows))
+            .unwrap();
+        let instance = |column: u32, index: &[u32]| oid(&[&ENTRY[..], &[column], index].concat());
+
+        let names = [
+            instance(2, &[1, 1, 98]),
+            instance(3, &[1, 1, 98]),
+            instance(2, &[1, 1]),
+        ];
+        let response = v2c(&agent, Pdus::GetRequest(GetRequest(request(11, &names)))).await;
+        let values = response
+            .variable_bindings
+            .into_iter()
+            .map(|x| x.value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                string("b"),
+                VarBindValue::NoSuchInstance,
+                VarBindValue::NoSuchInstance
+            ]
+        );
+
+        let response = v2c(
+            &agent,
+            Pdus::GetBulkRequest(GetBulkRequest(BulkPdu {
+                request_id: 12,
+                non_repeaters: 0,
+                max_repetitions: 10,
+                variable_bindings: request(12, &[oid(&ENTRY)]).variable_bindings,
+            })),
+        )
+        .await;
+        let names = response
+            .variable_bindings
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                instance(2, &[1, 1, 97]),
+                instance(2, &[1, 1, 98]),
+                instance(2, &[2, 1, 97]),
+                instance(3, &[1, 1, 97]),
+                instance(3, &[2, 1, 97]),
+                instance(3, &[2, 1, 97]),
+            ]
+        );
+        assert_eq!(response.variable_bindings[2].value, string("c"));
+        assert_eq!(
+            response.variable_bindings[5].value,
+            VarBindValue::EndOfMibView
+        );
+
+        let mut pdu = request(13, &[instance(2, &[1, 1, 97])]);
+        pdu.variable_bindings[0].value = string("d");
+        let response = v2c(&agent, Pdus::SetRequest(SetRequest(pdu))).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_NOT_WRITABLE);
+    }
+
+    async fn v1_get(agent: &Agent, request_id: i32, name: ObjectIdentifier) -> snmp_v1::Pdu {
+        let data = rasn::ber::encode(&snmp_v1::Message {
+            version: 0.into(),
+            community: OctetString::from("public"),
+            data: snmp_v1::Pdus::GetRequest(snmp_v1::GetRequest(snmp_v1::Pdu {
+                request_id: request_id.into(),
+                error_status: 0.into(),
+                error_index: 0.into(),
+                variable_bindings: vec![snmp_v1::VarBind {
+                    name,
+                    value: rasn_smi::v1::ObjectSyntax::Simple(rasn_smi::v1::SimpleSyntax::Empty),
+                }],
+            })),
+        })
+        .unwrap();
+        let reply: snmp_v1::Message<snmp_v1::Pdus> =
+            rasn::ber::decode(&agent.process(&data).await.unwrap()).unwrap();
+        match reply.data {
+            snmp_v1::Pdus::GetResponse(x) => x.0,
+            _ => panic!("expected a response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn table_set() {
+        const ENTRY: [u32; 6] = [1, 3, 6, 1, 4, 1];
+        let rows = table_rows();
+        let set_rows = rows.clone();
+        let agent = Agent::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_community("public")
+            .with_handler(
+                oid(&ENTRY),
+                Table::new([3, 2], rows.clone())
+                    .with_set(move |index: (u32, String), _, value| {
+                        if index.0 == 2 {
+                            return Err(ErrorStatus::ResourceUnavailable);
+                        }
+                        let speed = match value {
+                            ObjectSyntax::ApplicationWide(ApplicationSyntax::Unsigned(x)) => x.0,
+                            _ => return Err(ErrorStatus::WrongType),
+                        };
+                        set_rows.update(index, |x| x.speed = Some(speed)).unwrap();
+                        Ok(())
+                    })
+                    .with_test(|_, _, value| match value {
+                        ObjectSyntax::ApplicationWide(ApplicationSyntax::Unsigned(x))
+                            if x.0 > 1000 =>
+                        {
+                            Err(ErrorStatus::WrongValue)
+                        }
+                        _ => Ok(()),
+                    }),
+            )
+            .unwrap();
+        let instance = |column: u32, index: &[u32]| oid(&[&ENTRY[..], &[column], index].concat());
+        let speed = |index: (u32, &str)| {
+            rows.update((index.0, String::from(index.1)), |x| x.speed)
+                .unwrap()
+                .flatten()
+        };
+        let set = |request_id: i32, varbinds: Vec<(ObjectIdentifier, ObjectSyntax)>| {
+            let names = varbinds.iter().map(|x| x.0.clone()).collect::<Vec<_>>();
+            let mut pdu = request(request_id, &names);
+            for (varbind, (_, value)) in pdu.variable_bindings.iter_mut().zip(varbinds) {
+                varbind.value = VarBindValue::Value(value);
+            }
+            v2c(&agent, Pdus::SetRequest(SetRequest(pdu)))
+        };
+
+        let response = set(16, vec![(instance(3, &[1, 1, 97]), Gauge(5).into())]).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_NO_ERROR);
+        assert_eq!(speed((1, "a")), Some(5));
+
+        // values are checked before anything is set
+        let response = set(
+            17,
+            vec![(instance(3, &[1, 1, 97]), OctetString::from("fast").into())],
+        )
+        .await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_WRONG_TYPE);
+        let response = set(18, vec![(instance(3, &[1, 1, 97]), Gauge(5000).into())]).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_WRONG_VALUE);
+        let response = set(19, vec![(instance(3, &[1, 1, 98]), Gauge(5).into())]).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_NO_CREATION);
+        assert_eq!(speed((1, "a")), Some(5));
+
+        // the same instance set twice is undone back to its value before the request
+        let response = set(
+            20,
+            vec![
+                (instance(3, &[1, 1, 97]), Gauge(6).into()),
+                (instance(3, &[1, 1, 97]), Gauge(7).into()),
+                (instance(3, &[2, 1, 97]), Gauge(8).into()),
+            ],
+        )
+        .await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_COMMIT_FAILED);
+        assert_eq!(response.error_index, 3);
+        assert_eq!(speed((1, "a")), Some(5));
+        assert_eq!(speed((2, "a")), Some(10));
+    }
+
+    #[tokio::test]
+    async fn v1() {
+        let (agent, _) = agent().await;
+        let response = v1_get(&agent, 6, instance(&SYS_DESCR)).await;
+        assert_eq!(response.error_status, 0.into());
+        // Counter64 values can't be represented in SNMPv1
+        let response = v1_get(&agent, 7, instance(&BIG_COUNTER)).await;
+        assert_eq!(response.error_status, ErrorStatus::NoSuchName.code().into());
+        assert_eq!(response.error_index, 1.into());
+    }
+
+    #[tokio::test]
+    async fn unknown_community() {
+        let (agent, _) = agent().await;
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "private".into(),
+            data: Pdus::GetRequest(GetRequest(request(8, &[instance(&SYS_DESCR)]))),
+        })
+        .unwrap();
+        assert!(agent.process(&data).await.is_none());
+    }
+
+    async fn v3_request(agent: &Agent, max_size: u32, pdus: Pdus) -> Pdu {
+        match v3_exchange(agent, max_size, "", pdus).await {
+            Pdus::Response(x) => x.0,
+            _ => panic!("expected a response"),
+        }
+    }
+
+    // Sends a request in the given context with authPriv, returning the PDU the agent replied with.
+    async fn v3_exchange(agent: &Agent, max_size: u32, context: &str, pdus: Pdus) -> Pdus {
+        let engine = agent.engine();
+        let msg = v3::Message {
+            version: 3.into(),
+            global_data: HeaderData {
+                message_id: 9.into(),
+                max_size: max_size.into(),
+                flags: vec![0b111_u8].into(),
+                security_model: 3.into(),
+            },
+            security_parameters: USMSecurityParameters {
+                authoritative_engine_id: engine.engine_id().clone(),
+                authoritative_engine_boots: engine.boots().into(),
+                authoritative_engine_time: engine.time().into(),
+                user_name: OctetString::default(),
+                authentication_parameters: OctetString::default(),
+                privacy_parameters: OctetString::default(),
+            },
+            scoped_data: ScopedPduData::CleartextPdu(ScopedPdu {
+                engine_id: engine.engine_id().clone(),
+                name: context.into(),
+                data: pdus,
+            }),
+        };
+        let data = encode_outgoing(msg, &user(), 1).unwrap();
+
+        let reply = agent.process(&data).await.unwrap();
+        assert!(reply.len() <= max_size as usize);
+        let msg: v3::Message = rasn::ber::decode(&reply).unwrap();
+        assert_eq!(msg.global_data.message_id, 9.into());
+        process_incoming(&reply, msg, &user()).unwrap().data
+    }
+
+    #[tokio::test]
+    async fn v3_get() {
+        let (agent, _) = agent().await;
+        let pdus = Pdus::GetRequest(GetRequest(request(10, &[instance(&SYS_DESCR)])));
+        let response = v3_request(&agent, 65507, pdus).await;
+        assert_eq!(response.request_id, 10);
+        assert_eq!(response.variable_bindings[0].value, string("snipe"));
+    }
+
+    #[tokio::test]
+    async fn v3_unknown_context() {
+        let (agent, _) = agent().await;
+        let agent = agent.with_vacm(Vacm::new().with_context("lab"));
+        let pdus = Pdus::GetRequest(GetRequest(request(11, &[instance(&SYS_DESCR)])));
+        let report = match v3_exchange(&agent, 65507, "office", pdus).await {
+            Pdus::Report(x) => x.0,
+            _ => panic!("expected a report"),
+        };
+        assert_eq!(report.request_id, 11);
+        assert_eq!(
+            report.variable_bindings[0],
+            VarBind {
+                name: oid(&UNKNOWN_CONTEXTS),
+                value: VarBindValue::Value(ObjectSyntax::ApplicationWide(
+                    ApplicationSyntax::Counter(Counter(1)),
+                )),
+            }
+        );
+        assert_eq!(agent.unknown_contexts(), 1);
+    }
+
+    #[tokio::test]
+    async fn v3_unknown_context_without_vacm() {
+        // without access control, only the default context exists
+        let (agent, _) = agent().await;
+        let pdus = Pdus::GetRequest(GetRequest(request(12, &[instance(&SYS_DESCR)])));
+        assert!(matches!(
+            v3_exchange(&agent, 65507, "lab", pdus).await,
+            Pdus::Report(_)
+        ));
+        assert_eq!(agent.unknown_contexts(), 1);
+    }
+
+    #[tokio::test]
+    async fn v3_discovery() {
+        let (agent, _) = agent().await;
+        let probe = v3::Message {
+            version: 3.into(),
+            global_data: HeaderData {
+                message_id: 12.into(),
+                max_size: 65507.into(),
+                flags: vec![0b100_u8].into(),
+                security_model: 3.into(),
+            },
+            security_parameters: USMSecurityParameters {
+                authoritative_engine_id: OctetString::default(),
+                authoritative_engine_boots: 0.into(),
+                authoritative_engine_time: 0.into(),
+                user_name: OctetString::default(),
+                authentication_parameters: OctetString::default(),
+                privacy_parameters: OctetString::default(),
+            },
+            scoped_data: ScopedPduData::CleartextPdu(ScopedPdu {
+                engine_id: OctetString::default(),
+                name: OctetString::default(),
+                data: Pdus::GetRequest(GetRequest(request(13, &[]))),
+            }),
+        };
+
+        let reply = agent
+            .process(&rasn::ber::encode(&probe).unwrap())
+            .await
+            .unwrap();
+        let msg: v3::Message = rasn::ber::decode(&reply).unwrap();
+        assert_eq!(
+            &msg.security_parameters.authoritative_engine_id,
+            agent.engine().engine_id()
+        );
+        match msg.scoped_data {
+            ScopedPduData::CleartextPdu(ScopedPdu {
+                data: Pdus::Report(report),
+                ..
+            }) => assert_eq!(report.0.request_id, 13),
+            _ => panic!("expected a report"),
+        }
+    }
+
+    #[tokio::test]
+    async fn v3_max_size() {
+        let (agent, _) = agent().await;
+        // 484 octets is the smallest msgMaxSize allowed, and too small for this many variable bindings
+        let names = vec![instance(&SYS_DESCR); 40];
+        let pdus = Pdus::GetRequest(GetRequest(request(11, &names)));
+        let response = v3_request(&agent, 484, pdus).await;
+        assert_eq!(response.error_status, Pdu::ERROR_STATUS_TOO_BIG);
+        assert!(response.variable_bindings.is_empty());
+    }
+}
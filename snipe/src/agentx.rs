@@ -0,0 +1,1014 @@
+//! An AgentX subagent (RFC 2741), which serves [`Handler`]s through a master agent such as net-snmp's snmpd rather
+//! than answering SNMP requests itself.
+
+use std::time::Instant;
+
+use rasn::types::{Integer, ObjectIdentifier, OctetString};
+use rasn_smi::{
+    v1::{Counter, Gauge, IpAddress, Opaque, TimeTicks},
+    v2::{ApplicationSyntax, Counter64, ObjectSyntax, SimpleSyntax},
+};
+use rasn_snmp::v2::{VarBind, VarBindValue};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::agent::{ErrorStatus, Handler, Handlers, SetId};
+
+/// The path of the Unix socket net-snmp's master agent listens on by default.
+pub const DEFAULT_SOCKET_PATH: &str = "/var/agentx/master";
+
+/// The TCP port AgentX master agents listen on (RFC 2741 section 8.1.1).
+pub const DEFAULT_PORT: u16 = 705;
+
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 20;
+// The largest payload accepted from the master agent. Requests carry the variable bindings of a single SNMP message, so
+// this leaves plenty of room for AgentX's larger encoding of OIDs.
+const MAX_PAYLOAD_LEN: u32 = 1 << 20;
+
+// h.flags
+const NON_DEFAULT_CONTEXT: u8 = 0x08;
+const NETWORK_BYTE_ORDER: u8 = 0x10;
+
+// h.type
+const OPEN: u8 = 1;
+const CLOSE: u8 = 2;
+const REGISTER: u8 = 3;
+const GET: u8 = 5;
+const GET_NEXT: u8 = 6;
+const GET_BULK: u8 = 7;
+const TEST_SET: u8 = 8;
+const COMMIT_SET: u8 = 9;
+const UNDO_SET: u8 = 10;
+const CLEANUP_SET: u8 = 11;
+const RESPONSE: u8 = 18;
+
+// res.error values beyond the SNMP error-status values
+const UNSUPPORTED_CONTEXT: u16 = 262;
+const PARSE_ERROR: u16 = 266;
+const PROCESSING_ERROR: u16 = 268;
+
+// the value of c.reason when the subagent is shutting down
+const REASON_SHUTDOWN: u8 = 5;
+
+// the default registration priority (RFC 2741 section 6.2.3)
+const DEFAULT_PRIORITY: u8 = 127;
+
+// v.type
+const INTEGER: u16 = 2;
+const OCTET_STRING: u16 = 4;
+const NULL: u16 = 5;
+const OBJECT_IDENTIFIER: u16 = 6;
+const IP_ADDRESS: u16 = 64;
+const COUNTER32: u16 = 65;
+const GAUGE32: u16 = 66;
+const TIME_TICKS: u16 = 67;
+const OPAQUE: u16 = 68;
+const COUNTER64: u16 = 70;
+const NO_SUCH_OBJECT: u16 = 128;
+const NO_SUCH_INSTANCE: u16 = 129;
+const END_OF_MIB_VIEW: u16 = 130;
+
+/// The header common to all AgentX PDUs (RFC 2741 section 6.1).
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    pdu_type: u8,
+    flags: u8,
+    session_id: u32,
+    transaction_id: u32,
+    packet_id: u32,
+}
+
+/// Encodes PDU payloads in network byte order.
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, x: u8) -> &mut Self {
+        self.0.push(x);
+        self
+    }
+
+    fn u16(&mut self, x: u16) -> &mut Self {
+        self.0.extend_from_slice(&x.to_be_bytes());
+        self
+    }
+
+    fn u32(&mut self, x: u32) -> &mut Self {
+        self.0.extend_from_slice(&x.to_be_bytes());
+        self
+    }
+
+    // RFC 2741 section 5.1: OIDs within the internet subtree are shortened using the prefix field.
+    fn oid(&mut self, oid: &[u32], include: bool) -> Result<&mut Self, crate::Error> {
+        let (prefix, subids) = match oid {
+            [1, 3, 6, 1, prefix @ 1..=255, rest @ ..] => (*prefix as u8, rest),
+            _ => (0, oid),
+        };
+        let n_subid =
+            u8::try_from(subids.len()).map_err(|_| crate::Error::AgentXOidLength(subids.len()))?;
+        self.u8(n_subid).u8(prefix).u8(include.into()).u8(0);
+        for x in subids {
+            self.u32(*x);
+        }
+        Ok(self)
+    }
+
+    // RFC 2741 section 5.3: octet strings are padded to a multiple of 4 octets.
+    fn octet_string(&mut self, x: &[u8]) -> &mut Self {
+        self.u32(x.len() as u32);
+        self.0.extend_from_slice(x);
+        self.0.resize(self.0.len() + (4 - x.len() % 4) % 4, 0);
+        self
+    }
+
+    fn varbind(&mut self, varbind: &VarBind) -> Result<&mut Self, crate::Error> {
+        let (value_type, value) = match &varbind.value {
+            VarBindValue::Value(x) => (value_type(x), Some(x)),
+            VarBindValue::Unspecified => (NULL, None),
+            VarBindValue::NoSuchObject => (NO_SUCH_OBJECT, None),
+            VarBindValue::NoSuchInstance => (NO_SUCH_INSTANCE, None),
+            VarBindValue::EndOfMibView => (END_OF_MIB_VIEW, None),
+        };
+        self.u16(value_type).u16(0).oid(&varbind.name, false)?;
+        match value {
+            Some(ObjectSyntax::Simple(SimpleSyntax::Integer(x))) => {
+                let x: i32 = x.clone().try_into()?;
+                self.u32(x as u32)
+            }
+            Some(ObjectSyntax::Simple(SimpleSyntax::String(x))) => self.octet_string(x),
+            Some(ObjectSyntax::Simple(SimpleSyntax::ObjectId(x))) => self.oid(x, false)?,
+            Some(ObjectSyntax::ApplicationWide(x)) => match x {
+                ApplicationSyntax::Address(x) => self.octet_string(&x.0),
+                ApplicationSyntax::Counter(x) => self.u32(x.0),
+                ApplicationSyntax::Unsigned(x) => self.u32(x.0),
+                ApplicationSyntax::Ticks(x) => self.u32(x.0),
+                ApplicationSyntax::Arbitrary(x) => self.octet_string(&x.0),
+                ApplicationSyntax::BigCounter(x) => self.u32((x.0 >> 32) as u32).u32(x.0 as u32),
+            },
+            None => self,
+        };
+        Ok(self)
+    }
+
+    fn pdu(self, header: Header) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(HEADER_LEN + self.0.len());
+        ret.extend_from_slice(&[
+            VERSION,
+            header.pdu_type,
+            header.flags | NETWORK_BYTE_ORDER,
+            0,
+        ]);
+        ret.extend_from_slice(&header.session_id.to_be_bytes());
+        ret.extend_from_slice(&header.transaction_id.to_be_bytes());
+        ret.extend_from_slice(&header.packet_id.to_be_bytes());
+        ret.extend_from_slice(&(self.0.len() as u32).to_be_bytes());
+        ret.extend_from_slice(&self.0);
+        ret
+    }
+}
+
+fn value_type(value: &ObjectSyntax) -> u16 {
+    match value {
+        ObjectSyntax::Simple(SimpleSyntax::Integer(_)) => INTEGER,
+        ObjectSyntax::Simple(SimpleSyntax::String(_)) => OCTET_STRING,
+        ObjectSyntax::Simple(SimpleSyntax::ObjectId(_)) => OBJECT_IDENTIFIER,
+        ObjectSyntax::ApplicationWide(x) => match x {
+            ApplicationSyntax::Address(_) => IP_ADDRESS,
+            ApplicationSyntax::Counter(_) => COUNTER32,
+            ApplicationSyntax::Unsigned(_) => GAUGE32,
+            ApplicationSyntax::Ticks(_) => TIME_TICKS,
+            ApplicationSyntax::Arbitrary(_) => OPAQUE,
+            ApplicationSyntax::BigCounter(_) => COUNTER64,
+        },
+    }
+}
+
+/// Decodes PDU payloads in the byte order given by their header.
+struct Decoder<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8], flags: u8) -> Self {
+        Self {
+            data,
+            big_endian: flags & NETWORK_BYTE_ORDER != 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], crate::Error> {
+        if self.data.len() < N {
+            return Err(crate::Error::MalformedMessage);
+        }
+        let (ret, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(ret.try_into().unwrap_or([0; N]))
+    }
+
+    fn u16(&mut self) -> Result<u16, crate::Error> {
+        let x = self.take()?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(x)
+        } else {
+            u16::from_le_bytes(x)
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32, crate::Error> {
+        let x = self.take()?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(x)
+        } else {
+            u32::from_le_bytes(x)
+        })
+    }
+
+    // Returns the OID and its include field.
+    fn oid(&mut self) -> Result<(Vec<u32>, bool), crate::Error> {
+        let [n_subid, prefix, include, _] = self.take()?;
+        let mut ret = Vec::with_capacity(n_subid as usize + 5);
+        if prefix != 0 {
+            ret.extend_from_slice(&[1, 3, 6, 1, prefix.into()]);
+        }
+        for _ in 0..n_subid {
+            ret.push(self.u32()?);
+        }
+        Ok((ret, include != 0))
+    }
+
+    fn octet_string(&mut self) -> Result<&'a [u8], crate::Error> {
+        let len = self.u32()? as usize;
+        let padded = len
+            .checked_add((4 - len % 4) % 4)
+            .filter(|x| *x <= self.data.len())
+            .ok_or(crate::Error::MalformedMessage)?;
+        let (ret, rest) = self.data.split_at(padded);
+        self.data = rest;
+        Ok(&ret[..len])
+    }
+
+    fn varbind(&mut self) -> Result<VarBind, crate::Error> {
+        let value_type = self.u16()?;
+        self.u16()?;
+        let (name, _) = self.oid()?;
+        let value = match value_type {
+            INTEGER => SimpleSyntax::Integer(Integer::from(self.u32()? as i32)).into(),
+            OCTET_STRING => {
+                SimpleSyntax::String(OctetString::copy_from_slice(self.octet_string()?)).into()
+            }
+            OBJECT_IDENTIFIER => {
+                SimpleSyntax::ObjectId(ObjectIdentifier::new_unchecked(self.oid()?.0.into())).into()
+            }
+            IP_ADDRESS => ApplicationSyntax::Address(IpAddress(OctetString::copy_from_slice(
+                self.octet_string()?,
+            )))
+            .into(),
+            COUNTER32 => ApplicationSyntax::Counter(Counter(self.u32()?)).into(),
+            GAUGE32 => ApplicationSyntax::Unsigned(Gauge(self.u32()?)).into(),
+            TIME_TICKS => ApplicationSyntax::Ticks(TimeTicks(self.u32()?)).into(),
+            OPAQUE => ApplicationSyntax::Arbitrary(Opaque(OctetString::copy_from_slice(
+                self.octet_string()?,
+            )))
+            .into(),
+            COUNTER64 => {
+                let high = u64::from(self.u32()?);
+                let low = u64::from(self.u32()?);
+                ApplicationSyntax::BigCounter(Counter64(high << 32 | low)).into()
+            }
+            NULL => {
+                return Ok(VarBind {
+                    name: ObjectIdentifier::new_unchecked(name.into()),
+                    value: VarBindValue::Unspecified,
+                })
+            }
+            _ => return Err(crate::Error::MalformedMessage),
+        };
+        Ok(VarBind {
+            name: ObjectIdentifier::new_unchecked(name.into()),
+            value: VarBindValue::Value(value),
+        })
+    }
+
+    // Returns the start, include field and end of a SearchRange (RFC 2741 section 5.2). An empty end is unbounded.
+    fn search_range(&mut self) -> Result<(Vec<u32>, bool, Vec<u32>), crate::Error> {
+        let (start, include) = self.oid()?;
+        let (end, _) = self.oid()?;
+        Ok((start, include, end))
+    }
+}
+
+async fn read_pdu<S: AsyncRead + Unpin>(stream: &mut S) -> Result<(Header, Vec<u8>), crate::Error> {
+    let mut header = [0_u8; HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+    let mut decoder = Decoder::new(&header, header[2]);
+    let [version, pdu_type, flags, _] = decoder.take()?;
+    if version != VERSION {
+        return Err(crate::Error::UnsupportedVersion(version.into()));
+    }
+    let header_fields = Header {
+        pdu_type,
+        flags,
+        session_id: decoder.u32()?,
+        transaction_id: decoder.u32()?,
+        packet_id: decoder.u32()?,
+    };
+    // RFC 2741 section 6.1: payloads are a multiple of 4 octets long. The length is checked before allocating anything,
+    // as it comes from the peer.
+    let len = decoder.u32()?;
+    if len > MAX_PAYLOAD_LEN || len % 4 != 0 {
+        return Err(crate::Error::AgentXPayloadLength(MAX_PAYLOAD_LEN, len));
+    }
+    let mut payload = vec![0_u8; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok((header_fields, payload))
+}
+
+// A variable binding set by the current TestSet-PDU, with whether it has been committed.
+struct PendingSet {
+    id: SetId,
+    oid: Vec<u32>,
+    value: ObjectSyntax,
+    committed: bool,
+}
+
+/// An AgentX subagent session, serving the handlers registered on it through a master agent.
+///
+/// Only the default context is served. SET requests are processed in the phases driven by the master agent: every
+/// variable binding is tested before any is committed, and committed bindings are undone if the master agent asks.
+pub struct Subagent<S> {
+    stream: S,
+    session_id: u32,
+    packet_id: u32,
+    handlers: Handlers,
+    pending: Vec<PendingSet>,
+    started: Instant,
+}
+
+#[cfg(unix)]
+impl Subagent<tokio::net::UnixStream> {
+    /// Connects to a master agent listening on the given Unix socket (usually [`DEFAULT_SOCKET_PATH`]) and opens a
+    /// session.
+    pub async fn connect_unix(
+        path: impl AsRef<std::path::Path>,
+        id: impl Into<ObjectIdentifier>,
+        description: &str,
+    ) -> Result<Self, crate::Error> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        Self::open(stream, id, description).await
+    }
+}
+
+impl Subagent<TcpStream> {
+    /// Connects to a master agent listening on the given TCP address (usually on [`DEFAULT_PORT`]) and opens a
+    /// session.
+    pub async fn connect_tcp<A: ToSocketAddrs>(
+        addr: A,
+        id: impl Into<ObjectIdentifier>,
+        description: &str,
+    ) -> Result<Self, crate::Error> {
+        let stream = TcpStream::connect(addr).await?;
+        Self::open(stream, id, description).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Subagent<S> {
+    /// Opens a session with the master agent at the other end of the given stream, identifying the subagent with the
+    /// given OID (usually its sysObjectID) and description (RFC 2741 section 7.1.1).
+    pub async fn open(
+        stream: S,
+        id: impl Into<ObjectIdentifier>,
+        description: &str,
+    ) -> Result<Self, crate::Error> {
+        let mut ret = Self {
+            stream,
+            session_id: 0,
+            packet_id: 0,
+            handlers: Handlers::default(),
+            pending: vec![],
+            started: Instant::now(),
+        };
+        let mut payload = Encoder::default();
+        // 0 lets the master agent choose the timeout
+        payload
+            .u8(0)
+            .u8(0)
+            .u8(0)
+            .u8(0)
+            .oid(&id.into(), false)?
+            .octet_string(description.as_bytes());
+        let (header, _) = ret.request(OPEN, payload).await?;
+        ret.session_id = header.session_id;
+        Ok(ret)
+    }
+
+    /// The session ID assigned by the master agent.
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// Registers a handler for the subtree rooted at the given OID with the master agent (RFC 2741 section 7.1.4).
+    /// Subtrees of different handlers must not overlap, which is checked before anything is sent.
+    pub async fn register(
+        &mut self,
+        oid: impl Into<ObjectIdentifier>,
+        handler: impl Handler + 'static,
+    ) -> Result<(), crate::Error> {
+        let oid = oid.into().to_vec();
+        if self.handlers.overlaps(&oid) {
+            return Err(crate::Error::OverlappingHandler);
+        }
+        let mut payload = Encoder::default();
+        payload
+            .u8(0)
+            .u8(DEFAULT_PRIORITY)
+            .u8(0)
+            .u8(0)
+            .oid(&oid, false)?;
+        self.request(REGISTER, payload).await?;
+        self.handlers.insert(oid, Box::new(handler))
+    }
+
+    /// Answers requests from the master agent until it closes the session.
+    pub async fn run(&mut self) -> Result<(), crate::Error> {
+        loop {
+            let (header, payload) = read_pdu(&mut self.stream).await?;
+            if header.pdu_type == CLOSE {
+                return Ok(());
+            }
+            if header.pdu_type == RESPONSE {
+                continue;
+            }
+            let response = self.process(header, &payload).await;
+            self.stream.write_all(&response).await?;
+        }
+    }
+
+    /// Closes the session (RFC 2741 section 7.1.2).
+    pub async fn close(mut self) -> Result<(), crate::Error> {
+        let mut payload = Encoder::default();
+        payload.u8(REASON_SHUTDOWN).u8(0).u8(0).u8(0);
+        self.request(CLOSE, payload).await?;
+        Ok(())
+    }
+
+    // The sysUpTime of the subagent, as reported in responses.
+    fn uptime(&self) -> u32 {
+        (self.started.elapsed().as_millis() / 10) as u32
+    }
+
+    // Sends a PDU and waits for its response, returning the response's header and variable bindings. Requests from the
+    // master agent for subtrees already registered can arrive in the meantime, and are answered as they come.
+    async fn request(
+        &mut self,
+        pdu_type: u8,
+        payload: Encoder,
+    ) -> Result<(Header, Vec<VarBind>), crate::Error> {
+        self.packet_id = self.packet_id.wrapping_add(1);
+        let header = Header {
+            pdu_type,
+            flags: 0,
+            session_id: self.session_id,
+            transaction_id: 0,
+            packet_id: self.packet_id,
+        };
+        self.stream.write_all(&payload.pdu(header)).await?;
+
+        loop {
+            let (header, payload) = read_pdu(&mut self.stream).await?;
+            match header.pdu_type {
+                RESPONSE if header.packet_id == self.packet_id => {}
+                // a response to an earlier request that timed out on the master agent's side
+                RESPONSE => continue,
+                CLOSE => return Err(crate::Error::AgentXClosed),
+                _ => {
+                    let response = self.process(header, &payload).await;
+                    self.stream.write_all(&response).await?;
+                    continue;
+                }
+            }
+            let mut decoder = Decoder::new(&payload, header.flags);
+            decoder.u32()?;
+            let error = decoder.u16()?;
+            decoder.u16()?;
+            if error != 0 {
+                return Err(crate::Error::AgentX(error));
+            }
+            let mut varbinds = vec![];
+            while !decoder.is_empty() {
+                varbinds.push(decoder.varbind()?);
+            }
+            return Ok((header, varbinds));
+        }
+    }
+
+    // Answers a request from the master agent, returning the encoded Response-PDU.
+    async fn process(&mut self, header: Header, payload: &[u8]) -> Vec<u8> {
+        let result = if header.flags & NON_DEFAULT_CONTEXT != 0 {
+            Err((UNSUPPORTED_CONTEXT, 0))
+        } else {
+            let mut decoder = Decoder::new(payload, header.flags);
+            match header.pdu_type {
+                GET => self.get(&mut decoder).await,
+                GET_NEXT => self.get_next(&mut decoder).await,
+                GET_BULK => self.get_bulk(&mut decoder).await,
+                TEST_SET => self.test_set(&mut decoder).await,
+                COMMIT_SET => self.commit_set().await,
+                UNDO_SET => self.undo_set().await,
+                CLEANUP_SET => self.cleanup_set().await,
+                _ => Err((PROCESSING_ERROR, 0)),
+            }
+        };
+
+        let (error, index, varbinds) = match result {
+            Ok(varbinds) => (0, 0, varbinds),
+            Err((error, index)) => (error, index, vec![]),
+        };
+        let mut response = Encoder::default();
+        response.u32(self.uptime()).u16(error).u16(index);
+        for varbind in &varbinds {
+            if response.varbind(varbind).is_err() {
+                return self.error_response(header, PROCESSING_ERROR);
+            }
+        }
+        response.pdu(Header {
+            pdu_type: RESPONSE,
+            flags: 0,
+            ..header
+        })
+    }
+
+    fn error_response(&self, header: Header, error: u16) -> Vec<u8> {
+        let mut response = Encoder::default();
+        response.u32(self.uptime()).u16(error).u16(0);
+        response.pdu(Header {
+            pdu_type: RESPONSE,
+            flags: 0,
+            ..header
+        })
+    }
+
+    async fn get(&self, decoder: &mut Decoder<'_>) -> Result<Vec<VarBind>, (u16, u16)> {
+        let mut ret = vec![];
+        while !decoder.is_empty() {
+            let (oid, _, _) = decoder.search_range().map_err(|_| (PARSE_ERROR, 0))?;
+            let value = match self.handlers.find(&oid) {
+                Some((len, handler)) => handler
+                    .get(&oid[len..])
+                    .await
+                    .map_err(|e| (e.code() as u16, ret.len() as u16 + 1))?,
+                None => VarBindValue::NoSuchObject,
+            };
+            ret.push(VarBind {
+                name: ObjectIdentifier::new_unchecked(oid.into()),
+                value,
+            });
+        }
+        Ok(ret)
+    }
+
+    // Finds the first instance within a search range, as described in RFC 2741 section 7.2.3.2.
+    async fn next(
+        &self,
+        (start, include, end): &(Vec<u32>, bool, Vec<u32>),
+    ) -> Result<VarBind, ErrorStatus> {
+        if *include {
+            if let Some((len, handler)) = self.handlers.find(start) {
+                if let VarBindValue::Value(value) = handler.get(&start[len..]).await? {
+                    return Ok(VarBind {
+                        name: ObjectIdentifier::new_unchecked(start.clone().into()),
+                        value: VarBindValue::Value(value),
+                    });
+                }
+            }
+        }
+        match self.handlers.next(start, &|_, _| false).await? {
+            Some((name, value)) if end.is_empty() || name < *end => Ok(VarBind {
+                name: ObjectIdentifier::new_unchecked(name.into()),
+                value: VarBindValue::Value(value),
+            }),
+            _ => Ok(VarBind {
+                name: ObjectIdentifier::new_unchecked(start.clone().into()),
+                value: VarBindValue::EndOfMibView,
+            }),
+        }
+    }
+
+    async fn get_next(&self, decoder: &mut Decoder<'_>) -> Result<Vec<VarBind>, (u16, u16)> {
+        let mut ret = vec![];
+        while !decoder.is_empty() {
+            let range = decoder.search_range().map_err(|_| (PARSE_ERROR, 0))?;
+            let varbind = self
+                .next(&range)
+                .await
+                .map_err(|e| (e.code() as u16, ret.len() as u16 + 1))?;
+            ret.push(varbind);
+        }
+        Ok(ret)
+    }
+
+    // RFC 2741 section 7.2.3.3: as with SNMP, the first non_repeaters ranges are searched once, and the rest are
+    // walked for up to max_repetitions steps.
+    async fn get_bulk(&self, decoder: &mut Decoder<'_>) -> Result<Vec<VarBind>, (u16, u16)> {
+        let parse_error = |_| (PARSE_ERROR, 0);
+        let non_repeaters = decoder.u16().map_err(parse_error)? as usize;
+        let max_repetitions = decoder.u16().map_err(parse_error)?;
+        let mut ranges = vec![];
+        while !decoder.is_empty() {
+            ranges.push(decoder.search_range().map_err(parse_error)?);
+        }
+        let non_repeaters = non_repeaters.min(ranges.len());
+
+        let mut ret = vec![];
+        for (i, range) in ranges[..non_repeaters].iter().enumerate() {
+            let varbind = self
+                .next(range)
+                .await
+                .map_err(|e| (e.code() as u16, i as u16 + 1))?;
+            ret.push(varbind);
+        }
+        let mut ranges = ranges.split_off(non_repeaters);
+        for _ in 0..max_repetitions {
+            let mut end_of_mib_view = true;
+            for (i, range) in ranges.iter_mut().enumerate() {
+                let varbind = self
+                    .next(range)
+                    .await
+                    .map_err(|e| (e.code() as u16, (non_repeaters + i) as u16 + 1))?;
+                if varbind.value != VarBindValue::EndOfMibView {
+                    end_of_mib_view = false;
+                }
+                range.0 = varbind.name.to_vec();
+                range.1 = false;
+                ret.push(varbind);
+            }
+            if ranges.is_empty() || end_of_mib_view {
+                break;
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn test_set(&mut self, decoder: &mut Decoder<'_>) -> Result<Vec<VarBind>, (u16, u16)> {
+        self.pending.clear();
+        let request = SetId::new_request();
+        while !decoder.is_empty() {
+            let varbind = decoder.varbind().map_err(|_| (PARSE_ERROR, 0))?;
+            let index = self.pending.len() as u16 + 1;
+            let value = match varbind.value {
+                VarBindValue::Value(x) => x,
+                _ => return Err((ErrorStatus::WrongType.code() as u16, index)),
+            };
+            let (len, handler) = self
+                .handlers
+                .find(&varbind.name)
+                .ok_or((ErrorStatus::NoCreation.code() as u16, index))?;
+            let id = request.varbind(self.pending.len());
+            handler
+                .test(id, &varbind.name[len..], &value)
+                .await
+                .map_err(|e| (e.code() as u16, index))?;
+            self.pending.push(PendingSet {
+                id,
+                oid: varbind.name.to_vec(),
+                value,
+                committed: false,
+            });
+        }
+        Ok(vec![])
+    }
+
+    async fn commit_set(&mut self) -> Result<Vec<VarBind>, (u16, u16)> {
+        for (i, pending) in self.pending.iter_mut().enumerate() {
+            let (len, handler) = self
+                .handlers
+                .find(&pending.oid)
+                .ok_or((ErrorStatus::CommitFailed.code() as u16, i as u16 + 1))?;
+            handler
+                .commit(pending.id, &pending.oid[len..], pending.value.clone())
+                .await
+                .map_err(|_| (ErrorStatus::CommitFailed.code() as u16, i as u16 + 1))?;
+            pending.committed = true;
+        }
+        Ok(vec![])
+    }
+
+    async fn undo_set(&mut self) -> Result<Vec<VarBind>, (u16, u16)> {
+        let mut result = Ok(vec![]);
+        for (i, pending) in self.pending.iter().enumerate().rev() {
+            if !pending.committed {
+                continue;
+            }
+            let undone = match self.handlers.find(&pending.oid) {
+                Some((len, handler)) => handler.undo(pending.id, &pending.oid[len..]).await.is_ok(),
+                None => false,
+            };
+            if !undone {
+                result = Err((ErrorStatus::UndoFailed.code() as u16, i as u16 + 1));
+            }
+        }
+        result
+    }
+
+    async fn cleanup_set(&mut self) -> Result<Vec<VarBind>, (u16, u16)> {
+        for pending in self.pending.drain(..) {
+            if let Some((len, handler)) = self.handlers.find(&pending.oid) {
+                handler.cleanup(pending.id, &pending.oid[len..]).await;
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::DuplexStream;
+
+    use crate::agent::Scalar;
+
+    const SYS_DESCR: [u32; 8] = [1, 3, 6, 1, 2, 1, 1, 1];
+    const SYS_CONTACT: [u32; 8] = [1, 3, 6, 1, 2, 1, 1, 4];
+    const SESSION_ID: u32 = 42;
+
+    fn instance(x: &[u32]) -> Vec<u32> {
+        [x, &[0][..]].concat()
+    }
+
+    fn string(x: &str) -> VarBindValue {
+        VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::String(x.into())))
+    }
+
+    /// A stand-in for an AgentX master agent, sending requests to the subagent at the other end of a stream.
+    struct Master {
+        stream: DuplexStream,
+        packet_id: u32,
+    }
+
+    impl Master {
+        // Accepts a request from the subagent, returning its payload.
+        async fn accept(&mut self, pdu_type: u8) -> Vec<u8> {
+            let (header, payload) = read_pdu(&mut self.stream).await.unwrap();
+            assert_eq!(header.pdu_type, pdu_type);
+            self.respond(header).await;
+            payload
+        }
+
+        // Answers a request from the subagent successfully.
+        async fn respond(&mut self, header: Header) {
+            let mut response = Encoder::default();
+            response.u32(0).u16(0).u16(0);
+            let response = response.pdu(Header {
+                pdu_type: RESPONSE,
+                session_id: SESSION_ID,
+                ..header
+            });
+            self.stream.write_all(&response).await.unwrap();
+        }
+
+        // Sends a request to the subagent, returning the error, index and variable bindings of its response.
+        async fn send(&mut self, pdu_type: u8, payload: Encoder) -> (u16, u16, Vec<VarBind>) {
+            self.packet_id += 1;
+            let request = payload.pdu(Header {
+                pdu_type,
+                flags: 0,
+                session_id: SESSION_ID,
+                transaction_id: 1,
+                packet_id: self.packet_id,
+            });
+            self.stream.write_all(&request).await.unwrap();
+
+            let (header, payload) = read_pdu(&mut self.stream).await.unwrap();
+            assert_eq!(header.pdu_type, RESPONSE);
+            assert_eq!(header.packet_id, self.packet_id);
+            let mut decoder = Decoder::new(&payload, header.flags);
+            decoder.u32().unwrap();
+            let error = decoder.u16().unwrap();
+            let index = decoder.u16().unwrap();
+            let mut varbinds = vec![];
+            while !decoder.is_empty() {
+                varbinds.push(decoder.varbind().unwrap());
+            }
+            (error, index, varbinds)
+        }
+    }
+
+    fn ranges(ranges: &[(&[u32], bool)]) -> Encoder {
+        let mut ret = Encoder::default();
+        for (start, include) in ranges {
+            ret.oid(start, *include).unwrap().oid(&[], false).unwrap();
+        }
+        ret
+    }
+
+    #[test]
+    fn oid_prefix() {
+        let mut encoder = Encoder::default();
+        encoder
+            .oid(&instance(&SYS_DESCR), true)
+            .unwrap()
+            .oid(&[1, 2, 3], false)
+            .unwrap();
+        assert_eq!(&encoder.0[..4], &[4, 2, 1, 0]);
+        let mut decoder = Decoder::new(&encoder.0, NETWORK_BYTE_ORDER);
+        assert_eq!(decoder.oid().unwrap(), (instance(&SYS_DESCR), true));
+        assert_eq!(decoder.oid().unwrap(), (vec![1, 2, 3], false));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn oid_length() {
+        let mut encoder = Encoder::default();
+        assert!(encoder.oid(&[1; 255], false).is_ok());
+        assert!(matches!(
+            encoder.oid(&[1; 256], false),
+            Err(crate::Error::AgentXOidLength(256))
+        ));
+        // the prefix doesn't count towards the limit
+        assert!(encoder
+            .oid(&[&[1, 3, 6, 1, 4][..], &[1; 255]].concat(), false)
+            .is_ok());
+    }
+
+    #[test]
+    fn octet_string_length() {
+        let mut decoder = Decoder::new(&[0, 0, 0, 3, b'a', b'b', b'c', 0], NETWORK_BYTE_ORDER);
+        assert_eq!(decoder.octet_string().unwrap(), b"abc");
+        // lengths beyond the payload are rejected without overflowing when padded
+        for len in [5, u32::MAX - 1, u32::MAX] {
+            let data = len.to_be_bytes();
+            let mut decoder = Decoder::new(&data, NETWORK_BYTE_ORDER);
+            assert!(matches!(
+                decoder.octet_string(),
+                Err(crate::Error::MalformedMessage)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn payload_length() {
+        let pdu = |len: u32| {
+            let mut pdu = Encoder::default().pdu(Header {
+                pdu_type: RESPONSE,
+                flags: 0,
+                session_id: SESSION_ID,
+                transaction_id: 0,
+                packet_id: 1,
+            });
+            pdu[16..].copy_from_slice(&len.to_be_bytes());
+            pdu
+        };
+        // nothing is allocated for the payload, so there's no need to send it
+        for len in [u32::MAX, MAX_PAYLOAD_LEN + 4, 6] {
+            assert!(matches!(
+                read_pdu(&mut &pdu(len)[..]).await,
+                Err(crate::Error::AgentXPayloadLength(_, x)) if x == len
+            ));
+        }
+        let mut data = pdu(4);
+        data.extend_from_slice(&[0; 4]);
+        assert_eq!(read_pdu(&mut &data[..]).await.unwrap().1, vec![0; 4]);
+    }
+
+    #[tokio::test]
+    async fn subagent() {
+        let (stream, master) = tokio::io::duplex(4096);
+        let mut master = Master {
+            stream: master,
+            packet_id: 0,
+        };
+        let contact = Arc::new(Mutex::new(OctetString::from("nobody")));
+        let set_contact = contact.clone();
+
+        let subagent = async {
+            let mut subagent = Subagent::open(
+                stream,
+                ObjectIdentifier::new_unchecked(vec![1, 3, 6, 1, 4, 1, 99].into()),
+                "snipe",
+            )
+            .await?;
+            assert_eq!(subagent.session_id(), SESSION_ID);
+            subagent
+                .register(
+                    ObjectIdentifier::new_unchecked(SYS_DESCR.to_vec().into()),
+                    Scalar::new(|| OctetString::from("snipe")),
+                )
+                .await?;
+            subagent
+                .register(
+                    ObjectIdentifier::new_unchecked(SYS_CONTACT.to_vec().into()),
+                    Scalar::new(move || contact.lock().unwrap().clone()).with_set(move |x| {
+                        *set_contact.lock().unwrap() = x;
+                        Ok(())
+                    }),
+                )
+                .await?;
+            // overlapping subtrees are refused without asking the master agent
+            assert!(matches!(
+                subagent
+                    .register(
+                        ObjectIdentifier::new_unchecked(instance(&SYS_CONTACT).into()),
+                        Scalar::new(|| OctetString::from("root")),
+                    )
+                    .await,
+                Err(crate::Error::OverlappingHandler)
+            ));
+            subagent.run().await
+        };
+
+        let master = async {
+            let open = master.accept(OPEN).await;
+            let mut decoder = Decoder::new(&open, NETWORK_BYTE_ORDER);
+            decoder.u32().unwrap();
+            assert_eq!(decoder.oid().unwrap().0, vec![1, 3, 6, 1, 4, 1, 99]);
+            assert_eq!(decoder.octet_string().unwrap(), b"snipe");
+            master.accept(REGISTER).await;
+
+            // requests for registered subtrees are answered while the subagent waits for a response
+            let (header, _) = read_pdu(&mut master.stream).await.unwrap();
+            assert_eq!(header.pdu_type, REGISTER);
+            let (_, _, varbinds) = master
+                .send(GET, ranges(&[(&instance(&SYS_DESCR), false)]))
+                .await;
+            assert_eq!(varbinds[0].value, string("snipe"));
+            master.respond(header).await;
+
+            let (error, _, varbinds) = master
+                .send(
+                    GET,
+                    ranges(&[(&instance(&SYS_DESCR), false), (&SYS_CONTACT, false)]),
+                )
+                .await;
+            assert_eq!(error, 0);
+            assert_eq!(varbinds[0].value, string("snipe"));
+            assert_eq!(varbinds[1].value, VarBindValue::NoSuchInstance);
+
+            let (_, _, varbinds) = master
+                .send(
+                    GET_NEXT,
+                    ranges(&[
+                        (&instance(&SYS_DESCR), true),
+                        (&instance(&SYS_DESCR), false),
+                        (&instance(&SYS_CONTACT), false),
+                    ]),
+                )
+                .await;
+            assert_eq!(varbinds[0].name.to_vec(), instance(&SYS_DESCR));
+            assert_eq!(varbinds[1].name.to_vec(), instance(&SYS_CONTACT));
+            assert_eq!(varbinds[1].value, string("nobody"));
+            assert_eq!(varbinds[2].value, VarBindValue::EndOfMibView);
+
+            let mut payload = Encoder::default();
+            payload
+                .varbind(&VarBind {
+                    name: ObjectIdentifier::new_unchecked(instance(&SYS_CONTACT).into()),
+                    value: string("admin"),
+                })
+                .unwrap();
+            assert_eq!(master.send(TEST_SET, payload).await.0, 0);
+            assert_eq!(master.send(COMMIT_SET, Encoder::default()).await.0, 0);
+            assert_eq!(master.send(CLEANUP_SET, Encoder::default()).await.0, 0);
+
+            let (_, _, varbinds) = master
+                .send(GET, ranges(&[(&instance(&SYS_CONTACT), false)]))
+                .await;
+            assert_eq!(varbinds[0].value, string("admin"));
+
+            let mut payload = Encoder::default();
+            payload
+                .varbind(&VarBind {
+                    name: ObjectIdentifier::new_unchecked(instance(&SYS_DESCR).into()),
+                    value: string("agent"),
+                })
+                .unwrap();
+            let (error, index, _) = master.send(TEST_SET, payload).await;
+            assert_eq!(error, ErrorStatus::NotWritable.code() as u16);
+            assert_eq!(index, 1);
+            master.send(CLEANUP_SET, Encoder::default()).await;
+
+            let mut close = Encoder::default();
+            close.u8(REASON_SHUTDOWN).u8(0).u8(0).u8(0);
+            let close = close.pdu(Header {
+                pdu_type: CLOSE,
+                flags: 0,
+                session_id: SESSION_ID,
+                transaction_id: 0,
+                packet_id: 0,
+            });
+            master.stream.write_all(&close).await.unwrap();
+        };
+
+        let (result, ()) = tokio::join!(subagent, master);
+        result.unwrap();
+    }
+}
@@ -1,101 +1,444 @@
-use std::time::Instant;
+//! Sending of SNMPv3 requests to an agent over a stream transport, such as TCP (RFC 3430).
+
+use std::time::{Duration, Instant};
 
 use rasn::types::{ObjectIdentifier, OctetString};
 use rasn_smi::v2::ObjectSyntax;
 use rasn_snmp::{
-    v2::VarBindList,
+    v2::{
+        BulkPdu, GetBulkRequest, GetRequest, Pdu, Pdus, SetRequest, VarBind, VarBindList,
+        VarBindValue,
+    },
     v3::{HeaderData, Message, ScopedPdu, ScopedPduData, USMSecurityParameters},
 };
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    originator::RemoteEngine,
+    rfc3412::{request_id, MessageFlags},
+    rfc3414::{
+        authenticate_incoming, check_engine_id, encode_outgoing, process_incoming, reported_error,
+        LocalEngine, UsmUser,
+    },
+};
+
+const SNMP_VERSION: u32 = 3;
+const MAX_MESSAGE_SIZE: u32 = 65507;
+const DEFAULT_MAX_REPETITIONS: u32 = 10;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
-use crate::{rfc3412::MessageFlags, rfc3414::AuthProtocol};
+/// Reads a single message from a stream. Messages are sent back to back without any other framing (RFC 3430 section
+/// 2.1), so their length is taken from the BER length of the outer SEQUENCE.
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, crate::Error> {
+    let mut data = vec![0_u8; 2];
+    reader.read_exact(&mut data).await?;
+    if data[0] != 0x30 {
+        return Err(crate::Error::MalformedMessage);
+    }
 
-const SNMP_VERSION: i32 = 3;
-const MAX_MESSAGE_SIZE: i32 = 65507;
+    let len = if data[1] & 0x80 == 0 {
+        data[1] as usize
+    } else {
+        let num_octets = (data[1] & 0x7f) as usize;
+        if num_octets == 0 || num_octets > std::mem::size_of::<u32>() {
+            return Err(crate::Error::MalformedMessage);
+        }
+        data.resize(2 + num_octets, 0);
+        reader.read_exact(&mut data[2..]).await?;
+        data[2..]
+            .iter()
+            .fold(0_usize, |acc, x| (acc << 8) | *x as usize)
+    };
+    if len > MAX_MESSAGE_SIZE as usize {
+        return Err(crate::Error::MalformedMessage);
+    }
 
-// TODO REPLACE THIS! THIS IS FROM SNMPBULKWALK!
-const ENGINE_ID: &[u8] = &[
-    0x6e, 0x69, 0x67, 0x68, 0x74, 0x72, 0x61, 0x76, 0x65, 0x6e, 0x3c, 0xcf, 0x03, 0x40,
-];
+    let header_len = data.len();
+    data.resize(header_len + len, 0);
+    reader.read_exact(&mut data[header_len..]).await?;
+    Ok(data)
+}
 
+/// An [`SnmpInterface`](crate::SnmpInterface) sending SNMPv3 requests over a stream on behalf of a single user, at
+/// the security level configured for the user.
+///
+/// The agent's engine ID is discovered with the first request. Its boots and time are learnt from the authenticated
+/// notInTimeWindow Report it replies with the first time (RFC 3414 section 4).
 pub struct DefaultSnmpInterface<T: AsyncRead + AsyncWrite> {
     stream: T,
-    engine_id: OctetString,
-    msg_id: i32,
-    boots: i32,
-    init_time: Instant,
-    username: OctetString,
-    auth_protocol: AuthProtocol,
-    auth_password: Vec<u8>,
-    priv_password: Vec<u8>,
-    flags: MessageFlags,
+    user: UsmUser,
+    engine: LocalEngine,
+    remote: Option<RemoteEngine>,
+    request_id: i32,
+    message_id: i32,
+    max_repetitions: u32,
+    timeout: Duration,
 }
 
-impl<T: AsyncRead + AsyncWrite> DefaultSnmpInterface<T> {
-    fn reinit(&mut self) {
-        self.msg_id = 0;
-        self.init_time = Instant::now();
-        self.boots += 1;
+impl<T: AsyncRead + AsyncWrite + Unpin> DefaultSnmpInterface<T> {
+    /// Creates an interface sending requests over `stream`. The interface gets a randomly generated engine ID, use
+    /// [`DefaultSnmpInterface::with_engine`] to keep its snmpEngineBoots across restarts.
+    pub fn new(stream: T, user: UsmUser) -> Result<Self, crate::Error> {
+        Ok(Self {
+            stream,
+            user: user.with_master_keys()?,
+            engine: LocalEngine::new(LocalEngine::random_engine_id(), 1)?,
+            remote: None,
+            request_id: 0,
+            message_id: 0,
+            max_repetitions: DEFAULT_MAX_REPETITIONS,
+            timeout: DEFAULT_TIMEOUT,
+        })
     }
 
-    fn time(&mut self) -> i32 {
-        let time = Instant::now().duration_since(self.init_time).as_secs();
-        if time > i32::MAX as u64 {
-            self.reinit();
-            self.time()
-        } else {
-            time as i32
-        }
+    /// Sets the local engine, whose snmpEngineBoots is part of the salt of encrypted requests (RFC 3414 section
+    /// 8.1.1.1).
+    pub fn with_engine(mut self, engine: LocalEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Sets the max-repetitions of the GetBulkRequests sent by [`SnmpInterface::bulk`](crate::SnmpInterface::bulk).
+    pub fn with_max_repetitions(mut self, max_repetitions: u32) -> Self {
+        self.max_repetitions = max_repetitions;
+        self
+    }
+
+    /// Sets how long to wait for the agent to reply to a request. A request which times out may leave part of its
+    /// reply unread, so the stream should be reconnected afterwards.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn next_request_id(&mut self) -> i32 {
+        self.request_id = self.request_id.checked_add(1).unwrap_or(1);
+        self.request_id
     }
 
-    pub fn create_msg(&mut self, pdu: ScopedPdu) -> Result<Message, crate::Error> {
+    fn create_msg(
+        &mut self,
+        engine_id: OctetString,
+        flags: MessageFlags,
+        pdus: Pdus,
+    ) -> Result<Message, crate::Error> {
+        self.message_id = self.message_id.checked_add(1).unwrap_or(1);
+        let (boots, time) = self
+            .remote
+            .as_ref()
+            .map_or((0, 0), |remote| (remote.boots, remote.time()));
         Ok(Message {
             version: SNMP_VERSION.into(),
             global_data: HeaderData {
-                message_id: {
-                    self.msg_id += 1;
-                    if self.msg_id == i32::MAX {
-                        self.reinit();
-                    }
-                    self.msg_id.into()
-                },
+                message_id: self.message_id.into(),
                 max_size: MAX_MESSAGE_SIZE.into(),
-                flags: self.flags.try_into()?,
-                security_model: 3_u32.into(),
+                flags: flags.try_into()?,
+                security_model: 3.into(),
             },
             security_parameters: USMSecurityParameters {
-                authoritative_engine_id: self.engine_id.clone(),
-                authoritative_engine_time: self.time().into(),
-                // NOTE: ORDER MATTERS HERE!
-                authoritative_engine_boots: self.boots.into(),
-                user_name: self.username.clone(),
-                authentication_parameters: [0_u8; 12][..].into(),
-                privacy_parameters: [0_u8; 12][..].into(),
+                authoritative_engine_id: engine_id.clone(),
+                authoritative_engine_boots: boots.into(),
+                authoritative_engine_time: time.into(),
+                user_name: self.user.username.clone(),
+                authentication_parameters: OctetString::default(),
+                privacy_parameters: OctetString::default(),
             },
-            scoped_data: ScopedPduData::CleartextPdu(pdu),
+            scoped_data: ScopedPduData::CleartextPdu(ScopedPdu {
+                engine_id,
+                name: OctetString::default(),
+                data: pdus,
+            }),
         })
     }
-    pub fn format_read(oid: ObjectIdentifier) -> Message {
-        todo!()
+
+    // Reads messages until the reply to the last message sent, skipping late replies to earlier ones.
+    async fn read_reply(&mut self) -> Result<(Vec<u8>, Message), crate::Error> {
+        let (stream, message_id) = (&mut self.stream, self.message_id);
+        let reply = async move {
+            loop {
+                let data = read_message(stream).await?;
+                let msg: Message = rasn::ber::decode(&data).map_err(crate::Error::AsnDecode)?;
+                if i32::try_from(msg.global_data.message_id.clone()).ok() == Some(message_id) {
+                    return Ok((data, msg));
+                }
+            }
+        };
+        tokio::time::timeout(self.timeout, reply)
+            .await
+            .map_err(|_| crate::Error::Timeout)?
+    }
+
+    // Learns the agent's engine ID from the unknownEngineID Report it sends in reply to an unauthenticated request
+    // without one (RFC 3414 section 4).
+    async fn discover(&mut self) -> Result<(), crate::Error> {
+        let flags = MessageFlags {
+            reportable: true,
+            auth: false,
+            privacy: false,
+        };
+        let pdus = Pdus::GetRequest(GetRequest(Pdu {
+            request_id: self.next_request_id(),
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings: vec![],
+        }));
+        let mut msg = self.create_msg(OctetString::default(), flags, pdus)?;
+        msg.security_parameters.user_name = OctetString::default();
+        let data = rasn::ber::encode(&msg).map_err(crate::Error::AsnEncode)?;
+        self.stream.write_all(&data).await?;
+
+        let (_, msg) = self.read_reply().await?;
+        let engine_id = msg.security_parameters.authoritative_engine_id;
+        check_engine_id(&engine_id[..])?;
+        self.remote = Some(RemoteEngine {
+            engine_id,
+            boots: 0,
+            time: 0,
+            synced: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Sends a request, returning the agent's response. Responses with an error-status are returned as errors.
+    async fn request(&mut self, pdus: Pdus) -> Result<Pdu, crate::Error> {
+        if self.remote.is_none() {
+            self.discover().await?;
+        }
+        let mut synced = false;
+        loop {
+            match self.exchange(pdus.clone()).await {
+                // the agent's boots and time were updated from its report, so resend once with them
+                Err(crate::Error::NotInTimeWindow) if !synced => synced = true,
+                Ok(response) if response.error_status != Pdu::ERROR_STATUS_NO_ERROR => {
+                    return Err(crate::Error::ResponseError(
+                        response.error_status,
+                        response.error_index,
+                    ))
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn exchange(&mut self, pdus: Pdus) -> Result<Pdu, crate::Error> {
+        let request_id = request_id(&pdus);
+        let engine_id = self
+            .remote
+            .as_ref()
+            .ok_or(crate::Error::UnknownEngineId)?
+            .engine_id
+            .clone();
+        let flags = MessageFlags {
+            reportable: true,
+            ..self.user.security_level()
+        };
+        let msg = self.create_msg(engine_id, flags, pdus)?;
+        let data = encode_outgoing(msg, &self.user, self.engine.next_salt())?;
+        self.stream.write_all(&data).await?;
+
+        let (data, msg) = self.read_reply().await?;
+        // Reports may be sent at a lower security level than the request, so they are checked separately. Only
+        // authenticated reports are trusted to update the agent's boots and time.
+        if let ScopedPduData::CleartextPdu(ScopedPdu {
+            data: Pdus::Report(report),
+            ..
+        }) = &msg.scoped_data
+        {
+            let flags = MessageFlags::try_from(&msg.global_data.flags)?;
+            let authenticated = flags.auth
+                && authenticate_incoming(&data, &msg.security_parameters, &self.user).is_ok();
+            let error = reported_error(&report.0).unwrap_or(crate::Error::UnexpectedPdu);
+            if authenticated && matches!(error, crate::Error::NotInTimeWindow) {
+                if let Some(remote) = self.remote.as_mut() {
+                    remote.sync(&msg.security_parameters);
+                }
+            }
+            return Err(error);
+        }
+
+        match process_incoming(&data, msg, &self.user)?.data {
+            Pdus::Response(response) if response.0.request_id == request_id => Ok(response.0),
+            _ => Err(crate::Error::UnexpectedPdu),
+        }
     }
-    pub fn format_write(oid: ObjectIdentifier) -> Message {
-        todo!()
+
+    fn pdu(&mut self, variable_bindings: VarBindList) -> Pdu {
+        Pdu {
+            request_id: self.next_request_id(),
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings,
+        }
     }
 }
 
 #[async_trait::async_trait]
-impl<T: AsyncRead + AsyncWrite + Send + Sync> crate::SnmpInterface for DefaultSnmpInterface<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> crate::SnmpInterface
+    for DefaultSnmpInterface<T>
+{
     async fn read(&mut self, oid: ObjectIdentifier) -> Result<ObjectSyntax, crate::Error> {
-        todo!()
+        let pdu = self.pdu(vec![VarBind {
+            name: oid,
+            value: VarBindValue::Unspecified,
+        }]);
+        let response = self.request(Pdus::GetRequest(GetRequest(pdu))).await?;
+        match response.variable_bindings.into_iter().next() {
+            Some(VarBind {
+                value: VarBindValue::Value(value),
+                ..
+            }) => Ok(value),
+            _ => Err(crate::Error::NoSuchInstance),
+        }
     }
+
     async fn write(
         &mut self,
         oid: ObjectIdentifier,
-        value: rasn_smi::v2::ObjectSyntax,
+        value: ObjectSyntax,
     ) -> Result<(), crate::Error> {
-        todo!()
+        let pdu = self.pdu(vec![VarBind {
+            name: oid,
+            value: VarBindValue::Value(value),
+        }]);
+        self.request(Pdus::SetRequest(SetRequest(pdu))).await?;
+        Ok(())
     }
+
     async fn bulk(&mut self, oid: ObjectIdentifier) -> Result<VarBindList, crate::Error> {
-        todo!()
+        let pdu = BulkPdu {
+            request_id: self.next_request_id(),
+            non_repeaters: 0,
+            max_repetitions: self.max_repetitions,
+            variable_bindings: vec![VarBind {
+                name: oid,
+                value: VarBindValue::Unspecified,
+            }],
+        };
+        let response = self
+            .request(Pdus::GetBulkRequest(GetBulkRequest(pdu)))
+            .await?;
+        Ok(response.variable_bindings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use rasn_smi::v2::SimpleSyntax;
+    use tokio::io::DuplexStream;
+
+    use crate::{
+        agent::{Agent, ErrorStatus, Scalar},
+        rfc3414::{AuthProtocol, Credential, PrivProtocol},
+        SnmpInterface,
+    };
+
+    const SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+    const SYS_DESCR: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 1, 0];
+    const SYS_CONTACT: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 4, 0];
+
+    fn oid(x: &[u32]) -> ObjectIdentifier {
+        ObjectIdentifier::new_unchecked(x.to_vec().into())
+    }
+
+    fn string(x: &str) -> ObjectSyntax {
+        ObjectSyntax::Simple(SimpleSyntax::String(x.into()))
+    }
+
+    fn user() -> UsmUser {
+        UsmUser {
+            username: "manager".into(),
+            auth_protocol: AuthProtocol::Sha1,
+            auth_key: Credential::Password("authpassword".into()),
+            priv_protocol: PrivProtocol::Aes,
+            priv_key: Credential::Password("privpassword".into()),
+        }
+    }
+
+    // Answers the messages sent over the stream with the agent, as an agent accepting TCP connections would.
+    async fn serve(agent: Agent, mut stream: DuplexStream) {
+        while let Ok(data) = read_message(&mut stream).await {
+            if let Some(reply) = agent.process(&data).await {
+                stream.write_all(&reply).await.unwrap();
+            }
+        }
+    }
+
+    async fn client() -> (DefaultSnmpInterface<DuplexStream>, Arc<Mutex<OctetString>>) {
+        let contact = Arc::new(Mutex::new(OctetString::from("nobody")));
+        let (get_contact, set_contact) = (contact.clone(), contact.clone());
+        let agent = Agent::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_user(user())
+            .unwrap()
+            .with_handler(
+                oid(&SYS_DESCR[..8]),
+                Scalar::new(|| OctetString::from("snipe")),
+            )
+            .unwrap()
+            .with_handler(
+                oid(&SYS_CONTACT[..8]),
+                Scalar::new(move || get_contact.lock().unwrap().clone()).with_set(move |x| {
+                    *set_contact.lock().unwrap() = x;
+                    Ok(())
+                }),
+            )
+            .unwrap();
+        let (stream, agent_stream) = tokio::io::duplex(MAX_MESSAGE_SIZE as usize);
+        tokio::spawn(serve(agent, agent_stream));
+        (DefaultSnmpInterface::new(stream, user()).unwrap(), contact)
+    }
+
+    #[tokio::test]
+    async fn read_write() {
+        let (mut client, contact) = client().await;
+        assert_eq!(client.read(oid(&SYS_DESCR)).await.unwrap(), string("snipe"));
+        assert!(matches!(
+            client.read(oid(&[&SYSTEM[..], &[2, 0]].concat())).await,
+            Err(crate::Error::NoSuchInstance)
+        ));
+
+        client
+            .write(oid(&SYS_CONTACT), string("root"))
+            .await
+            .unwrap();
+        assert_eq!(*contact.lock().unwrap(), OctetString::from("root"));
+        assert_eq!(
+            client.read(oid(&SYS_CONTACT)).await.unwrap(),
+            string("root")
+        );
+
+        let not_writable = ErrorStatus::NotWritable.code();
+        assert!(matches!(
+            client.write(oid(&SYS_DESCR), string("other")).await,
+            Err(crate::Error::ResponseError(status, 1)) if status == not_writable
+        ));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        // the agent's end is kept open but never replies
+        let (stream, _agent_stream) = tokio::io::duplex(MAX_MESSAGE_SIZE as usize);
+        let mut client = DefaultSnmpInterface::new(stream, user())
+            .unwrap()
+            .with_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            client.read(oid(&SYS_DESCR)).await,
+            Err(crate::Error::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn bulk() {
+        let (client, _) = client().await;
+        let mut client = client.with_max_repetitions(3);
+        let varbinds = client.bulk(oid(&SYSTEM)).await.unwrap();
+        let names: Vec<_> = varbinds.iter().map(|x| x.name.to_vec()).collect();
+        assert_eq!(names[..2], [SYS_DESCR.to_vec(), SYS_CONTACT.to_vec()]);
+        assert_eq!(varbinds[2].value, VarBindValue::EndOfMibView);
     }
 }
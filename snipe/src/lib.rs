@@ -1,10 +1,18 @@
+pub mod agent;
+pub mod agentx;
 pub mod client;
+pub mod notification;
+pub mod originator;
 pub mod prelude;
 pub mod rfc1212;
+pub mod rfc2579;
 pub mod rfc3412;
 pub mod rfc3414;
+pub mod rfc3415;
+pub mod rfc3584;
 
 use std::{
+    collections::BTreeMap,
     io::{Read, Write},
     num::{ParseIntError, TryFromIntError},
     str::Utf8Error,
@@ -15,13 +23,52 @@ use aes::cipher::{block_padding::UnpadError, inout::PadError};
 use num_bigint::TryFromBigIntError;
 pub use rasn as asn;
 pub use rasn::prelude::*;
-use rasn_smi::v1::{InvalidVariant, IpAddress};
+pub use rasn_smi as smi;
+use rasn_smi::v1::InvalidVariant;
 pub use rasn_snmp as snmp;
-use snmp::v2::{ObjectSyntax, VarBindList};
+use snmp::v2::{ObjectSyntax, VarBindList, VarBindValue};
 
 extern crate self as snipe;
 
-pub use snipe_macros::{declare_mib, declare_oid};
+#[doc(hidden)]
+pub use snipe_macros::__private_api_declare_mib_struct;
+pub use snipe_macros::{declare_mib, declare_oid, oid};
+
+/// `declare_oid!` only generates the traits an object's access allows. A read-write object can be read and set:
+///
+/// ```
+/// snipe::__private_api_declare_mib_struct!("TEST-MIB");
+/// snipe::declare_oid!(TestMib, "testValue", i32, oid = 1.3.6.1.4.1.32473.1, access = read-write);
+///
+/// async fn update<I: snipe::SnmpInterface + Send + Sync>(mib: &mut TestMib<'_, I>) -> Result<(), snipe::Error> {
+///     let value = mib.test_value().await?;
+///     mib.set_test_value(value + 1).await
+/// }
+/// ```
+///
+/// Setting a read-only object doesn't compile, as it has no `Write` trait:
+///
+/// ```compile_fail
+/// snipe::__private_api_declare_mib_struct!("TEST-MIB");
+/// snipe::declare_oid!(TestMib, "testValue", i32, oid = 1.3.6.1.4.1.32473.1, access = read-only);
+///
+/// async fn update<I: snipe::SnmpInterface + Send + Sync>(mib: &mut TestMib<'_, I>) -> Result<(), snipe::Error> {
+///     mib.set_test_value(1).await
+/// }
+/// ```
+///
+/// Neither does reading a not-accessible object, which has no `Read` trait:
+///
+/// ```compile_fail
+/// snipe::__private_api_declare_mib_struct!("TEST-MIB");
+/// snipe::declare_oid!(TestMib, "testValue", i32, oid = 1.3.6.1.4.1.32473.1, access = not-accessible);
+///
+/// async fn read<I: snipe::SnmpInterface + Send + Sync>(mib: &mut TestMib<'_, I>) -> Result<i32, snipe::Error> {
+///     mib.test_value().await
+/// }
+/// ```
+#[cfg(doctest)]
+pub struct DeclareOidAccess;
 
 // ------------------------------------- FIXED -------------------------------------
 
@@ -47,6 +94,149 @@ pub fn append_index<T, C: prelude::OidConverter<T>>(
         .ok_or_else(|| Error::BaseOid(base_oid.iter().map(|x| format!("{x}.")).collect()))
 }
 
+/// A conceptual row of a table, as generated for each table in a MIB. Columns are set one at a time as a table is
+/// walked.
+pub trait TableRow: Default {
+    fn set_column(&mut self, column: u32, value: ObjectSyntax) -> Result<(), Error>;
+}
+
+/// Fetches every row of a conceptual table by walking its entry OID with [`SnmpInterface::bulk`]. Rows are returned in
+/// index order, with each index read from the instance OIDs by `C`.
+pub async fn fetch_table<Index, Row: TableRow, C: prelude::OidConverter<Index>>(
+    interface: &mut impl SnmpInterface,
+    entry_oid: &[u32],
+) -> Result<Vec<(Index, Row)>, Error> {
+    walk_table::<Row>(interface, entry_oid)
+        .await?
+        .into_iter()
+        .map(|(index, row)| Ok((C::try_from_oid(&index)?.converted, row)))
+        .collect()
+}
+
+/// Fetches every row of a conceptual table along with the matching row of a second table which shares its index, such
+/// as one which AUGMENTS it. Rows are matched by their instance OIDs, and the second table's row is `None` if it has no
+/// matching row.
+pub async fn fetch_joined_table<
+    Index,
+    Row: TableRow,
+    Other: TableRow,
+    C: prelude::OidConverter<Index>,
+>(
+    interface: &mut impl SnmpInterface,
+    entry_oid: &[u32],
+    other_entry_oid: &[u32],
+) -> Result<Vec<(Index, Row, Option<Other>)>, Error> {
+    let rows = walk_table::<Row>(interface, entry_oid).await?;
+    let mut others = walk_table::<Other>(interface, other_entry_oid).await?;
+    rows.into_iter()
+        .map(|(index, row)| {
+            let other = others.remove(&index);
+            Ok((C::try_from_oid(&index)?.converted, row, other))
+        })
+        .collect()
+}
+
+/// Creates a row of a conceptual table whose status column is a RowStatus, as described in RFC 2579: the row is created
+/// with createAndWait, the given columns are set one at a time, and the row is then activated.
+pub async fn create_row<Index, C: prelude::OidConverter<Index>>(
+    interface: &mut impl SnmpInterface,
+    entry_oid: &[u32],
+    status_column: u32,
+    index: Index,
+    columns: Vec<(u32, ObjectSyntax)>,
+) -> Result<(), Error> {
+    let instance = C::try_to_oid(index)?;
+    let status = |value: rfc2579::RowStatus| {
+        <prelude::DefaultConverter as prelude::SnmpConverter<rfc2579::RowStatus>>::try_to_snmp(
+            value,
+        )
+    };
+    interface
+        .write(
+            column_oid(entry_oid, status_column, &instance)?,
+            status(rfc2579::RowStatus::CreateAndWait)?,
+        )
+        .await?;
+    for (column, value) in columns {
+        interface
+            .write(column_oid(entry_oid, column, &instance)?, value)
+            .await?;
+    }
+    interface
+        .write(
+            column_oid(entry_oid, status_column, &instance)?,
+            status(rfc2579::RowStatus::Active)?,
+        )
+        .await
+}
+
+/// Destroys a row of a conceptual table whose status column is a RowStatus.
+pub async fn destroy_row<Index, C: prelude::OidConverter<Index>>(
+    interface: &mut impl SnmpInterface,
+    entry_oid: &[u32],
+    status_column: u32,
+    index: Index,
+) -> Result<(), Error> {
+    let instance = C::try_to_oid(index)?;
+    interface
+        .write(
+            column_oid(entry_oid, status_column, &instance)?,
+            <prelude::DefaultConverter as prelude::SnmpConverter<rfc2579::RowStatus>>::try_to_snmp(
+                rfc2579::RowStatus::Destroy,
+            )?,
+        )
+        .await
+}
+
+/// Returns the OID of a column's instance in a conceptual table.
+fn column_oid(
+    entry_oid: &[u32],
+    column: u32,
+    instance: &ObjectIdentifier,
+) -> Result<ObjectIdentifier, Error> {
+    let vec = entry_oid
+        .iter()
+        .copied()
+        .chain([column])
+        .chain(instance.iter().copied())
+        .collect::<Vec<_>>();
+    ObjectIdentifier::new(vec)
+        .ok_or_else(|| Error::BaseOid(entry_oid.iter().map(|x| format!("{x}.")).collect()))
+}
+
+/// Walks a conceptual table, returning its rows keyed by the index part of their instance OIDs.
+async fn walk_table<Row: TableRow>(
+    interface: &mut impl SnmpInterface,
+    entry_oid: &[u32],
+) -> Result<BTreeMap<Vec<u32>, Row>, Error> {
+    let mut rows = BTreeMap::<Vec<u32>, Row>::new();
+    let mut next = ObjectIdentifier::new(entry_oid.to_vec())
+        .ok_or_else(|| Error::BaseOid(entry_oid.iter().map(|x| format!("{x}.")).collect()))?;
+    'walk: loop {
+        let varbinds = interface.bulk(next.clone()).await?;
+        if varbinds.is_empty() {
+            break;
+        }
+        for varbind in varbinds {
+            // stops at the end of the table, or if the agent stops making progress
+            if !varbind.name.starts_with(entry_oid)
+                || varbind.name.len() <= entry_oid.len() + 1
+                || varbind.name[..] <= next[..]
+            {
+                break 'walk;
+            }
+            let VarBindValue::Value(value) = varbind.value else {
+                break 'walk;
+            };
+            rows.entry(varbind.name[entry_oid.len() + 1..].to_vec())
+                .or_default()
+                .set_column(varbind.name[entry_oid.len()], value)?;
+            next = varbind.name;
+        }
+    }
+    Ok(rows)
+}
+
 impl<T: SnmpInterface + Send + Sync> GetSnmpInterface for T {
     type Interface = T;
 
@@ -56,20 +246,8 @@ impl<T: SnmpInterface + Send + Sync> GetSnmpInterface for T {
 }
 
 // ----------------------------------- GENERATED ------------------------------------
-// Per MIB
-
+// Generated from EXAMPLE-MIB.mib in the crate's root, with ip_address implemented by the ReadIpAddress trait
 declare_mib!("EXAMPLE-MIB.mib");
-declare_oid!("ipAddress", IpAddress);
-
-// Per OID per MIB
-impl<'a, I: SnmpInterface + Send + Sync> ReadIpAddress for ExampleMib<'a, I> {
-    type Converter = prelude::DefaultConverter;
-    const OID: ConstOid = ConstOid(&[1_u32, 3_u32, 6_u32, 1_u32, 4_u32, 1_u32]);
-}
-
-//     where ip_address is implemented by trait GetIpAddressOid generated from an OID called ipAddress
-//     this trait is implemented on MyMibName
-//     this method returns IpAddress
 
 async fn x<T: SnmpInterface>(mut x: T) {
     let _ = x.example_mib().ip_address().await.unwrap();
@@ -103,10 +281,66 @@ pub enum Error {
     PrivNoAuth,
     #[error("secret key is too short (expected 8, got {})", .0)]
     SecretKeyIsTooShort(usize),
+    #[error("key is of an invalid length (expected {}, got {})", .0, .1)]
+    KeyLength(usize, usize),
+    #[error("engine ID must be between 5 and 32 octets long (got {})", .0)]
+    EngineIdLength(usize),
+    #[error("privacy parameters are of an invalid length (expected {}, got {})", .0, .1)]
+    PrivacyParamsLength(usize, usize),
     #[error("hash key is of an invalid length")]
     HashKeyInvalidLength,
     #[error("the incoming message did not match the expected hash")]
     IncomingAuthFail,
+    #[error("authentication parameters are of an invalid length (expected {}, got {})", .0, .1)]
+    AuthParamsLength(usize, usize),
+    #[error("received an encrypted PDU but no privacy protocol is configured")]
+    UnexpectedEncryptedPdu,
+    #[error("the message is not a well formed SNMP message")]
+    MalformedMessage,
+    #[error("unsupported SNMP message version: {}", .0)]
+    UnsupportedVersion(u64),
+    #[error("the message's security model is not supported")]
+    UnsupportedSecurityModel,
+    #[error("the message's security level is not supported for its user")]
+    UnsupportedSecurityLevel,
+    #[error("the message is outside of the authoritative engine's time window")]
+    NotInTimeWindow,
+    #[error("the message could not be decrypted")]
+    DecryptionError,
+    #[error("the authoritative engine ID is not known")]
+    UnknownEngineId,
+    #[error("the community is not accepted")]
+    UnknownCommunity,
+    #[error("the user name is not known")]
+    UnknownUserName,
+    #[error("the request's context is not known to this engine")]
+    UnknownContext,
+    #[error("received a PDU of an unexpected type")]
+    UnexpectedPdu,
+    #[error("the notification does not start with sysUpTime.0 and snmpTrapOID.0")]
+    MalformedNotification,
+    #[error("SNMPv1 does not support informs")]
+    InformV1,
+    #[error("the response does not fit within the largest message the requester accepts")]
+    ResponseTooBig,
+    #[error("timed out waiting for a response")]
+    Timeout,
+    #[error("the agent responded with error-status {} at index {}", .0, .1)]
+    ResponseError(u32, u32),
+    #[error("the agent has no value for the requested instance")]
+    NoSuchInstance,
+    #[error("a handler is already registered on an overlapping subtree")]
+    OverlappingHandler,
+    #[error("the AgentX master agent responded with error {}", .0)]
+    AgentX(u16),
+    #[error("AgentX payloads must be a multiple of 4 octets long and at most {} octets (got {})", .0, .1)]
+    AgentXPayloadLength(u32, u32),
+    #[error("AgentX OIDs can have at most 255 sub-identifiers after the prefix (got {})", .0)]
+    AgentXOidLength(usize),
+    #[error("the AgentX master agent closed the session")]
+    AgentXClosed,
+    #[error("I/O error: {}", .0)]
+    Io(#[from] std::io::Error),
     #[error("failed to encode ASN: {}", .0)]
     AsnEncode(rasn::ber::enc::Error),
     #[error("failed to decode ASN: {}", .0)]
@@ -125,9 +359,156 @@ impl From<InvalidVariant> for Error {
 
 #[cfg(test)]
 mod tests {
+    use snmp::v2::VarBind;
+
+    use super::*;
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    /// Answers GetBulk requests two variable bindings at a time from a sorted list.
+    struct Walk(Vec<(Vec<u32>, u32)>);
+
+    #[async_trait::async_trait]
+    impl SnmpInterface for Walk {
+        async fn read(&mut self, _: ObjectIdentifier) -> Result<ObjectSyntax, Error> {
+            Err(Error::UnexpectedPdu)
+        }
+
+        async fn write(&mut self, _: ObjectIdentifier, _: ObjectSyntax) -> Result<(), Error> {
+            Err(Error::UnexpectedPdu)
+        }
+
+        async fn bulk(&mut self, oid: ObjectIdentifier) -> Result<VarBindList, Error> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|(name, _)| name[..] > oid[..])
+                .take(2)
+                .map(|(name, value)| VarBind {
+                    name: ObjectIdentifier::new_unchecked(name.clone().into()),
+                    value: VarBindValue::Value((*value).try_into().unwrap()),
+                })
+                .collect())
+        }
+    }
+
+    #[derive(Default)]
+    struct Row(Vec<(u32, u32)>);
+
+    impl TableRow for Row {
+        fn set_column(&mut self, column: u32, value: ObjectSyntax) -> Result<(), Error> {
+            self.0.push((column, value.try_into()?));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_table() {
+        let mut walk = Walk(vec![
+            (vec![1, 3, 6, 1, 1, 1, 1], 1),
+            (vec![1, 3, 6, 1, 1, 1, 2], 2),
+            (vec![1, 3, 6, 1, 1, 2, 1], 10),
+            (vec![1, 3, 6, 1, 1, 2, 2], 20),
+            (vec![1, 3, 6, 1, 1, 3, 2], 200),
+            (vec![1, 3, 6, 1, 2], 0),
+        ]);
+        let rows =
+            super::fetch_table::<u32, Row, prelude::DefaultConverter>(&mut walk, &[1, 3, 6, 1, 1])
+                .await
+                .unwrap();
+        let rows = rows
+            .into_iter()
+            .map(|(index, row)| (index, row.0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                (1, vec![(1, 1), (2, 10)]),
+                (2, vec![(1, 2), (2, 20), (3, 200)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_joined_table() {
+        let mut walk = Walk(vec![
+            (vec![1, 3, 6, 1, 1, 1, 1], 1),
+            (vec![1, 3, 6, 1, 1, 1, 2], 2),
+            (vec![1, 3, 6, 1, 2, 1, 2], 20),
+            (vec![1, 3, 6, 1, 3], 0),
+        ]);
+        let rows = super::fetch_joined_table::<u32, Row, Row, prelude::DefaultConverter>(
+            &mut walk,
+            &[1, 3, 6, 1, 1],
+            &[1, 3, 6, 1, 2],
+        )
+        .await
+        .unwrap();
+        let rows = rows
+            .into_iter()
+            .map(|(index, row, other)| (index, row.0, other.map(|x| x.0)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                (1, vec![(1, 1)], None),
+                (2, vec![(1, 2)], Some(vec![(1, 20)]))
+            ]
+        );
+    }
+
+    /// Records the requests it is sent.
+    #[derive(Default)]
+    struct Writes(Vec<(Vec<u32>, ObjectSyntax)>);
+
+    #[async_trait::async_trait]
+    impl SnmpInterface for Writes {
+        async fn read(&mut self, _: ObjectIdentifier) -> Result<ObjectSyntax, Error> {
+            Err(Error::UnexpectedPdu)
+        }
+
+        async fn write(&mut self, oid: ObjectIdentifier, value: ObjectSyntax) -> Result<(), Error> {
+            self.0.push((oid.to_vec(), value));
+            Ok(())
+        }
+
+        async fn bulk(&mut self, _: ObjectIdentifier) -> Result<VarBindList, Error> {
+            Err(Error::UnexpectedPdu)
+        }
+    }
+
+    #[tokio::test]
+    async fn create_row() {
+        let mut writes = Writes::default();
+        super::create_row::<u32, prelude::DefaultConverter>(
+            &mut writes,
+            &[1, 3, 6, 1, 1],
+            3,
+            7,
+            vec![(2, 20_u32.try_into().unwrap())],
+        )
+        .await
+        .unwrap();
+        super::destroy_row::<u32, prelude::DefaultConverter>(&mut writes, &[1, 3, 6, 1, 1], 3, 7)
+            .await
+            .unwrap();
+        let writes = writes
+            .0
+            .into_iter()
+            .map(|(oid, value)| (oid, u32::try_from(value).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            writes,
+            vec![
+                (vec![1, 3, 6, 1, 1, 3, 7], 5),
+                (vec![1, 3, 6, 1, 1, 2, 7], 20),
+                (vec![1, 3, 6, 1, 1, 3, 7], 1),
+                (vec![1, 3, 6, 1, 1, 3, 7], 6),
+            ]
+        );
+    }
 }
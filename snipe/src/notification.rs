@@ -0,0 +1,983 @@
+//! Receiving of SNMP notifications: SNMPv1 Traps, and SNMPv2c/SNMPv3 Traps and InformRequests.
+
+use std::net::SocketAddr;
+
+use rasn::types::{ConstOid, ObjectIdentifier, OctetString};
+use rasn_smi::v2::{ApplicationSyntax, ObjectSyntax, SimpleSyntax, TimeTicks};
+use rasn_snmp::{
+    v1 as snmp_v1,
+    v2::{Pdu, Pdus, Response, VarBind, VarBindList, VarBindValue},
+    v2c,
+    v3::{self, ScopedPdu},
+};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use crate::{
+    oid,
+    rfc3412::{check_security_model, message_version, MessageFlags, SnmpVersion},
+    rfc3414::{
+        drop_message, encode_response, process_authoritative, process_incoming, Dropped,
+        LocalEngine, RemoteEngines, UsmStats, UsmUser, UsmUserStore,
+    },
+    rfc3584::trap_to_v2,
+};
+
+/// sysUpTime.0, from SNMPv2-MIB. This is the first variable binding of every SNMPv2 notification.
+pub const SYS_UP_TIME: ConstOid = oid!("1.3.6.1.2.1.1.3.0");
+
+/// snmpTrapOID.0, from SNMPv2-MIB. This is the second variable binding of every SNMPv2 notification.
+pub const SNMP_TRAP_OID: ConstOid = oid!("1.3.6.1.6.3.1.1.4.1.0");
+
+/// snmpTraps, from SNMPv2-MIB. The generic traps (coldStart, warmStart, ...) are defined under this OID.
+pub const SNMP_TRAPS: ConstOid = oid!("1.3.6.1.6.3.1.1.5");
+
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 65507;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// An unconfirmed notification: an SNMPv1 Trap-PDU or an SNMPv2-Trap-PDU.
+    Trap,
+    /// A confirmed notification, which the sender expects a Response to.
+    Inform,
+}
+
+/// A notification received from an agent, in SNMPv2 form regardless of the version it was sent with.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// The address the notification was sent from.
+    pub source: SocketAddr,
+    pub version: SnmpVersion,
+    /// The community for SNMPv1 and SNMPv2c notifications, or the user name for SNMPv3 notifications.
+    pub security_name: OctetString,
+    /// The contextEngineID of an SNMPv3 notification. Empty for other versions.
+    pub context_engine_id: OctetString,
+    /// The contextName of an SNMPv3 notification. Empty for other versions.
+    pub context_name: OctetString,
+    pub kind: NotificationKind,
+    pub request_id: i32,
+    /// The value of sysUpTime.0 when the notification was sent.
+    pub uptime: TimeTicks,
+    /// The value of snmpTrapOID.0, which identifies the notification.
+    pub trap_oid: ObjectIdentifier,
+    /// The variable bindings following sysUpTime.0 and snmpTrapOID.0.
+    pub varbinds: VarBindList,
+}
+
+/// A notification defined in a MIB, as generated for each NOTIFICATION-TYPE and TRAP-TYPE along with its objects.
+/// Objects are set one at a time from the variable bindings of a received notification.
+pub trait NotificationType: Default {
+    /// The notification's snmpTrapOID.
+    const OID: ConstOid;
+
+    /// Sets the object with the given instance OID. Objects which aren't part of the notification are ignored.
+    fn set_object(&mut self, oid: &[u32], value: ObjectSyntax) -> Result<(), crate::Error>;
+}
+
+/// The parts of a [`Notification`] taken from its PDU.
+struct NotificationPdu {
+    kind: NotificationKind,
+    request_id: i32,
+    uptime: TimeTicks,
+    trap_oid: ObjectIdentifier,
+    varbinds: VarBindList,
+}
+
+impl TryFrom<Pdus> for NotificationPdu {
+    type Error = crate::Error;
+
+    fn try_from(value: Pdus) -> Result<Self, Self::Error> {
+        let (kind, pdu) = match value {
+            Pdus::Trap(trap) => (NotificationKind::Trap, trap.0),
+            Pdus::InformRequest(inform) => (NotificationKind::Inform, inform.0),
+            _ => return Err(crate::Error::UnexpectedPdu),
+        };
+        Self::from_varbinds(kind, pdu.request_id, pdu.variable_bindings)
+    }
+}
+
+impl NotificationPdu {
+    fn from_varbinds(
+        kind: NotificationKind,
+        request_id: i32,
+        variable_bindings: VarBindList,
+    ) -> Result<Self, crate::Error> {
+        let mut varbinds = variable_bindings.into_iter();
+        let uptime = match varbinds.next() {
+            Some(VarBind {
+                name,
+                value:
+                    VarBindValue::Value(ObjectSyntax::ApplicationWide(ApplicationSyntax::Ticks(ticks))),
+            }) if name[..] == SYS_UP_TIME.0[..] => ticks,
+            _ => return Err(crate::Error::MalformedNotification),
+        };
+        let trap_oid = match varbinds.next() {
+            Some(VarBind {
+                name,
+                value: VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::ObjectId(oid))),
+            }) if name[..] == SNMP_TRAP_OID.0[..] => oid,
+            _ => return Err(crate::Error::MalformedNotification),
+        };
+
+        Ok(Self {
+            kind,
+            request_id,
+            uptime,
+            trap_oid,
+            varbinds: varbinds.collect(),
+        })
+    }
+
+    fn from_v1_trap(trap: snmp_v1::Trap, community: &OctetString) -> Result<Self, crate::Error> {
+        Self::from_varbinds(NotificationKind::Trap, 0, trap_to_v2(trap, community)?)
+    }
+
+    fn into_notification(
+        self,
+        source: SocketAddr,
+        version: SnmpVersion,
+        security_name: OctetString,
+        context_engine_id: OctetString,
+        context_name: OctetString,
+    ) -> Notification {
+        Notification {
+            source,
+            version,
+            security_name,
+            context_engine_id,
+            context_name,
+            kind: self.kind,
+            request_id: self.request_id,
+            uptime: self.uptime,
+            trap_oid: self.trap_oid,
+            varbinds: self.varbinds,
+        }
+    }
+}
+
+impl Notification {
+    /// The full variable bindings of the notification, starting with sysUpTime.0 and snmpTrapOID.0.
+    pub fn variable_bindings(&self) -> VarBindList {
+        [
+            VarBind {
+                name: SYS_UP_TIME.into(),
+                value: VarBindValue::Value(ObjectSyntax::ApplicationWide(
+                    ApplicationSyntax::Ticks(self.uptime),
+                )),
+            },
+            VarBind {
+                name: SNMP_TRAP_OID.into(),
+                value: VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::ObjectId(
+                    self.trap_oid.clone(),
+                ))),
+            },
+        ]
+        .into_iter()
+        .chain(self.varbinds.iter().cloned())
+        .collect()
+    }
+
+    /// Reads the objects of a notification defined in a MIB, or returns `None` if this is a different notification.
+    pub fn objects<T: NotificationType>(&self) -> Result<Option<T>, crate::Error> {
+        if self.trap_oid[..] != T::OID.0[..] {
+            return Ok(None);
+        }
+        let mut ret = T::default();
+        for varbind in &self.varbinds {
+            if let VarBindValue::Value(value) = &varbind.value {
+                ret.set_object(&varbind.name, value.clone())?;
+            }
+        }
+        Ok(Some(ret))
+    }
+
+    fn response_pdu(&self) -> Pdus {
+        Pdus::Response(Response(Pdu {
+            request_id: self.request_id,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings: self.variable_bindings(),
+        }))
+    }
+}
+
+/// The outcome of processing a datagram: what to return to the caller, and what to send back to the datagram's source.
+struct Processed {
+    result: Result<Option<Notification>, crate::Error>,
+    reply: Option<Vec<u8>>,
+}
+
+impl From<crate::Error> for Processed {
+    fn from(value: crate::Error) -> Self {
+        Processed {
+            result: Err(value),
+            reply: None,
+        }
+    }
+}
+
+impl From<Dropped> for Processed {
+    fn from(value: Dropped) -> Self {
+        Processed {
+            result: Err(value.error),
+            reply: value.report,
+        }
+    }
+}
+
+/// Receives notifications on a UDP port.
+///
+/// SNMPv1 and SNMPv2c notifications are only accepted for the configured communities, and SNMPv3 notifications only for
+/// the configured users, authenticated and decrypted according to their credentials. SNMPv3 users are looked up by
+/// the sending engine's ID and the user name, see [`UsmUserStore`].
+///
+/// Authenticated SNMPv3 traps must be within the time window of their sending engine, as learned from the traps it
+/// sent before (RFC 3414 section 3.2 step 7b), so that they can't be replayed.
+///
+/// InformRequests are acknowledged automatically once they have passed security processing. For SNMPv3 informs the
+/// receiver acts as the authoritative engine, answering discovery probes and timeliness failures with Reports as
+/// described in RFC 3414 section 4.
+pub struct NotificationReceiver {
+    socket: UdpSocket,
+    communities: Vec<OctetString>,
+    users: UsmUserStore,
+    engine: LocalEngine,
+    remote_engines: RemoteEngines,
+    stats: UsmStats,
+    buf: Box<[u8]>,
+}
+
+impl NotificationReceiver {
+    /// Binds a receiver to the given address, with a randomly generated engine ID. Use
+    /// [`NotificationReceiver::with_engine`] if SNMPv3 senders are configured with this receiver's engine ID.
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, crate::Error> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr).await?,
+            communities: vec![],
+            users: UsmUserStore::new(),
+            engine: LocalEngine::new(LocalEngine::random_engine_id(), 1)?,
+            remote_engines: RemoteEngines::new(),
+            stats: UsmStats::default(),
+            buf: vec![0_u8; MAX_DATAGRAM_SIZE].into_boxed_slice(),
+        })
+    }
+
+    /// Accepts SNMPv1 and SNMPv2c notifications sent with the given community.
+    pub fn with_community(mut self, community: impl Into<OctetString>) -> Self {
+        self.communities.push(community.into());
+        self
+    }
+
+    /// Accepts SNMPv3 notifications sent by the given user from any engine.
+    pub fn with_user(mut self, user: UsmUser) -> Result<Self, crate::Error> {
+        self.users.insert_wildcard(user)?;
+        Ok(self)
+    }
+
+    /// Accepts SNMPv3 notifications sent by the given user from the given engine only. For informs, this is the
+    /// receiver's own engine.
+    pub fn with_engine_user(
+        mut self,
+        engine_id: impl Into<OctetString>,
+        user: UsmUser,
+    ) -> Result<Self, crate::Error> {
+        self.users.insert(engine_id, user)?;
+        Ok(self)
+    }
+
+    /// Sets the engine the receiver uses as the authoritative engine for SNMPv3 informs.
+    pub fn with_engine(mut self, engine: LocalEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn engine(&self) -> &LocalEngine {
+        &self.engine
+    }
+
+    pub fn stats(&self) -> &UsmStats {
+        &self.stats
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, crate::Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Waits for the next notification, acknowledging it if it is an inform. An error only concerns the datagram that
+    /// caused it, so the caller can keep receiving after logging it.
+    pub async fn recv(&mut self) -> Result<Notification, crate::Error> {
+        loop {
+            let (len, source) = self.socket.recv_from(&mut self.buf[..]).await?;
+            let processed = self.process(&self.buf[..len], source);
+            if let Some(reply) = processed.reply {
+                self.socket.send_to(&reply, source).await?;
+            }
+            if let Some(result) = processed.result.transpose() {
+                return result;
+            }
+        }
+    }
+
+    fn check_community(&self, community: &OctetString) -> Result<(), crate::Error> {
+        if self.communities.contains(community) {
+            Ok(())
+        } else {
+            Err(crate::Error::UnknownCommunity)
+        }
+    }
+
+    /// Decodes a notification from a received datagram, without sending any replies.
+    pub fn decode(&self, data: &[u8], source: SocketAddr) -> Result<Notification, crate::Error> {
+        self.process(data, source)
+            .result?
+            .ok_or(crate::Error::UnknownEngineId)
+    }
+
+    fn process(&self, data: &[u8], source: SocketAddr) -> Processed {
+        let version = match message_version(data) {
+            Ok(x) => x,
+            Err(e) => return e.into(),
+        };
+        let processed = match version {
+            SnmpVersion::V1 => self.process_v1(data, source),
+            SnmpVersion::V2c => self.process_v2c(data, source),
+            SnmpVersion::V3 => return self.process_v3(data, source),
+        };
+        match processed {
+            Ok(x) => x,
+            Err(e) => e.into(),
+        }
+    }
+
+    fn process_v1(&self, data: &[u8], source: SocketAddr) -> Result<Processed, crate::Error> {
+        let msg: snmp_v1::Message<snmp_v1::Pdus> =
+            rasn::ber::decode(data).map_err(crate::Error::AsnDecode)?;
+        self.check_community(&msg.community)?;
+        let pdu = match msg.data {
+            snmp_v1::Pdus::Trap(trap) => NotificationPdu::from_v1_trap(trap, &msg.community)?,
+            _ => return Err(crate::Error::UnexpectedPdu),
+        };
+        let notification = pdu.into_notification(
+            source,
+            SnmpVersion::V1,
+            msg.community,
+            OctetString::default(),
+            OctetString::default(),
+        );
+        Ok(Processed {
+            result: Ok(Some(notification)),
+            reply: None,
+        })
+    }
+
+    fn process_v2c(&self, data: &[u8], source: SocketAddr) -> Result<Processed, crate::Error> {
+        let msg: v2c::Message<Pdus> = rasn::ber::decode(data).map_err(crate::Error::AsnDecode)?;
+        self.check_community(&msg.community)?;
+        let notification = NotificationPdu::try_from(msg.data)?.into_notification(
+            source,
+            SnmpVersion::V2c,
+            msg.community,
+            OctetString::default(),
+            OctetString::default(),
+        );
+
+        let reply = match notification.kind {
+            NotificationKind::Trap => None,
+            NotificationKind::Inform => Some(
+                rasn::ber::encode(&v2c::Message {
+                    version: msg.version,
+                    community: notification.security_name.clone(),
+                    data: notification.response_pdu(),
+                })
+                .map_err(crate::Error::AsnEncode)?,
+            ),
+        };
+        Ok(Processed {
+            result: Ok(Some(notification)),
+            reply,
+        })
+    }
+
+    fn process_v3(&self, data: &[u8], source: SocketAddr) -> Processed {
+        let msg: v3::Message = match rasn::ber::decode(data) {
+            Ok(x) => x,
+            Err(e) => return crate::Error::AsnDecode(e).into(),
+        };
+        let flags = match check_security_model(&msg)
+            .and_then(|_| MessageFlags::try_from(&msg.global_data.flags))
+        {
+            Ok(x) => x,
+            Err(e) => return e.into(),
+        };
+
+        // Traps are sent by their authoritative engine, whereas informs must be addressed to this engine. A reportable
+        // message for any other engine is a discovery probe, or was sent to the wrong engine.
+        let engine_id = &msg.security_parameters.authoritative_engine_id;
+        let authoritative = flags.reportable || engine_id == self.engine.engine_id();
+        let processed = if authoritative {
+            process_authoritative(data, msg.clone(), &self.engine, &self.users, &self.stats)
+        } else {
+            // captured traps could otherwise be replayed, so they must be within the time window of the sender
+            self.users
+                .get(&engine_id[..], &msg.security_parameters.user_name[..])
+                .and_then(|user| {
+                    let scoped_pdu = process_incoming(data, msg.clone(), &user)?;
+                    if flags.auth {
+                        self.users.cache_localized(&engine_id[..], &user);
+                        self.remote_engines
+                            .check_timeliness(&msg.security_parameters)?;
+                    }
+                    Ok((user, scoped_pdu))
+                })
+                .map_err(|e| drop_message(&self.engine, &self.stats, &msg, e, 0, None))
+        };
+        let (user, scoped_pdu) = match processed {
+            Ok(x) => x,
+            // a discovery probe, which is answered with a Report rather than being an error
+            Err(Dropped {
+                report: Some(report),
+                ..
+            }) if engine_id.is_empty() => {
+                return Processed {
+                    result: Ok(None),
+                    reply: Some(report),
+                };
+            }
+            Err(dropped) => return dropped.into(),
+        };
+
+        let notification = match NotificationPdu::try_from(scoped_pdu.data) {
+            Ok(x) => x.into_notification(
+                source,
+                SnmpVersion::V3,
+                msg.security_parameters.user_name.clone(),
+                scoped_pdu.engine_id,
+                scoped_pdu.name,
+            ),
+            Err(e) => return e.into(),
+        };
+        let reply = match notification.kind {
+            NotificationKind::Trap => None,
+            NotificationKind::Inform if !authoritative => {
+                return crate::Error::UnknownEngineId.into();
+            }
+            NotificationKind::Inform => {
+                let pdu = ScopedPdu {
+                    engine_id: notification.context_engine_id.clone(),
+                    name: notification.context_name.clone(),
+                    data: notification.response_pdu(),
+                };
+                match encode_response(&self.engine, &msg, &user, pdu) {
+                    Ok(x) => Some(x),
+                    Err(e) => return e.into(),
+                }
+            }
+        };
+        Processed {
+            result: Ok(Some(notification)),
+            reply,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rasn_smi::v1;
+    use rasn_snmp::{
+        v2::{InformRequest, Trap},
+        v3::{HeaderData, ScopedPduData, USMSecurityParameters},
+    };
+
+    use crate::{
+        rfc3414::{encode_outgoing, AuthProtocol, Credential, PrivProtocol},
+        rfc3584::SNMP_TRAP_ADDRESS,
+    };
+
+    const LINK_DOWN: [u32; 10] = [1, 3, 6, 1, 6, 3, 1, 1, 5, 3];
+    const IF_INDEX: [u32; 11] = [1, 3, 6, 1, 2, 1, 2, 2, 1, 1, 2];
+
+    fn oid(x: &[u32]) -> ObjectIdentifier {
+        ObjectIdentifier::new_unchecked(x.to_vec().into())
+    }
+
+    fn notification_pdu(request_id: i32) -> Pdu {
+        Pdu {
+            request_id,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings: vec![
+                VarBind {
+                    name: SYS_UP_TIME.into(),
+                    value: VarBindValue::Value(ObjectSyntax::ApplicationWide(
+                        ApplicationSyntax::Ticks(TimeTicks(1234)),
+                    )),
+                },
+                VarBind {
+                    name: SNMP_TRAP_OID.into(),
+                    value: VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::ObjectId(oid(
+                        &LINK_DOWN,
+                    )))),
+                },
+                VarBind {
+                    name: oid(&IF_INDEX),
+                    value: VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::Integer(
+                        2.into(),
+                    ))),
+                },
+            ],
+        }
+    }
+
+    fn user() -> UsmUser {
+        UsmUser {
+            username: "trapper".into(),
+            auth_protocol: AuthProtocol::Sha1,
+            auth_key: Credential::Password("authpassword".into()),
+            priv_protocol: PrivProtocol::Aes,
+            priv_key: Credential::Password("privpassword".into()),
+        }
+    }
+
+    fn source() -> SocketAddr {
+        "192.0.2.1:162".parse().unwrap()
+    }
+
+    async fn receiver() -> NotificationReceiver {
+        NotificationReceiver::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_community("public")
+            .with_user(user())
+            .unwrap()
+    }
+
+    fn assert_link_down(notification: &Notification) {
+        assert_eq!(notification.uptime, TimeTicks(1234));
+        assert_eq!(notification.trap_oid, oid(&LINK_DOWN));
+        assert_eq!(notification.varbinds.len(), 1);
+        assert_eq!(notification.varbinds[0].name, oid(&IF_INDEX));
+    }
+
+    #[tokio::test]
+    async fn v2c_trap() {
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "public".into(),
+            data: Pdus::Trap(Trap(notification_pdu(7))),
+        })
+        .unwrap();
+
+        let receiver = receiver().await;
+        let notification = receiver.decode(&data, source()).unwrap();
+        assert_eq!(notification.version, SnmpVersion::V2c);
+        assert_eq!(notification.kind, NotificationKind::Trap);
+        assert_eq!(notification.request_id, 7);
+        assert_link_down(&notification);
+    }
+
+    #[derive(Default)]
+    struct LinkDown {
+        if_index: Option<(u32, i32)>,
+    }
+
+    impl NotificationType for LinkDown {
+        const OID: ConstOid = ConstOid(&LINK_DOWN);
+
+        fn set_object(&mut self, oid: &[u32], value: ObjectSyntax) -> Result<(), crate::Error> {
+            if let Some([index]) = oid.strip_prefix(&IF_INDEX[..10]) {
+                self.if_index = Some((*index, value.try_into()?));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn objects() {
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "public".into(),
+            data: Pdus::Trap(Trap(notification_pdu(7))),
+        })
+        .unwrap();
+
+        let notification = receiver().await.decode(&data, source()).unwrap();
+        let link_down = notification.objects::<LinkDown>().unwrap().unwrap();
+        assert_eq!(link_down.if_index, Some((2, 2)));
+
+        let mut notification = notification;
+        notification.trap_oid = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 4]);
+        assert!(notification.objects::<LinkDown>().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn v2c_unknown_community() {
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "private".into(),
+            data: Pdus::InformRequest(InformRequest(notification_pdu(7))),
+        })
+        .unwrap();
+
+        assert!(matches!(
+            receiver().await.decode(&data, source()),
+            Err(crate::Error::UnknownCommunity)
+        ));
+    }
+
+    #[tokio::test]
+    async fn v1_trap() {
+        let data = rasn::ber::encode(&snmp_v1::Message {
+            version: 0.into(),
+            community: "public".into(),
+            data: snmp_v1::Pdus::Trap(snmp_v1::Trap {
+                enterprise: oid(&[1, 3, 6, 1, 4, 1, 8072]),
+                agent_addr: v1::NetworkAddress::Internet(v1::IpAddress([192, 0, 2, 1][..].into())),
+                generic_trap: 6.into(),
+                specific_trap: 42.into(),
+                time_stamp: TimeTicks(1234),
+                variable_bindings: vec![],
+            }),
+        })
+        .unwrap();
+
+        let notification = receiver().await.decode(&data, source()).unwrap();
+        assert_eq!(notification.version, SnmpVersion::V1);
+        assert_eq!(notification.uptime, TimeTicks(1234));
+        assert_eq!(notification.trap_oid, oid(&[1, 3, 6, 1, 4, 1, 8072, 0, 42]));
+        assert_eq!(notification.varbinds[0].name, SNMP_TRAP_ADDRESS.into());
+        assert_eq!(
+            notification.varbinds[0].value,
+            VarBindValue::Value(ObjectSyntax::ApplicationWide(ApplicationSyntax::Address(
+                v1::IpAddress([192, 0, 2, 1][..].into())
+            )))
+        );
+    }
+
+    const SENDER_ENGINE_ID: [u8; 9] = [0x80, 0x00, 0x1f, 0x88, 0x04, 0x74, 0x65, 0x73, 0x74];
+
+    fn v3_message(
+        user_name: &str,
+        flags: u8,
+        (engine_id, boots, time): (&[u8], u32, u32),
+        pdu: Pdus,
+    ) -> v3::Message {
+        v3::Message {
+            version: 3.into(),
+            global_data: HeaderData {
+                message_id: 1.into(),
+                max_size: 65507.into(),
+                flags: vec![flags].into(),
+                security_model: 3.into(),
+            },
+            security_parameters: USMSecurityParameters {
+                authoritative_engine_id: engine_id.to_vec().into(),
+                authoritative_engine_boots: boots.into(),
+                authoritative_engine_time: time.into(),
+                user_name: user_name.into(),
+                authentication_parameters: OctetString::default(),
+                privacy_parameters: OctetString::default(),
+            },
+            scoped_data: ScopedPduData::CleartextPdu(ScopedPdu {
+                engine_id: engine_id.to_vec().into(),
+                name: OctetString::default(),
+                data: pdu,
+            }),
+        }
+    }
+
+    fn v3_trap(user_name: &str) -> v3::Message {
+        v3_message(
+            user_name,
+            0b011,
+            (&SENDER_ENGINE_ID, 1, 100),
+            Pdus::Trap(Trap(notification_pdu(9))),
+        )
+    }
+
+    #[tokio::test]
+    async fn v3_auth_priv_trap() {
+        let data = encode_outgoing(v3_trap("trapper"), &user(), 0x0102_0304_0506_0708).unwrap();
+
+        let notification = receiver().await.decode(&data, source()).unwrap();
+        assert_eq!(notification.version, SnmpVersion::V3);
+        assert_eq!(notification.security_name, OctetString::from("trapper"));
+        assert_link_down(&notification);
+    }
+
+    #[tokio::test]
+    async fn v3_tampered_trap() {
+        let mut data = encode_outgoing(v3_trap("trapper"), &user(), 1).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+
+        assert!(matches!(
+            receiver().await.decode(&data, source()),
+            Err(crate::Error::IncomingAuthFail)
+        ));
+    }
+
+    #[tokio::test]
+    async fn v3_replayed_trap() {
+        let receiver = receiver().await;
+        let trap = |boots, time| {
+            let msg = v3_message(
+                "trapper",
+                0b011,
+                (&SENDER_ENGINE_ID, boots, time),
+                Pdus::Trap(Trap(notification_pdu(9))),
+            );
+            encode_outgoing(msg, &user(), 1).unwrap()
+        };
+        let old = trap(1, 100);
+
+        assert_link_down(&receiver.decode(&old, source()).unwrap());
+        assert_link_down(&receiver.decode(&trap(2, 10), source()).unwrap());
+        // from before the sender rebooted
+        assert!(matches!(
+            receiver.decode(&old, source()),
+            Err(crate::Error::NotInTimeWindow)
+        ));
+        assert_link_down(&receiver.decode(&trap(2, 1000), source()).unwrap());
+        // more than 150 seconds older than the latest trap
+        assert!(matches!(
+            receiver.decode(&trap(2, 800), source()),
+            Err(crate::Error::NotInTimeWindow)
+        ));
+        assert_link_down(&receiver.decode(&trap(2, 900), source()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn v3_unknown_security_model() {
+        let mut msg = v3_trap("trapper");
+        msg.global_data.security_model = 2.into();
+        let data = rasn::ber::encode(&msg).unwrap();
+
+        assert!(matches!(
+            receiver().await.decode(&data, source()),
+            Err(crate::Error::UnsupportedSecurityModel)
+        ));
+    }
+
+    #[tokio::test]
+    async fn v3_unknown_user() {
+        let data = rasn::ber::encode(&v3_trap("nobody")).unwrap();
+
+        assert!(matches!(
+            receiver().await.decode(&data, source()),
+            Err(crate::Error::UnknownUserName)
+        ));
+    }
+
+    #[tokio::test]
+    async fn v3_unknown_engine() {
+        let receiver = NotificationReceiver::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_engine_user(
+                &[0x80, 0x00, 0x1f, 0x88, 0x04, 0x6f, 0x74, 0x68, 0x72][..],
+                user(),
+            )
+            .unwrap();
+        let data = encode_outgoing(v3_trap("trapper"), &user(), 1).unwrap();
+
+        assert!(matches!(
+            receiver.decode(&data, source()),
+            Err(crate::Error::UnknownEngineId)
+        ));
+        assert_eq!(
+            receiver
+                .stats()
+                .unknown_engine_ids
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+
+        let receiver = receiver
+            .with_engine_user(&SENDER_ENGINE_ID[..], user())
+            .unwrap();
+        assert_link_down(&receiver.decode(&data, source()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn recv_over_udp() {
+        let mut receiver = receiver().await;
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "public".into(),
+            data: Pdus::Trap(Trap(notification_pdu(3))),
+        })
+        .unwrap();
+        sender
+            .send_to(&data, receiver.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let notification = receiver.recv().await.unwrap();
+        assert_eq!(notification.source, sender.local_addr().unwrap());
+        assert_link_down(&notification);
+    }
+
+    fn decode_v3_reply(reply: &[u8]) -> (v3::Message, Pdus) {
+        let msg: v3::Message = rasn::ber::decode(reply).unwrap();
+        let pdu = match process_incoming(reply, msg.clone(), &user()) {
+            Ok(pdu) => pdu.data,
+            // replies which are not sent at the user's security level
+            Err(_) => match &msg.scoped_data {
+                ScopedPduData::CleartextPdu(pdu) => pdu.data.clone(),
+                ScopedPduData::EncryptedPdu(_) => panic!("reply is encrypted"),
+            },
+        };
+        (msg, pdu)
+    }
+
+    #[tokio::test]
+    async fn v2c_inform_is_acknowledged() {
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "public".into(),
+            data: Pdus::InformRequest(InformRequest(notification_pdu(11))),
+        })
+        .unwrap();
+
+        let processed = receiver().await.process(&data, source());
+        let notification = processed.result.unwrap().unwrap();
+        assert_eq!(notification.kind, NotificationKind::Inform);
+        let reply: v2c::Message<Pdus> = rasn::ber::decode(&processed.reply.unwrap()).unwrap();
+        match reply.data {
+            Pdus::Response(response) => {
+                assert_eq!(response.0.request_id, 11);
+                assert_eq!(
+                    response.0.variable_bindings,
+                    notification.variable_bindings()
+                );
+            }
+            _ => panic!("expected a response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn v2c_trap_is_not_acknowledged() {
+        let data = rasn::ber::encode(&v2c::Message {
+            version: 1.into(),
+            community: "public".into(),
+            data: Pdus::Trap(Trap(notification_pdu(11))),
+        })
+        .unwrap();
+
+        assert!(receiver().await.process(&data, source()).reply.is_none());
+    }
+
+    #[tokio::test]
+    async fn v3_discovery() {
+        let receiver = receiver().await;
+        let probe = v3_message(
+            "",
+            0b100,
+            (&[], 0, 0),
+            Pdus::GetRequest(rasn_snmp::v2::GetRequest(Pdu {
+                request_id: 21,
+                error_status: 0,
+                error_index: 0,
+                variable_bindings: vec![],
+            })),
+        );
+
+        let processed = receiver.process(&rasn::ber::encode(&probe).unwrap(), source());
+        assert!(matches!(processed.result, Ok(None)));
+        let (msg, pdu) = decode_v3_reply(&processed.reply.unwrap());
+        assert_eq!(
+            &msg.security_parameters.authoritative_engine_id,
+            receiver.engine().engine_id()
+        );
+        match pdu {
+            Pdus::Report(report) => {
+                assert_eq!(report.0.request_id, 21);
+                assert_eq!(
+                    report.0.variable_bindings[0].name[..],
+                    [1, 3, 6, 1, 6, 3, 15, 1, 1, 4, 0]
+                );
+            }
+            _ => panic!("expected a report"),
+        }
+    }
+
+    #[tokio::test]
+    async fn v3_unreportable_probe() {
+        // without the reportable flag there is no Report to send, so the message is an error like any other
+        let probe = v3_message(
+            "",
+            0b000,
+            (&[], 0, 0),
+            Pdus::Trap(Trap(notification_pdu(22))),
+        );
+
+        let processed = receiver()
+            .await
+            .process(&rasn::ber::encode(&probe).unwrap(), source());
+        assert!(matches!(
+            processed.result,
+            Err(crate::Error::UnknownUserName)
+        ));
+        assert!(processed.reply.is_none());
+    }
+
+    #[tokio::test]
+    async fn v3_inform_is_acknowledged() {
+        let receiver = receiver().await;
+        let engine = receiver.engine();
+        let inform = v3_message(
+            "trapper",
+            0b111,
+            (&engine.engine_id()[..], engine.boots(), engine.time()),
+            Pdus::InformRequest(InformRequest(notification_pdu(31))),
+        );
+        let data = encode_outgoing(inform, &user(), 5).unwrap();
+
+        let processed = receiver.process(&data, source());
+        assert_eq!(
+            processed.result.unwrap().unwrap().kind,
+            NotificationKind::Inform
+        );
+        let (msg, pdu) = decode_v3_reply(&processed.reply.unwrap());
+        assert_eq!(msg.global_data.flags, OctetString::from(vec![0b011]));
+        match pdu {
+            Pdus::Response(response) => assert_eq!(response.0.request_id, 31),
+            _ => panic!("expected a response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn v3_inform_not_in_time_window() {
+        let receiver = receiver().await;
+        let engine = receiver.engine();
+        let inform = v3_message(
+            "trapper",
+            0b111,
+            (&engine.engine_id()[..], engine.boots() + 1, 0),
+            Pdus::InformRequest(InformRequest(notification_pdu(41))),
+        );
+        let data = encode_outgoing(inform, &user(), 5).unwrap();
+
+        let processed = receiver.process(&data, source());
+        assert!(matches!(
+            processed.result,
+            Err(crate::Error::NotInTimeWindow)
+        ));
+        let (msg, pdu) = decode_v3_reply(&processed.reply.unwrap());
+        // the report is authenticated so the sender can trust the engine boots and time
+        assert_eq!(msg.global_data.flags, OctetString::from(vec![0b001]));
+        match pdu {
+            Pdus::Report(report) => assert_eq!(report.0.request_id, 41),
+            _ => panic!("expected a report"),
+        }
+        assert_eq!(
+            receiver
+                .stats()
+                .not_in_time_windows
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! Sending of SNMP notifications: SNMPv2 Traps and InformRequests, sent as SNMPv1, SNMPv2c or SNMPv3 messages.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use rasn::types::{ObjectIdentifier, OctetString};
+use rasn_smi::v2::{ApplicationSyntax, ObjectSyntax, SimpleSyntax, TimeTicks};
+use rasn_snmp::{
+    v1 as snmp_v1,
+    v2::{InformRequest, Pdu, Pdus, Trap, VarBind, VarBindList, VarBindValue},
+    v2c,
+    v3::{self, HeaderData, ScopedPdu, ScopedPduData, USMSecurityParameters},
+};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use crate::{
+    notification::{MAX_DATAGRAM_SIZE, SNMP_TRAP_OID, SYS_UP_TIME},
+    prelude::{DefaultConverter, SnmpConverter},
+    rfc3412::MessageFlags,
+    rfc3414::{
+        authenticate_incoming, check_engine_id, encode_outgoing, process_incoming, reported_error,
+        LocalEngine, UsmUser,
+    },
+    rfc3584::v2_to_trap,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_RETRIES: u32 = 3;
+
+/// A notification to send, identified by its snmpTrapOID.0 value. sysUpTime.0 is filled in when it is sent.
+#[derive(Debug, Clone)]
+pub struct NotificationBuilder {
+    trap_oid: ObjectIdentifier,
+    varbinds: VarBindList,
+}
+
+impl NotificationBuilder {
+    pub fn new(trap_oid: impl Into<ObjectIdentifier>) -> Self {
+        Self {
+            trap_oid: trap_oid.into(),
+            varbinds: vec![],
+        }
+    }
+
+    /// Appends a variable binding, converting the value with the [`DefaultConverter`].
+    pub fn value<T>(self, name: impl Into<ObjectIdentifier>, value: T) -> Result<Self, crate::Error>
+    where
+        DefaultConverter: SnmpConverter<T>,
+    {
+        self.value_with::<DefaultConverter, T>(name, value)
+    }
+
+    /// Appends a variable binding, converting the value with the given converter.
+    pub fn value_with<C: SnmpConverter<T>, T>(
+        mut self,
+        name: impl Into<ObjectIdentifier>,
+        value: T,
+    ) -> Result<Self, crate::Error> {
+        self.varbinds.push(VarBind {
+            name: name.into(),
+            value: VarBindValue::Value(C::try_to_snmp(value)?),
+        });
+        Ok(self)
+    }
+
+    /// Appends a variable binding as is.
+    pub fn varbind(mut self, varbind: VarBind) -> Self {
+        self.varbinds.push(varbind);
+        self
+    }
+
+    fn into_pdu(self, request_id: i32, uptime: TimeTicks) -> Pdu {
+        let header = [
+            VarBind {
+                name: SYS_UP_TIME.into(),
+                value: VarBindValue::Value(ObjectSyntax::ApplicationWide(
+                    ApplicationSyntax::Ticks(uptime),
+                )),
+            },
+            VarBind {
+                name: SNMP_TRAP_OID.into(),
+                value: VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::ObjectId(
+                    self.trap_oid,
+                ))),
+            },
+        ];
+        Pdu {
+            request_id,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings: header.into_iter().chain(self.varbinds).collect(),
+        }
+    }
+}
+
+/// The message version and security used to send notifications.
+pub enum NotificationSecurity {
+    /// SNMPv1 with the given community. Notifications are translated to SNMPv1 Trap-PDUs as described in RFC 3584
+    /// section 3.2, and informs are not supported.
+    V1(OctetString),
+    /// SNMPv2c with the given community.
+    V2c(OctetString),
+    /// SNMPv3 on behalf of the given user, at the security level configured for the user.
+    V3(UsmUser),
+}
+
+// The snmpEngineBoots and snmpEngineTime of an inform's target, as last learned from it.
+pub(crate) struct RemoteEngine {
+    pub(crate) engine_id: OctetString,
+    pub(crate) boots: u32,
+    pub(crate) time: u32,
+    pub(crate) synced: Instant,
+}
+
+impl RemoteEngine {
+    pub(crate) fn time(&self) -> u32 {
+        let elapsed = Instant::now().duration_since(self.synced).as_secs();
+        self.time
+            .saturating_add(elapsed.try_into().unwrap_or(u32::MAX))
+    }
+
+    pub(crate) fn sync(&mut self, security_params: &USMSecurityParameters) {
+        let boots = u32::try_from(security_params.authoritative_engine_boots.clone());
+        let time = u32::try_from(security_params.authoritative_engine_time.clone());
+        if let (Ok(boots), Ok(time)) = (boots, time) {
+            self.boots = boots;
+            self.time = time;
+            self.synced = Instant::now();
+        }
+    }
+}
+
+/// Sends notifications to a single manager over UDP.
+///
+/// SNMPv3 traps are sent with this originator's engine as the authoritative engine. SNMPv3 informs are sent to the
+/// target's engine, whose engine ID must be configured with [`NotificationOriginator::with_target_engine_id`] as
+/// discovery isn't performed; its boots and time are learnt from the authenticated notInTimeWindow Report it replies
+/// with the first time (RFC 3414 section 4).
+pub struct NotificationOriginator {
+    socket: UdpSocket,
+    target: SocketAddr,
+    security: NotificationSecurity,
+    engine: LocalEngine,
+    remote: Option<RemoteEngine>,
+    agent_addr: Ipv4Addr,
+    init_time: Instant,
+    request_id: i32,
+    message_id: i32,
+    timeout: Duration,
+    retries: u32,
+    buf: Box<[u8]>,
+}
+
+impl NotificationOriginator {
+    /// Binds an originator to the given local address, sending to `target`. The originator gets a randomly generated
+    /// engine ID, use [`NotificationOriginator::with_engine`] if managers are configured with its engine ID.
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A,
+        target: SocketAddr,
+        security: NotificationSecurity,
+    ) -> Result<Self, crate::Error> {
+        let socket = UdpSocket::bind(addr).await?;
+        let agent_addr = match socket.local_addr()?.ip() {
+            IpAddr::V4(x) => x,
+            IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+        };
+        Ok(Self {
+            socket,
+            target,
+            security,
+            engine: LocalEngine::new(LocalEngine::random_engine_id(), 1)?,
+            remote: None,
+            agent_addr,
+            init_time: Instant::now(),
+            request_id: 0,
+            message_id: 0,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            buf: vec![0_u8; MAX_DATAGRAM_SIZE].into_boxed_slice(),
+        })
+    }
+
+    /// Sets the engine the originator uses as the authoritative engine for SNMPv3 traps.
+    pub fn with_engine(mut self, engine: LocalEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Sets the engine ID of the target, which SNMPv3 informs are sent to.
+    pub fn with_target_engine_id(
+        mut self,
+        engine_id: impl Into<OctetString>,
+    ) -> Result<Self, crate::Error> {
+        let engine_id = engine_id.into();
+        check_engine_id(&engine_id[..])?;
+        self.remote = Some(RemoteEngine {
+            engine_id,
+            boots: 0,
+            time: 0,
+            synced: Instant::now(),
+        });
+        Ok(self)
+    }
+
+    /// Sets the agent-addr of SNMPv1 traps which don't contain snmpTrapAddress.0. Defaults to the address the originator
+    /// is bound to.
+    pub fn with_agent_addr(mut self, agent_addr: Ipv4Addr) -> Self {
+        self.agent_addr = agent_addr;
+        self
+    }
+
+    /// Sets how long to wait for the acknowledgement of an inform before resending it.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times an unacknowledged inform is resent.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn engine(&self) -> &LocalEngine {
+        &self.engine
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, crate::Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// The value of sysUpTime.0 sent with notifications: hundredths of a second since the originator was created.
+    pub fn uptime(&self) -> TimeTicks {
+        let elapsed = Instant::now().duration_since(self.init_time).as_millis() / 10;
+        TimeTicks(elapsed as u32)
+    }
+
+    fn next_request_id(&mut self) -> i32 {
+        self.request_id = self.request_id.checked_add(1).unwrap_or(1);
+        self.request_id
+    }
+
+    /// Sends a trap, which isn't acknowledged.
+    pub async fn trap(&mut self, notification: NotificationBuilder) -> Result<(), crate::Error> {
+        let uptime = self.uptime();
+        let data = if let NotificationSecurity::V1(community) = &self.security {
+            let pdu = notification.into_pdu(0, uptime);
+            let trap = v2_to_trap(pdu.variable_bindings, self.agent_addr)?;
+            rasn::ber::encode(&snmp_v1::Message {
+                version: 0.into(),
+                community: community.clone(),
+                data: snmp_v1::Pdus::Trap(trap),
+            })
+            .map_err(crate::Error::AsnEncode)?
+        } else {
+            let pdu = notification.into_pdu(self.next_request_id(), uptime);
+            self.encode(Pdus::Trap(Trap(pdu)))?
+        };
+        self.socket.send_to(&data, self.target).await?;
+        Ok(())
+    }
+
+    /// Sends an inform, waiting until the target acknowledges it. The inform is resent each time the timeout elapses,
+    /// up to the configured number of retries.
+    pub async fn inform(&mut self, notification: NotificationBuilder) -> Result<(), crate::Error> {
+        let request_id = self.next_request_id();
+        let pdu = notification.into_pdu(request_id, self.uptime());
+        let mut retries = 0;
+        let mut synced = false;
+        loop {
+            let data = self.encode(Pdus::InformRequest(InformRequest(pdu.clone())))?;
+            self.socket.send_to(&data, self.target).await?;
+            match self.await_response(request_id).await {
+                Err(crate::Error::Timeout) if retries < self.retries => retries += 1,
+                // the target's boots and time were updated from its report, so resend once with them
+                Err(crate::Error::NotInTimeWindow) if !synced => synced = true,
+                result => return result,
+            }
+        }
+    }
+
+    fn encode(&mut self, pdus: Pdus) -> Result<Vec<u8>, crate::Error> {
+        let user = match &self.security {
+            NotificationSecurity::V1(_) => return Err(crate::Error::InformV1),
+            NotificationSecurity::V2c(community) => {
+                return rasn::ber::encode(&v2c::Message {
+                    version: 1.into(),
+                    community: community.clone(),
+                    data: pdus,
+                })
+                .map_err(crate::Error::AsnEncode);
+            }
+            NotificationSecurity::V3(user) => user,
+        };
+
+        let inform = matches!(pdus, Pdus::InformRequest(_));
+        let mut security_parameters = USMSecurityParameters {
+            authoritative_engine_id: OctetString::default(),
+            authoritative_engine_boots: 0.into(),
+            authoritative_engine_time: 0.into(),
+            user_name: user.username.clone(),
+            authentication_parameters: OctetString::default(),
+            privacy_parameters: OctetString::default(),
+        };
+        if inform {
+            let remote = self.remote.as_ref().ok_or(crate::Error::UnknownEngineId)?;
+            security_parameters.authoritative_engine_id = remote.engine_id.clone();
+            security_parameters.authoritative_engine_boots = remote.boots.into();
+            security_parameters.authoritative_engine_time = remote.time().into();
+        } else {
+            self.engine.fill_security_params(&mut security_parameters);
+        }
+
+        // every retransmission gets a new msgID (RFC 3412 section 6.2)
+        self.message_id = self.message_id.checked_add(1).unwrap_or(1);
+        let msg = v3::Message {
+            version: 3.into(),
+            global_data: HeaderData {
+                message_id: self.message_id.into(),
+                max_size: (MAX_DATAGRAM_SIZE as u32).into(),
+                flags: MessageFlags {
+                    reportable: inform,
+                    ..user.security_level()
+                }
+                .try_into()?,
+                security_model: 3.into(),
+            },
+            security_parameters,
+            scoped_data: ScopedPduData::CleartextPdu(ScopedPdu {
+                engine_id: self.engine.engine_id().clone(),
+                name: OctetString::default(),
+                data: pdus,
+            }),
+        };
+        encode_outgoing(msg, user, self.engine.next_salt())
+    }
+
+    // Waits for the response to the last message sent, ignoring datagrams that aren't replies to it.
+    async fn await_response(&mut self, request_id: i32) -> Result<(), crate::Error> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            let (len, source) =
+                tokio::time::timeout_at(deadline, self.socket.recv_from(&mut self.buf[..]))
+                    .await
+                    .map_err(|_| crate::Error::Timeout)??;
+            if source != self.target {
+                continue;
+            }
+            let data = self.buf[..len].to_vec();
+            if let Some(result) = self.decode_response(&data, request_id) {
+                return result;
+            }
+        }
+    }
+
+    fn decode_response(
+        &mut self,
+        data: &[u8],
+        request_id: i32,
+    ) -> Option<Result<(), crate::Error>> {
+        let user = match &self.security {
+            NotificationSecurity::V1(_) => return None,
+            NotificationSecurity::V2c(community) => {
+                let msg: v2c::Message<Pdus> = rasn::ber::decode(data).ok()?;
+                return match msg.data {
+                    Pdus::Response(response)
+                        if msg.community == *community && response.0.request_id == request_id =>
+                    {
+                        Some(Ok(()))
+                    }
+                    _ => None,
+                };
+            }
+            NotificationSecurity::V3(user) => user,
+        };
+
+        let msg: v3::Message = rasn::ber::decode(data).ok()?;
+        if i32::try_from(msg.global_data.message_id.clone()).ok()? != self.message_id {
+            return None;
+        }
+
+        // Reports may be sent at a lower security level than the inform, so they are checked separately. Only
+        // authenticated reports are trusted to update the target's boots and time.
+        if let ScopedPduData::CleartextPdu(ScopedPdu {
+            data: Pdus::Report(report),
+            ..
+        }) = &msg.scoped_data
+        {
+            let flags = MessageFlags::try_from(&msg.global_data.flags).ok()?;
+            let authenticated =
+                flags.auth && authenticate_incoming(data, &msg.security_parameters, user).is_ok();
+            let error = reported_error(&report.0).unwrap_or(crate::Error::UnexpectedPdu);
+            if authenticated && matches!(error, crate::Error::NotInTimeWindow) {
+                if let Some(remote) = self.remote.as_mut() {
+                    remote.sync(&msg.security_parameters);
+                }
+            }
+            return Some(Err(error));
+        }
+
+        match process_incoming(data, msg, user) {
+            Ok(ScopedPdu {
+                data: Pdus::Response(response),
+                ..
+            }) if response.0.request_id == request_id => Some(Ok(())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        notification::{NotificationKind, NotificationReceiver},
+        rfc3412::SnmpVersion,
+        rfc3414::{AuthProtocol, Credential, PrivProtocol},
+    };
+
+    const LINK_DOWN: [u32; 10] = [1, 3, 6, 1, 6, 3, 1, 1, 5, 3];
+    const IF_INDEX: [u32; 11] = [1, 3, 6, 1, 2, 1, 2, 2, 1, 1, 2];
+    const ENTERPRISE_TRAP: [u32; 9] = [1, 3, 6, 1, 4, 1, 8072, 0, 3];
+
+    fn oid(x: &[u32]) -> ObjectIdentifier {
+        ObjectIdentifier::new_unchecked(x.to_vec().into())
+    }
+
+    fn user() -> UsmUser {
+        UsmUser {
+            username: "trapper".into(),
+            auth_protocol: AuthProtocol::Sha1,
+            auth_key: Credential::Password("authpassword".into()),
+            priv_protocol: PrivProtocol::Aes,
+            priv_key: Credential::Password("privpassword".into()),
+        }
+    }
+
+    fn link_down() -> NotificationBuilder {
+        NotificationBuilder::new(oid(&LINK_DOWN))
+            .value(oid(&IF_INDEX), 2_u32)
+            .unwrap()
+    }
+
+    async fn receiver() -> NotificationReceiver {
+        NotificationReceiver::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_community("public")
+            .with_user(user())
+            .unwrap()
+    }
+
+    async fn originator(
+        receiver: &NotificationReceiver,
+        security: NotificationSecurity,
+    ) -> NotificationOriginator {
+        NotificationOriginator::bind("127.0.0.1:0", receiver.local_addr().unwrap(), security)
+            .await
+            .unwrap()
+            .with_timeout(Duration::from_millis(200))
+    }
+
+    #[tokio::test]
+    async fn v2c_trap() {
+        let mut receiver = receiver().await;
+        let mut originator =
+            originator(&receiver, NotificationSecurity::V2c("public".into())).await;
+
+        originator.trap(link_down()).await.unwrap();
+        let notification = receiver.recv().await.unwrap();
+        assert_eq!(notification.kind, NotificationKind::Trap);
+        assert_eq!(notification.trap_oid, oid(&LINK_DOWN));
+        assert_eq!(notification.varbinds[0].name, oid(&IF_INDEX));
+        assert_eq!(
+            notification.varbinds[0].value,
+            VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::Integer(2.into())))
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_trap() {
+        let mut receiver = receiver().await;
+        let mut originator = originator(&receiver, NotificationSecurity::V1("public".into())).await;
+
+        originator
+            .trap(NotificationBuilder::new(oid(&ENTERPRISE_TRAP)))
+            .await
+            .unwrap();
+        let notification = receiver.recv().await.unwrap();
+        assert_eq!(notification.version, SnmpVersion::V1);
+        assert_eq!(notification.trap_oid, oid(&ENTERPRISE_TRAP));
+
+        originator.trap(link_down()).await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap().trap_oid, oid(&LINK_DOWN));
+
+        let inform = originator.inform(link_down()).await;
+        assert!(matches!(inform, Err(crate::Error::InformV1)));
+    }
+
+    #[tokio::test]
+    async fn v3_trap() {
+        let mut receiver = receiver().await;
+        let mut originator = originator(&receiver, NotificationSecurity::V3(user())).await;
+
+        originator.trap(link_down()).await.unwrap();
+        let notification = receiver.recv().await.unwrap();
+        assert_eq!(notification.version, SnmpVersion::V3);
+        assert_eq!(
+            &notification.context_engine_id,
+            originator.engine().engine_id()
+        );
+        assert_eq!(notification.trap_oid, oid(&LINK_DOWN));
+    }
+
+    #[tokio::test]
+    async fn v2c_inform() {
+        let mut receiver = receiver().await;
+        let mut originator =
+            originator(&receiver, NotificationSecurity::V2c("public".into())).await;
+
+        let (received, sent) = tokio::join!(receiver.recv(), originator.inform(link_down()));
+        sent.unwrap();
+        assert_eq!(received.unwrap().kind, NotificationKind::Inform);
+    }
+
+    #[tokio::test]
+    async fn v3_inform() {
+        let mut receiver = receiver().await;
+        let mut originator = originator(&receiver, NotificationSecurity::V3(user()))
+            .await
+            .with_target_engine_id(receiver.engine().engine_id().clone())
+            .unwrap();
+
+        let receive = async {
+            // the first attempt isn't in the receiver's time window
+            let first = receiver.recv().await;
+            (first, receiver.recv().await)
+        };
+        let ((first, second), sent) = tokio::join!(receive, originator.inform(link_down()));
+        sent.unwrap();
+        assert!(matches!(first, Err(crate::Error::NotInTimeWindow)));
+        assert_eq!(second.unwrap().kind, NotificationKind::Inform);
+    }
+
+    #[tokio::test]
+    async fn inform_times_out() {
+        let receiver = receiver().await;
+        let mut originator = originator(&receiver, NotificationSecurity::V2c("public".into()))
+            .await
+            .with_retries(1);
+
+        let inform = originator.inform(link_down()).await;
+        assert!(matches!(inform, Err(crate::Error::Timeout)));
+    }
+}
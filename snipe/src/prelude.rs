@@ -9,8 +9,14 @@ use rasn_smi::{
 
 pub use crate::rfc1212::*;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FixedLengthOctetString<const N: usize>(pub [u8; N]);
 
+/// An index component marked IMPLIED, whose length isn't encoded in the instance OID. As it consumes the rest of the
+/// OID, it can only be the last component of an index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Implied<T>(pub T);
+
 /// Encapsulates an OID conversion result. That is, a type containing the given T and the number of identifiers
 /// consumed when reading that T from an OID.
 pub struct OidConversionResult<T> {
@@ -59,6 +65,93 @@ macro_rules! try_into_snmp_converter {
     }
 }
 
+/// Defines an enum for an enumerated INTEGER syntax, such as `INTEGER { up(1), down(2) }`, along with conversions to
+/// and from `i32` and an SNMP conversion for [`DefaultConverter`]. Values which aren't named by the MIB are kept in an
+/// `Unknown` variant, so that undocumented values returned by agents don't fail decoding.
+#[macro_export]
+macro_rules! snmp_enum {
+    ($(#[$outer:meta])* $visibility:vis enum $name:ident { $($(#[$inner:meta])* $variant:ident = $value:literal),+ $(,)? }) => {
+        $(
+            #[$outer]
+        )*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        $visibility enum $name {
+            $(
+                $(
+                    #[$inner]
+                )*
+                $variant,
+            )+
+            /// A value which isn't named by the MIB.
+            Unknown(i32),
+        }
+
+        impl From<i32> for $name {
+            fn from(value: i32) -> Self {
+                match value {
+                    $($value => Self::$variant,)+
+                    x => Self::Unknown(x),
+                }
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $value,)+
+                    $name::Unknown(x) => x,
+                }
+            }
+        }
+
+        impl $crate::prelude::SnmpConverter<$name> for $crate::prelude::DefaultConverter {
+            fn try_from_snmp(syntax: $crate::smi::v2::ObjectSyntax) -> Result<$name, $crate::Error> {
+                <Self as $crate::prelude::SnmpConverter<i32>>::try_from_snmp(syntax).map(Into::into)
+            }
+
+            fn try_to_snmp(value: $name) -> Result<$crate::smi::v2::ObjectSyntax, $crate::Error> {
+                <Self as $crate::prelude::SnmpConverter<i32>>::try_to_snmp(value.into())
+            }
+        }
+    }
+}
+
+/// Defines a newtype for a textual convention, such as `DisplayString ::= TEXTUAL-CONVENTION ... SYNTAX OCTET STRING`,
+/// wrapping the Rust type of its underlying syntax. It has conversions to and from the wrapped type and an SNMP
+/// conversion for [`DefaultConverter`], so values of different textual conventions can't be mixed up.
+#[macro_export]
+macro_rules! snmp_newtype {
+    ($(#[$outer:meta])* $visibility:vis struct $name:ident($inner:ty);) => {
+        $(
+            #[$outer]
+        )*
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        $visibility struct $name(pub $inner);
+
+        impl From<$inner> for $name {
+            fn from(value: $inner) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<$name> for $inner {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl $crate::prelude::SnmpConverter<$name> for $crate::prelude::DefaultConverter {
+            fn try_from_snmp(syntax: $crate::smi::v2::ObjectSyntax) -> Result<$name, $crate::Error> {
+                <Self as $crate::prelude::SnmpConverter<$inner>>::try_from_snmp(syntax).map($name)
+            }
+
+            fn try_to_snmp(value: $name) -> Result<$crate::smi::v2::ObjectSyntax, $crate::Error> {
+                <Self as $crate::prelude::SnmpConverter<$inner>>::try_to_snmp(value.0)
+            }
+        }
+    }
+}
+
 impl<const N: usize> TryFrom<ObjectSyntax> for FixedLengthOctetString<N> {
     type Error = crate::Error;
 
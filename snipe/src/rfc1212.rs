@@ -104,14 +104,14 @@ impl OidConverter<String> for DefaultConverter {
         if identifier.is_empty() {
             Err(crate::Error::InsufficientLength(1))
         } else {
-            let end_idx = (identifier[0] + 1) as usize;
+            let end_idx = identifier[0] as usize + 1;
             if identifier.len() < end_idx {
                 Err(crate::Error::InsufficientLength(end_idx))
             } else {
-                let mut ret = Vec::with_capacity(end_idx - 1);
-                for (i, v) in ret.iter_mut().enumerate() {
-                    *v = identifier[i + 1].try_into()?;
-                }
+                let ret = identifier[1..end_idx]
+                    .iter()
+                    .map(|x| u8::try_from(*x))
+                    .collect::<Result<Vec<_>, _>>()?;
 
                 Ok(OidConversionResult {
                     num_consumed: end_idx,
@@ -123,11 +123,8 @@ impl OidConverter<String> for DefaultConverter {
 
     fn try_to_oid(value: String) -> Result<ObjectIdentifier, crate::Error> {
         Ok(ObjectIdentifier::new_unchecked(
-            value
-                .as_bytes()
-                .iter()
-                .copied()
-                .map(|x| x as u32)
+            std::iter::once(value.len().try_into()?)
+                .chain(value.as_bytes().iter().copied().map(|x| x as u32))
                 .collect::<Vec<_>>()
                 .into(),
         ))
@@ -139,14 +136,14 @@ impl OidConverter<OctetString> for DefaultConverter {
         if identifier.is_empty() {
             Err(crate::Error::InsufficientLength(1))
         } else {
-            let end_idx = (identifier[0] + 1) as usize;
+            let end_idx = identifier[0] as usize + 1;
             if identifier.len() < end_idx {
                 Err(crate::Error::InsufficientLength(end_idx))
             } else {
-                let mut ret = Vec::with_capacity(end_idx - 1);
-                for (i, v) in ret.iter_mut().enumerate() {
-                    *v = identifier[i + 1].try_into()?;
-                }
+                let ret = identifier[1..end_idx]
+                    .iter()
+                    .map(|x| u8::try_from(*x))
+                    .collect::<Result<Vec<_>, _>>()?;
 
                 Ok(OidConversionResult {
                     num_consumed: end_idx,
@@ -158,10 +155,8 @@ impl OidConverter<OctetString> for DefaultConverter {
 
     fn try_to_oid(value: OctetString) -> Result<ObjectIdentifier, crate::Error> {
         Ok(ObjectIdentifier::new_unchecked(
-            value
-                .iter()
-                .copied()
-                .map(|x| x as u32)
+            std::iter::once(value.len().try_into()?)
+                .chain(value.iter().copied().map(|x| x as u32))
                 .collect::<Vec<_>>()
                 .into(),
         ))
@@ -175,7 +170,7 @@ impl OidConverter<ObjectIdentifier> for DefaultConverter {
         if identifier.is_empty() {
             Err(crate::Error::InsufficientLength(1))
         } else {
-            let end_idx = (identifier[0] + 1) as usize;
+            let end_idx = identifier[0] as usize + 1;
             if identifier.len() < end_idx {
                 Err(crate::Error::InsufficientLength(end_idx))
             } else {
@@ -199,6 +194,80 @@ impl OidConverter<ObjectIdentifier> for DefaultConverter {
     }
 }
 
+impl OidConverter<Implied<String>> for DefaultConverter {
+    fn try_from_oid(
+        identifier: &[u32],
+    ) -> Result<OidConversionResult<Implied<String>>, crate::Error> {
+        let ret = identifier
+            .iter()
+            .map(|x| u8::try_from(*x))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(OidConversionResult {
+            num_consumed: identifier.len(),
+            converted: Implied(String::from_utf8(ret)?),
+        })
+    }
+
+    fn try_to_oid(value: Implied<String>) -> Result<ObjectIdentifier, crate::Error> {
+        Ok(ObjectIdentifier::new_unchecked(
+            value
+                .0
+                .as_bytes()
+                .iter()
+                .copied()
+                .map(|x| x as u32)
+                .collect::<Vec<_>>()
+                .into(),
+        ))
+    }
+}
+
+impl OidConverter<Implied<OctetString>> for DefaultConverter {
+    fn try_from_oid(
+        identifier: &[u32],
+    ) -> Result<OidConversionResult<Implied<OctetString>>, crate::Error> {
+        let ret = identifier
+            .iter()
+            .map(|x| u8::try_from(*x))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(OidConversionResult {
+            num_consumed: identifier.len(),
+            converted: Implied(OctetString::copy_from_slice(&ret[..])),
+        })
+    }
+
+    fn try_to_oid(value: Implied<OctetString>) -> Result<ObjectIdentifier, crate::Error> {
+        Ok(ObjectIdentifier::new_unchecked(
+            value
+                .0
+                .iter()
+                .copied()
+                .map(|x| x as u32)
+                .collect::<Vec<_>>()
+                .into(),
+        ))
+    }
+}
+
+impl OidConverter<Implied<ObjectIdentifier>> for DefaultConverter {
+    fn try_from_oid(
+        identifier: &[u32],
+    ) -> Result<OidConversionResult<Implied<ObjectIdentifier>>, crate::Error> {
+        Ok(OidConversionResult {
+            num_consumed: identifier.len(),
+            converted: Implied(ObjectIdentifier::new_unchecked(
+                identifier.to_owned().into(),
+            )),
+        })
+    }
+
+    fn try_to_oid(value: Implied<ObjectIdentifier>) -> Result<ObjectIdentifier, crate::Error> {
+        Ok(value.0)
+    }
+}
+
 impl OidConverter<NetworkAddress> for DefaultConverter {
     fn try_from_oid(
         identifier: &[u32],
@@ -265,3 +334,58 @@ impl OidConverter<IpAddress> for DefaultConverter {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_prefixed_strings() {
+        // RFC 1212 section 4.1.6: variable-length strings are prefixed with their length, so that they can be followed
+        // by further index components
+        let oid =
+            <DefaultConverter as OidConverter<OctetString>>::try_to_oid(OctetString::from(vec![
+                0x61, 0x62,
+            ]))
+            .unwrap();
+        assert_eq!(&oid[..], &[2, 0x61, 0x62]);
+        let oid =
+            <DefaultConverter as OidConverter<String>>::try_to_oid("abc".to_string()).unwrap();
+        assert_eq!(&oid[..], &[3, 0x61, 0x62, 0x63]);
+
+        let index = <DefaultConverter as OidConverter<(OctetString, String)>>::try_from_oid(&[
+            2, 0x61, 0x62, 1, 0x63,
+        ])
+        .unwrap();
+        assert_eq!(index.num_consumed, 5);
+        assert_eq!(
+            index.converted,
+            (OctetString::from(vec![0x61, 0x62]), "c".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_strings() {
+        assert!(matches!(
+            <DefaultConverter as OidConverter<String>>::try_from_oid(&[3, 0x61]),
+            Err(crate::Error::InsufficientLength(4))
+        ));
+        assert!(<DefaultConverter as OidConverter<OctetString>>::try_from_oid(&[1, 256]).is_err());
+    }
+
+    #[test]
+    fn implied() {
+        let index = <DefaultConverter as OidConverter<(u32, Implied<String>)>>::try_from_oid(&[
+            7, 0x61, 0x62, 0x63,
+        ])
+        .unwrap();
+        assert_eq!(index.num_consumed, 4);
+        assert_eq!(index.converted, (7, Implied("abc".to_string())));
+
+        let oid = <DefaultConverter as OidConverter<Implied<ObjectIdentifier>>>::try_to_oid(
+            Implied(ObjectIdentifier::new_unchecked(vec![1, 3, 6].into())),
+        )
+        .unwrap();
+        assert_eq!(&oid[..], &[1, 3, 6]);
+    }
+}
@@ -0,0 +1,61 @@
+//! The enumerated textual conventions from RFC 2579 (SNMPv2-TC), which code generated from MIBs uses for objects with
+//! these syntaxes.
+
+crate::snmp_enum! {
+    /// A boolean value.
+    pub enum TruthValue {
+        True = 1,
+        False = 2,
+    }
+}
+
+crate::snmp_enum! {
+    /// The status of a conceptual row, used to create and delete rows (see RFC 2579 for the state machine).
+    pub enum RowStatus {
+        Active = 1,
+        NotInService = 2,
+        NotReady = 3,
+        CreateAndGo = 4,
+        CreateAndWait = 5,
+        Destroy = 6,
+    }
+}
+
+crate::snmp_enum! {
+    /// The type of memory a conceptual row is stored in.
+    pub enum StorageType {
+        Other = 1,
+        Volatile = 2,
+        NonVolatile = 3,
+        Permanent = 4,
+        ReadOnly = 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rasn_smi::v2::{ObjectSyntax, SimpleSyntax};
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn truth_value() {
+        assert_eq!(TruthValue::from(1), TruthValue::True);
+        assert_eq!(TruthValue::from(7), TruthValue::Unknown(7));
+        assert_eq!(i32::from(TruthValue::False), 2);
+        assert_eq!(i32::from(TruthValue::Unknown(7)), 7);
+
+        let syntax =
+            <DefaultConverter as SnmpConverter<RowStatus>>::try_to_snmp(RowStatus::Destroy)
+                .unwrap();
+        assert_eq!(
+            syntax,
+            ObjectSyntax::Simple(SimpleSyntax::Integer(6.into()))
+        );
+        assert_eq!(
+            <DefaultConverter as SnmpConverter<RowStatus>>::try_from_snmp(syntax).unwrap(),
+            RowStatus::Destroy
+        );
+    }
+}
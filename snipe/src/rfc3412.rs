@@ -1,4 +1,5 @@
 use rasn::types::OctetString;
+use rasn_snmp::{v2::Pdus, v3};
 
 // msgFlags   OCTET STRING (SIZE(1)),
 //            --  .... ...1   authFlag
@@ -41,3 +42,167 @@ impl TryFrom<MessageFlags> for OctetString {
         }
     }
 }
+
+impl TryFrom<&OctetString> for MessageFlags {
+    type Error = crate::Error;
+
+    fn try_from(value: &OctetString) -> Result<Self, Self::Error> {
+        if value.len() != 1 {
+            Err(crate::Error::MalformedMessage)
+        } else {
+            let flags = MessageFlags {
+                reportable: value[0] & 0b0000_0100 != 0,
+                privacy: value[0] & 0b0000_0010 != 0,
+                auth: value[0] & 0b0000_0001 != 0,
+            };
+            if flags.privacy && !flags.auth {
+                Err(crate::Error::PrivNoAuth)
+            } else {
+                Ok(flags)
+            }
+        }
+    }
+}
+
+/// The SNMP message versions, as they appear in the version field of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnmpVersion {
+    V1,
+    V2c,
+    V3,
+}
+
+impl SnmpVersion {
+    pub fn number(&self) -> u32 {
+        match self {
+            SnmpVersion::V1 => 0,
+            SnmpVersion::V2c => 1,
+            SnmpVersion::V3 => 3,
+        }
+    }
+}
+
+// Reads a BER identifier and length, returning the contents and whatever follows them.
+pub(crate) fn read_tlv(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual_tag, data) = data.split_first()?;
+    if actual_tag != tag {
+        return None;
+    }
+
+    let (&first, data) = data.split_first()?;
+    let (len, data) = if first & 0x80 == 0 {
+        (first as usize, data)
+    } else {
+        let num_octets = (first & 0x7f) as usize;
+        if num_octets == 0 || num_octets > std::mem::size_of::<u32>() || data.len() < num_octets {
+            return None;
+        }
+        let (len_octets, data) = data.split_at(num_octets);
+        let len = len_octets
+            .iter()
+            .fold(0_usize, |acc, x| (acc << 8) | *x as usize);
+        (len, data)
+    };
+
+    if data.len() < len {
+        None
+    } else {
+        Some(data.split_at(len))
+    }
+}
+
+/// Determines the version of an incoming message (RFC 3412 section 7.2.2) without decoding the rest of it, so it can
+/// be dispatched to the right message processing model.
+pub fn message_version(data: &[u8]) -> Result<SnmpVersion, crate::Error> {
+    // Message ::= SEQUENCE { version INTEGER, ... }
+    const SEQUENCE: u8 = 0x30;
+    const INTEGER: u8 = 0x02;
+
+    let (message, _) = read_tlv(data, SEQUENCE).ok_or(crate::Error::MalformedMessage)?;
+    let (version, _) = read_tlv(message, INTEGER).ok_or(crate::Error::MalformedMessage)?;
+    match version {
+        [0] => Ok(SnmpVersion::V1),
+        [1] => Ok(SnmpVersion::V2c),
+        [3] => Ok(SnmpVersion::V3),
+        _ => Err(crate::Error::UnsupportedVersion(
+            version.iter().fold(0_u64, |acc, x| (acc << 8) | *x as u64),
+        )),
+    }
+}
+
+/// Checks that an SNMPv3 message uses the User-based Security Model, the only security model supported (RFC 3412
+/// section 7.2 step 4).
+pub fn check_security_model(msg: &v3::Message) -> Result<(), crate::Error> {
+    // SnmpSecurityModel, from RFC 3411
+    const USM: i32 = 3;
+
+    match i32::try_from(msg.global_data.security_model.clone()) {
+        Ok(USM) => Ok(()),
+        _ => Err(crate::Error::UnsupportedSecurityModel),
+    }
+}
+
+/// Returns the request-id of any PDU.
+pub fn request_id(pdus: &Pdus) -> i32 {
+    match pdus {
+        Pdus::GetRequest(x) => x.0.request_id,
+        Pdus::GetNextRequest(x) => x.0.request_id,
+        Pdus::Response(x) => x.0.request_id,
+        Pdus::SetRequest(x) => x.0.request_id,
+        Pdus::GetBulkRequest(x) => x.0.request_id,
+        Pdus::InformRequest(x) => x.0.request_id,
+        Pdus::Trap(x) => x.0.request_id,
+        Pdus::Report(x) => x.0.request_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_flags_round_trip() {
+        for bits in [0b000_u8, 0b001, 0b011, 0b100, 0b101, 0b111] {
+            let flags = MessageFlags::try_from(&OctetString::from(vec![bits])).unwrap();
+            assert_eq!(
+                OctetString::try_from(flags).unwrap(),
+                OctetString::from(vec![bits])
+            );
+        }
+        assert!(matches!(
+            MessageFlags::try_from(&OctetString::from(vec![0b010])),
+            Err(crate::Error::PrivNoAuth)
+        ));
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(
+            message_version(&[0x30, 0x03, 0x02, 0x01, 0x00]).unwrap(),
+            SnmpVersion::V1
+        );
+        assert_eq!(
+            message_version(&[0x30, 0x03, 0x02, 0x01, 0x01]).unwrap(),
+            SnmpVersion::V2c
+        );
+        assert_eq!(
+            message_version(&[0x30, 0x81, 0x03, 0x02, 0x01, 0x03]).unwrap(),
+            SnmpVersion::V3
+        );
+        assert!(matches!(
+            message_version(&[0x30, 0x03, 0x02, 0x01, 0x02]),
+            Err(crate::Error::UnsupportedVersion(2))
+        ));
+        for truncated in [
+            &[][..],
+            &[0x30],
+            &[0x30, 0x05, 0x02, 0x01],
+            &[0x30, 0x84, 0xff],
+        ] {
+            assert!(matches!(
+                message_version(truncated),
+                Err(crate::Error::MalformedMessage)
+            ));
+        }
+    }
+}
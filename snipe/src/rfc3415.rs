@@ -0,0 +1,430 @@
+//! An implementation of the View-based Access Control Model (VACM) described in RFC 3415, used by the agent to decide
+//! which principals may read, write or be notified of which OIDs.
+
+use std::collections::{HashMap, HashSet};
+
+use rasn::types::{ObjectIdentifier, OctetString};
+
+use crate::rfc3412::MessageFlags;
+
+/// The security models a principal can be authenticated with (RFC 3411 section 5, SnmpSecurityModel).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SecurityModel {
+    /// Matches every security model. Only valid in access entries.
+    Any = 0,
+    V1 = 1,
+    V2c = 2,
+    Usm = 3,
+}
+
+/// The level of security a message was sent with (RFC 3411 section 5, SnmpSecurityLevel).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SecurityLevel {
+    NoAuthNoPriv = 1,
+    AuthNoPriv = 2,
+    AuthPriv = 3,
+}
+
+impl From<MessageFlags> for SecurityLevel {
+    fn from(value: MessageFlags) -> Self {
+        match (value.auth, value.privacy) {
+            (true, true) => SecurityLevel::AuthPriv,
+            (true, false) => SecurityLevel::AuthNoPriv,
+            _ => SecurityLevel::NoAuthNoPriv,
+        }
+    }
+}
+
+/// The kinds of access a view can be selected for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewType {
+    Read,
+    Write,
+    Notify,
+}
+
+/// How an access entry's context name is matched against a request's context name (vacmAccessContextMatch).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContextMatch {
+    Exact,
+    Prefix,
+}
+
+/// The reasons access can be denied, as returned by isAccessAllowed (RFC 3415 section 3.2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessDenied {
+    /// The OID isn't in the selected view.
+    NotInView,
+    /// No view is configured for the requested kind of access.
+    NoSuchView,
+    NoSuchContext,
+    /// The principal isn't mapped to a group.
+    NoGroupName,
+    /// No access entry matches the principal's group, security model and level and the context.
+    NoAccessEntry,
+}
+
+/// A row of the vacmAccessTable, giving a group its views in matching contexts. An empty view name means no view is
+/// configured for that kind of access.
+#[derive(Clone, Debug)]
+pub struct AccessEntry {
+    pub group_name: OctetString,
+    pub context_prefix: OctetString,
+    pub context_match: ContextMatch,
+    pub security_model: SecurityModel,
+    /// The minimum level of security requests must be sent with.
+    pub security_level: SecurityLevel,
+    pub read_view: OctetString,
+    pub write_view: OctetString,
+    pub notify_view: OctetString,
+}
+
+impl AccessEntry {
+    fn matches(
+        &self,
+        group_name: &[u8],
+        context_name: &[u8],
+        security_model: SecurityModel,
+        security_level: SecurityLevel,
+    ) -> bool {
+        let context_matches = match self.context_match {
+            ContextMatch::Exact => &self.context_prefix[..] == context_name,
+            ContextMatch::Prefix => context_name.starts_with(&self.context_prefix),
+        };
+        &self.group_name[..] == group_name
+            && context_matches
+            && (self.security_model == SecurityModel::Any || self.security_model == security_model)
+            && self.security_level <= security_level
+    }
+
+    // The preference order for matching entries given in RFC 3415 section 4, vacmAccessTable.
+    fn preference(&self) -> (bool, bool, usize, SecurityLevel) {
+        (
+            self.security_model != SecurityModel::Any,
+            self.context_match == ContextMatch::Exact,
+            self.context_prefix.len(),
+            self.security_level,
+        )
+    }
+
+    fn view_name(&self, view_type: ViewType) -> &OctetString {
+        match view_type {
+            ViewType::Read => &self.read_view,
+            ViewType::Write => &self.write_view,
+            ViewType::Notify => &self.notify_view,
+        }
+    }
+}
+
+/// A row of the vacmViewTreeFamilyTable: a subtree included in or excluded from a view.
+#[derive(Clone, Debug)]
+struct ViewFamily {
+    subtree: Vec<u32>,
+    mask: Vec<u8>,
+    included: bool,
+}
+
+impl ViewFamily {
+    // Each bit of the mask says whether the corresponding sub-identifier of the subtree must match exactly, starting
+    // with the most significant bit of the first octet. Missing bits are taken to be 1.
+    fn contains(&self, oid: &[u32]) -> bool {
+        oid.len() >= self.subtree.len()
+            && self.subtree.iter().zip(oid).enumerate().all(|(i, (x, y))| {
+                let wildcard = self
+                    .mask
+                    .get(i / 8)
+                    .is_some_and(|octet| octet & (0x80 >> (i % 8)) == 0);
+                wildcard || x == y
+            })
+    }
+}
+
+/// A MIB view selected for a request: the set of OIDs it may access.
+#[derive(Clone, Copy, Debug)]
+pub struct View<'a> {
+    families: &'a [ViewFamily],
+}
+
+impl View<'_> {
+    /// Whether the given OID is in the view. When several families contain the OID, the one with the longest subtree
+    /// decides, with ties going to the lexicographically greater subtree (RFC 3415 section 5).
+    pub fn contains(&self, oid: &[u32]) -> bool {
+        self.families
+            .iter()
+            .filter(|x| x.contains(oid))
+            .max_by(|a, b| {
+                a.subtree
+                    .len()
+                    .cmp(&b.subtree.len())
+                    .then_with(|| a.subtree.cmp(&b.subtree))
+            })
+            .is_some_and(|x| x.included)
+    }
+}
+
+/// The local configuration datastore of the VACM: groups, access entries and views.
+///
+/// Community-based principals are identified by their community, which is used as their security name.
+#[derive(Clone, Debug)]
+pub struct Vacm {
+    contexts: HashSet<OctetString>,
+    groups: HashMap<(SecurityModel, OctetString), OctetString>,
+    access: Vec<AccessEntry>,
+    views: HashMap<OctetString, Vec<ViewFamily>>,
+}
+
+impl Default for Vacm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vacm {
+    /// An empty configuration, which knows only the default (empty) context and denies all access.
+    pub fn new() -> Self {
+        Self {
+            contexts: HashSet::from([OctetString::default()]),
+            groups: HashMap::new(),
+            access: vec![],
+            views: HashMap::new(),
+        }
+    }
+
+    /// Adds a context requests can be made in (vacmContextTable).
+    pub fn with_context(mut self, context_name: impl Into<OctetString>) -> Self {
+        self.contexts.insert(context_name.into());
+        self
+    }
+
+    /// Maps a principal to a group (vacmSecurityToGroupTable).
+    pub fn with_group(
+        mut self,
+        security_model: SecurityModel,
+        security_name: impl Into<OctetString>,
+        group_name: impl Into<OctetString>,
+    ) -> Self {
+        self.groups
+            .insert((security_model, security_name.into()), group_name.into());
+        self
+    }
+
+    /// Adds an entry to the vacmAccessTable.
+    pub fn with_access(mut self, entry: AccessEntry) -> Self {
+        self.access.push(entry);
+        self
+    }
+
+    /// Includes (or excludes) a subtree in the named view (vacmViewTreeFamilyTable). The mask selects which
+    /// sub-identifiers of the subtree must match; an empty mask matches the whole subtree exactly.
+    pub fn with_view(
+        mut self,
+        view_name: impl Into<OctetString>,
+        subtree: impl Into<ObjectIdentifier>,
+        mask: impl Into<Vec<u8>>,
+        included: bool,
+    ) -> Self {
+        self.views
+            .entry(view_name.into())
+            .or_default()
+            .push(ViewFamily {
+                subtree: subtree.into().to_vec(),
+                mask: mask.into(),
+                included,
+            });
+        self
+    }
+
+    /// Selects the view a principal may use for the given kind of access in the given context, following steps 1 to 4
+    /// of isAccessAllowed (RFC 3415 section 3.2).
+    pub fn view(
+        &self,
+        security_model: SecurityModel,
+        security_name: &[u8],
+        security_level: SecurityLevel,
+        view_type: ViewType,
+        context_name: &[u8],
+    ) -> Result<View<'_>, AccessDenied> {
+        if !self.contexts.contains(context_name) {
+            return Err(AccessDenied::NoSuchContext);
+        }
+
+        let group_name = self
+            .groups
+            .get(&(security_model, OctetString::copy_from_slice(security_name)))
+            .ok_or(AccessDenied::NoGroupName)?;
+        let entry = self
+            .access
+            .iter()
+            .filter(|x| x.matches(group_name, context_name, security_model, security_level))
+            .max_by_key(|x| x.preference())
+            .ok_or(AccessDenied::NoAccessEntry)?;
+
+        let view_name = entry.view_name(view_type);
+        match self.views.get(view_name) {
+            Some(families) if !view_name.is_empty() => Ok(View { families }),
+            _ => Err(AccessDenied::NoSuchView),
+        }
+    }
+
+    /// Checks whether a principal may access the given OID (isAccessAllowed, RFC 3415 section 3.2).
+    pub fn is_access_allowed(
+        &self,
+        security_model: SecurityModel,
+        security_name: &[u8],
+        security_level: SecurityLevel,
+        view_type: ViewType,
+        context_name: &[u8],
+        oid: &[u32],
+    ) -> Result<(), AccessDenied> {
+        let view = self.view(
+            security_model,
+            security_name,
+            security_level,
+            view_type,
+            context_name,
+        )?;
+        if view.contains(oid) {
+            Ok(())
+        } else {
+            Err(AccessDenied::NotInView)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERNET: [u32; 4] = [1, 3, 6, 1];
+    const IF_ENTRY: [u32; 9] = [1, 3, 6, 1, 2, 1, 2, 2, 1];
+
+    fn oid(x: &[u32]) -> ObjectIdentifier {
+        ObjectIdentifier::new_unchecked(x.to_vec().into())
+    }
+
+    fn vacm() -> Vacm {
+        let entry =
+            |group_name: &str, security_model, security_level, write_view: &str| AccessEntry {
+                group_name: group_name.into(),
+                context_prefix: OctetString::default(),
+                context_match: ContextMatch::Exact,
+                security_model,
+                security_level,
+                read_view: "all".into(),
+                write_view: write_view.into(),
+                notify_view: OctetString::default(),
+            };
+        Vacm::new()
+            .with_group(SecurityModel::V2c, "public", "readers")
+            .with_group(SecurityModel::Usm, "admin", "admins")
+            .with_access(entry(
+                "readers",
+                SecurityModel::Any,
+                SecurityLevel::NoAuthNoPriv,
+                "",
+            ))
+            .with_access(entry(
+                "admins",
+                SecurityModel::Usm,
+                SecurityLevel::AuthPriv,
+                "row7",
+            ))
+            .with_view("all", oid(&INTERNET), vec![], true)
+            .with_view("all", oid(&[1, 3, 6, 1, 6, 3]), vec![], false)
+            // every column of the ifEntry with index 7: 1.3.6.1.2.1.2.2.1.*.7
+            .with_view(
+                "row7",
+                oid(&[&IF_ENTRY[..], &[0, 7]].concat()),
+                vec![0xff, 0xa0],
+                true,
+            )
+    }
+
+    #[test]
+    fn read_view() {
+        let vacm = vacm();
+        let allowed = |oid: &[u32]| {
+            vacm.is_access_allowed(
+                SecurityModel::V2c,
+                b"public",
+                SecurityLevel::NoAuthNoPriv,
+                ViewType::Read,
+                b"",
+                oid,
+            )
+        };
+        assert_eq!(allowed(&[1, 3, 6, 1, 2, 1, 1, 1, 0]), Ok(()));
+        // the longest matching family wins
+        assert_eq!(
+            allowed(&[1, 3, 6, 1, 6, 3, 15, 1, 1, 1, 0]),
+            Err(AccessDenied::NotInView)
+        );
+        assert_eq!(allowed(&[1, 3, 6, 2]), Err(AccessDenied::NotInView));
+    }
+
+    #[test]
+    fn write_view() {
+        let vacm = vacm();
+        let allowed = |security_name: &[u8], security_level, oid: &[u32]| {
+            vacm.is_access_allowed(
+                SecurityModel::Usm,
+                security_name,
+                security_level,
+                ViewType::Write,
+                b"",
+                oid,
+            )
+        };
+        let if_descr = [&IF_ENTRY[..], &[2, 7]].concat();
+        assert_eq!(
+            allowed(b"admin", SecurityLevel::AuthPriv, &if_descr),
+            Ok(())
+        );
+        assert_eq!(
+            allowed(
+                b"admin",
+                SecurityLevel::AuthPriv,
+                &[&IF_ENTRY[..], &[3, 7]].concat()
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            allowed(
+                b"admin",
+                SecurityLevel::AuthPriv,
+                &[&IF_ENTRY[..], &[2, 8]].concat()
+            ),
+            Err(AccessDenied::NotInView)
+        );
+        assert_eq!(
+            allowed(b"admin", SecurityLevel::AuthNoPriv, &if_descr),
+            Err(AccessDenied::NoAccessEntry)
+        );
+        assert_eq!(
+            allowed(b"public", SecurityLevel::AuthPriv, &if_descr),
+            Err(AccessDenied::NoGroupName)
+        );
+        assert_eq!(
+            vacm.is_access_allowed(
+                SecurityModel::V2c,
+                b"public",
+                SecurityLevel::NoAuthNoPriv,
+                ViewType::Write,
+                b"",
+                &if_descr
+            ),
+            Err(AccessDenied::NoSuchView)
+        );
+        assert_eq!(
+            vacm.is_access_allowed(
+                SecurityModel::Usm,
+                b"admin",
+                SecurityLevel::AuthPriv,
+                ViewType::Write,
+                b"other",
+                &if_descr
+            ),
+            Err(AccessDenied::NoSuchContext)
+        );
+    }
+}
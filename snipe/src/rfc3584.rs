@@ -0,0 +1,342 @@
+//! An implementation of the parts of RFC 3584 (coexistence between SNMP versions) concerning notifications: the
+//! translation of SNMPv1 Trap-PDUs to SNMPv2 notifications (section 3.1) and back (section 3.2).
+
+use std::net::Ipv4Addr;
+
+use rasn::types::{ConstOid, ObjectIdentifier, OctetString};
+use rasn_smi::{
+    v1,
+    v2::{ApplicationSyntax, ObjectSyntax, SimpleSyntax},
+};
+use rasn_snmp::{
+    v1 as snmp_v1,
+    v2::{VarBind, VarBindList, VarBindValue},
+};
+
+use crate::{
+    notification::{SNMP_TRAPS, SNMP_TRAP_OID, SYS_UP_TIME},
+    oid,
+};
+
+/// snmpTrapAddress.0, from SNMP-COMMUNITY-MIB. Holds the agent-addr of a translated SNMPv1 trap.
+pub const SNMP_TRAP_ADDRESS: ConstOid = oid!("1.3.6.1.6.3.18.1.3.0");
+
+/// snmpTrapCommunity.0, from SNMP-COMMUNITY-MIB. Holds the community of a translated SNMPv1 trap.
+pub const SNMP_TRAP_COMMUNITY: ConstOid = oid!("1.3.6.1.6.3.18.1.4.0");
+
+/// snmpTrapEnterprise.0, from SNMPv2-MIB. Holds the enterprise of a translated SNMPv1 trap.
+pub const SNMP_TRAP_ENTERPRISE: ConstOid = oid!("1.3.6.1.6.3.1.1.4.3.0");
+
+// SNMPv1 generic-trap value for enterprise specific traps
+const ENTERPRISE_SPECIFIC: u32 = 6;
+
+/// Converts an SNMPv1 value to its SNMPv2 equivalent. SNMPv1 NULL values become unspecified values.
+pub fn v1_to_v2_value(value: v1::ObjectSyntax) -> VarBindValue {
+    let syntax = match value {
+        v1::ObjectSyntax::Simple(simple) => ObjectSyntax::Simple(match simple {
+            v1::SimpleSyntax::Number(x) => SimpleSyntax::Integer(x),
+            v1::SimpleSyntax::String(x) => SimpleSyntax::String(x),
+            v1::SimpleSyntax::Object(x) => SimpleSyntax::ObjectId(x),
+            v1::SimpleSyntax::Empty => return VarBindValue::Unspecified,
+        }),
+        v1::ObjectSyntax::ApplicationWide(app) => ObjectSyntax::ApplicationWide(match app {
+            v1::ApplicationSyntax::Address(v1::NetworkAddress::Internet(x)) => {
+                ApplicationSyntax::Address(x)
+            }
+            v1::ApplicationSyntax::Counter(x) => ApplicationSyntax::Counter(x),
+            v1::ApplicationSyntax::Gauge(x) => ApplicationSyntax::Unsigned(x),
+            v1::ApplicationSyntax::Ticks(x) => ApplicationSyntax::Ticks(x),
+            v1::ApplicationSyntax::Arbitrary(x) => ApplicationSyntax::Arbitrary(x),
+        }),
+    };
+    VarBindValue::Value(syntax)
+}
+
+/// Converts an SNMPv2 value to its SNMPv1 equivalent. Counter64 values and exceptions have no SNMPv1 equivalent, and
+/// unspecified values become NULL.
+pub fn v2_to_v1_value(value: VarBindValue) -> Result<v1::ObjectSyntax, crate::Error> {
+    let syntax = match value {
+        VarBindValue::Value(ObjectSyntax::Simple(simple)) => {
+            v1::ObjectSyntax::Simple(match simple {
+                SimpleSyntax::Integer(x) => v1::SimpleSyntax::Number(x),
+                SimpleSyntax::String(x) => v1::SimpleSyntax::String(x),
+                SimpleSyntax::ObjectId(x) => v1::SimpleSyntax::Object(x),
+            })
+        }
+        VarBindValue::Value(ObjectSyntax::ApplicationWide(app)) => {
+            v1::ObjectSyntax::ApplicationWide(match app {
+                ApplicationSyntax::Address(x) => {
+                    v1::ApplicationSyntax::Address(v1::NetworkAddress::Internet(x))
+                }
+                ApplicationSyntax::Counter(x) => v1::ApplicationSyntax::Counter(x),
+                ApplicationSyntax::Unsigned(x) => v1::ApplicationSyntax::Gauge(x),
+                ApplicationSyntax::Ticks(x) => v1::ApplicationSyntax::Ticks(x),
+                ApplicationSyntax::Arbitrary(x) => v1::ApplicationSyntax::Arbitrary(x),
+                ApplicationSyntax::BigCounter(_) => return Err(crate::Error::InvalidVariant),
+            })
+        }
+        VarBindValue::Unspecified => v1::ObjectSyntax::Simple(v1::SimpleSyntax::Empty),
+        _ => return Err(crate::Error::InvalidVariant),
+    };
+    Ok(syntax)
+}
+
+fn varbind(name: ConstOid, value: ObjectSyntax) -> VarBind {
+    VarBind {
+        name: name.into(),
+        value: VarBindValue::Value(value),
+    }
+}
+
+fn find<'a>(varbinds: &'a [VarBind], name: &ConstOid) -> Option<&'a VarBindValue> {
+    varbinds
+        .iter()
+        .find(|x| x.name[..] == name.0[..])
+        .map(|x| &x.value)
+}
+
+/// Translates an SNMPv1 Trap-PDU received with the given community to the variable bindings of the equivalent SNMPv2
+/// notification, as described in RFC 3584 section 3.1:
+///
+/// - sysUpTime.0 takes the value of the time-stamp.
+/// - snmpTrapOID.0 is the generic trap under snmpTraps for generic traps, and the enterprise followed by 0 and the
+///   specific-trap for enterprise specific traps.
+/// - snmpTrapAddress.0, snmpTrapCommunity.0 and snmpTrapEnterprise.0 are appended with the agent-addr, community and
+///   enterprise, unless the trap already contains them.
+pub fn trap_to_v2(
+    trap: snmp_v1::Trap,
+    community: &OctetString,
+) -> Result<VarBindList, crate::Error> {
+    let generic_trap: u32 = trap.generic_trap.try_into()?;
+    let trap_oid = match generic_trap {
+        ENTERPRISE_SPECIFIC => {
+            let specific_trap: u32 = trap.specific_trap.try_into()?;
+            trap.enterprise
+                .iter()
+                .copied()
+                .chain([0, specific_trap])
+                .collect::<Vec<_>>()
+        }
+        0..=5 => SNMP_TRAPS
+            .0
+            .iter()
+            .copied()
+            .chain([generic_trap + 1])
+            .collect::<Vec<_>>(),
+        _ => return Err(crate::Error::MalformedNotification),
+    };
+    let trap_oid = ObjectIdentifier::new(trap_oid).ok_or(crate::Error::MalformedNotification)?;
+    let v1::NetworkAddress::Internet(agent_addr) = trap.agent_addr;
+
+    let mut varbinds = vec![
+        varbind(
+            SYS_UP_TIME,
+            ApplicationSyntax::Ticks(trap.time_stamp).into(),
+        ),
+        varbind(SNMP_TRAP_OID, SimpleSyntax::ObjectId(trap_oid).into()),
+    ];
+    varbinds.extend(trap.variable_bindings.into_iter().map(|x| VarBind {
+        name: x.name,
+        value: v1_to_v2_value(x.value),
+    }));
+    let appended = [
+        (
+            SNMP_TRAP_ADDRESS,
+            ApplicationSyntax::Address(agent_addr).into(),
+        ),
+        (
+            SNMP_TRAP_COMMUNITY,
+            SimpleSyntax::String(community.clone()).into(),
+        ),
+        (
+            SNMP_TRAP_ENTERPRISE,
+            SimpleSyntax::ObjectId(trap.enterprise).into(),
+        ),
+    ];
+    for (name, value) in appended {
+        if find(&varbinds, &name).is_none() {
+            varbinds.push(varbind(name, value));
+        }
+    }
+    Ok(varbinds)
+}
+
+/// Translates the variable bindings of an SNMPv2 notification, starting with sysUpTime.0 and snmpTrapOID.0, to an
+/// SNMPv1 Trap-PDU as described in RFC 3584 section 3.2:
+///
+/// - Generic traps map to their generic-trap, with the enterprise taken from snmpTrapEnterprise.0 if present.
+/// - Other notifications are enterprise specific. The specific-trap is the last sub-identifier of snmpTrapOID.0, and
+///   the enterprise is the rest of it, without the next-to-last sub-identifier if that is 0.
+/// - The agent-addr is taken from snmpTrapAddress.0, or is `agent_addr` if it isn't present.
+/// - snmpTrapAddress.0, snmpTrapCommunity.0 and snmpTrapEnterprise.0 are dropped, as they are represented by the
+///   Trap-PDU itself, so that translating a trap to SNMPv2 and back gives the same variable bindings.
+/// - Variable bindings with Counter64 values are dropped, as SNMPv1 can't represent them.
+pub fn v2_to_trap(
+    varbinds: VarBindList,
+    agent_addr: Ipv4Addr,
+) -> Result<snmp_v1::Trap, crate::Error> {
+    let mut varbinds = varbinds.into_iter();
+    let time_stamp = match varbinds.next() {
+        Some(VarBind {
+            name,
+            value:
+                VarBindValue::Value(ObjectSyntax::ApplicationWide(ApplicationSyntax::Ticks(ticks))),
+        }) if name[..] == SYS_UP_TIME.0[..] => ticks,
+        _ => return Err(crate::Error::MalformedNotification),
+    };
+    let trap_oid = match varbinds.next() {
+        Some(VarBind {
+            name,
+            value: VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::ObjectId(oid))),
+        }) if name[..] == SNMP_TRAP_OID.0[..] => oid,
+        _ => return Err(crate::Error::MalformedNotification),
+    };
+    let varbinds = varbinds.collect::<Vec<_>>();
+
+    let (enterprise, generic_trap, specific_trap) = match trap_oid[..] {
+        [ref prefix @ .., x]
+            if prefix == SNMP_TRAPS.0 && (1..=ENTERPRISE_SPECIFIC).contains(&x) =>
+        {
+            let enterprise = match find(&varbinds, &SNMP_TRAP_ENTERPRISE) {
+                Some(VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::ObjectId(oid)))) => {
+                    oid.to_vec()
+                }
+                _ => SNMP_TRAPS.0.to_vec(),
+            };
+            (enterprise, x - 1, 0)
+        }
+        [ref prefix @ .., 0, x] | [ref prefix @ .., x] => (prefix.to_vec(), ENTERPRISE_SPECIFIC, x),
+        [] => return Err(crate::Error::MalformedNotification),
+    };
+    let agent_addr = match find(&varbinds, &SNMP_TRAP_ADDRESS) {
+        Some(VarBindValue::Value(ObjectSyntax::ApplicationWide(ApplicationSyntax::Address(x)))) => {
+            x.clone()
+        }
+        _ => v1::IpAddress(agent_addr.octets().to_vec().into()),
+    };
+
+    Ok(snmp_v1::Trap {
+        enterprise: ObjectIdentifier::new(enterprise).ok_or(crate::Error::MalformedNotification)?,
+        agent_addr: v1::NetworkAddress::Internet(agent_addr),
+        generic_trap: generic_trap.into(),
+        specific_trap: specific_trap.into(),
+        time_stamp,
+        variable_bindings: varbinds
+            .into_iter()
+            .filter(|x| {
+                ![SNMP_TRAP_ADDRESS, SNMP_TRAP_COMMUNITY, SNMP_TRAP_ENTERPRISE]
+                    .iter()
+                    .any(|name| x.name[..] == name.0[..])
+            })
+            .filter(|x| {
+                !matches!(
+                    x.value,
+                    VarBindValue::Value(ObjectSyntax::ApplicationWide(
+                        ApplicationSyntax::BigCounter(_)
+                    ))
+                )
+            })
+            .map(|x| {
+                Ok(snmp_v1::VarBind {
+                    name: x.name,
+                    value: v2_to_v1_value(x.value)?,
+                })
+            })
+            .collect::<Result<_, crate::Error>>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rasn_smi::v2::{Counter64, TimeTicks};
+
+    const IF_INDEX: [u32; 11] = [1, 3, 6, 1, 2, 1, 2, 2, 1, 1, 2];
+    const NET_SNMP: [u32; 7] = [1, 3, 6, 1, 4, 1, 8072];
+
+    fn oid(x: &[u32]) -> ObjectIdentifier {
+        ObjectIdentifier::new_unchecked(x.to_vec().into())
+    }
+
+    fn trap(generic_trap: u32, specific_trap: u32) -> snmp_v1::Trap {
+        snmp_v1::Trap {
+            enterprise: oid(&NET_SNMP),
+            agent_addr: v1::NetworkAddress::Internet(v1::IpAddress([192, 0, 2, 1][..].into())),
+            generic_trap: generic_trap.into(),
+            specific_trap: specific_trap.into(),
+            time_stamp: TimeTicks(1234),
+            variable_bindings: vec![snmp_v1::VarBind {
+                name: oid(&IF_INDEX),
+                value: v1::ObjectSyntax::Simple(v1::SimpleSyntax::Number(2.into())),
+            }],
+        }
+    }
+
+    fn names(varbinds: &[VarBind]) -> Vec<ObjectIdentifier> {
+        varbinds.iter().map(|x| x.name.clone()).collect()
+    }
+
+    #[test]
+    fn generic_trap_to_v2() {
+        let varbinds = trap_to_v2(trap(2, 0), &"public".into()).unwrap();
+        assert_eq!(
+            names(&varbinds),
+            vec![
+                SYS_UP_TIME.into(),
+                SNMP_TRAP_OID.into(),
+                oid(&IF_INDEX),
+                SNMP_TRAP_ADDRESS.into(),
+                SNMP_TRAP_COMMUNITY.into(),
+                SNMP_TRAP_ENTERPRISE.into(),
+            ]
+        );
+        assert_eq!(
+            varbinds[1].value,
+            VarBindValue::Value(
+                SimpleSyntax::ObjectId(oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 3])).into()
+            )
+        );
+        assert_eq!(
+            varbinds[3].value,
+            VarBindValue::Value(
+                ApplicationSyntax::Address(v1::IpAddress([192, 0, 2, 1][..].into())).into()
+            )
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for (generic_trap, specific_trap) in [(0, 0), (5, 0), (6, 0), (6, 42)] {
+            let varbinds = trap_to_v2(trap(generic_trap, specific_trap), &"public".into()).unwrap();
+            let translated = v2_to_trap(varbinds, Ipv4Addr::UNSPECIFIED).unwrap();
+            assert_eq!(translated, trap(generic_trap, specific_trap));
+        }
+    }
+
+    #[test]
+    fn enterprise_without_zero() {
+        let varbinds = vec![
+            varbind(SYS_UP_TIME, ApplicationSyntax::Ticks(TimeTicks(1)).into()),
+            varbind(
+                SNMP_TRAP_OID,
+                SimpleSyntax::ObjectId(oid(&[1, 3, 6, 1, 4, 1, 8072, 9])).into(),
+            ),
+            varbind(
+                ConstOid(&IF_INDEX),
+                ApplicationSyntax::BigCounter(Counter64(1)).into(),
+            ),
+        ];
+
+        let trap = v2_to_trap(varbinds, Ipv4Addr::new(192, 0, 2, 7)).unwrap();
+        assert_eq!(trap.enterprise, oid(&NET_SNMP));
+        assert_eq!(trap.generic_trap, 6.into());
+        assert_eq!(trap.specific_trap, 9.into());
+        assert_eq!(
+            trap.agent_addr,
+            v1::NetworkAddress::Internet(v1::IpAddress([192, 0, 2, 7][..].into()))
+        );
+        // Counter64 values can't be sent in SNMPv1
+        assert!(trap.variable_bindings.is_empty());
+    }
+}